@@ -0,0 +1,276 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use hound::WavSpec;
+
+use crate::{
+    decoder::{self, Decoder},
+    fmt_impl::Fmt,
+    util::PlayError,
+    wav::{Header, WAVE_FORMAT_ADPCM, WAVE_FORMAT_IMA_ADPCM},
+};
+
+const BAD_BLOCK: io::Error = io::const_error!(io::ErrorKind::InvalidData, "invalid ADPCM block header");
+
+/// Number of standard predictor coefficient pairs every MS ADPCM file carries, before any custom ones.
+pub const MS_COEFS: usize = 7;
+
+const MS_ADAPTATION: [i32; 16] = [230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230];
+
+const IMA_INDEX: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+const IMA_STEP: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130, 143, 157, 173,
+    190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899, 15289, 16818,
+    18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Number of frames in an ADPCM block of `bytes` bytes, 0 if it cannot even hold the block header.
+pub const fn block_frames(tag: u16, channels: u16, bytes: u64) -> u64 {
+    let channels = channels as u64;
+    match tag {
+        // 每声道 7 字节的块头里已有两个采样，之后每字节两个采样
+        WAVE_FORMAT_ADPCM if channels != 0 && bytes >= 7 * channels => (bytes - 7 * channels) * 2 / channels + 2,
+        // 每声道 4 字节的块头里有一个采样，之后每声道 4 字节一组、每组 8 个采样
+        WAVE_FORMAT_IMA_ADPCM if channels != 0 && bytes >= 4 * channels => (bytes - 4 * channels) / (4 * channels) * 8 + 1,
+        _ => 0,
+    }
+}
+
+#[inline]
+fn clamp16(x: i32) -> i16 {
+    x.clamp(i32::from(i16::MIN), i32::from(i16::MAX)) as i16
+}
+
+/// Decodes one MS ADPCM block into interleaved samples.
+fn decode_ms(block: &[u8], channels: usize, coefs: &[[i16; 2]], out: &mut Vec<i16>) -> io::Result<()> {
+    let i16_at = |i: usize| i32::from(i16::from_le_bytes([block[i], block[i + 1]]));
+    let mut state = Vec::with_capacity(channels); // (系数, delta, 前一个采样, 前两个采样)
+    for (c, &predictor) in block[..channels].iter().enumerate() {
+        let coef = coefs.get(usize::from(predictor)).ok_or(BAD_BLOCK)?;
+        let delta = i16_at(channels + c * 2);
+        let s1 = i16_at(channels * 3 + c * 2);
+        let s2 = i16_at(channels * 5 + c * 2);
+        state.push((coef.map(i32::from), delta, s1, s2));
+    }
+    // 块头里的两个采样先输出较早的那个
+    out.extend(state.iter().map(|&(_, _, _, s2)| s2 as i16));
+    out.extend(state.iter().map(|&(_, _, s1, _)| s1 as i16));
+
+    let nibbles = block[channels * 7..].iter().flat_map(|&b| [b >> 4, b & 0x0f]);
+    for (i, n) in nibbles.enumerate() {
+        let (coef, delta, s1, s2) = &mut state[i % channels];
+        let predicted = (*s1 * coef[0] + *s2 * coef[1]) >> 8;
+        let signed = i32::from((n << 4).cast_signed() >> 4);
+        let sample = clamp16(predicted + signed * *delta);
+        *s2 = *s1;
+        *s1 = i32::from(sample);
+        *delta = ((MS_ADAPTATION[usize::from(n)] * *delta) >> 8).max(16);
+        out.push(sample);
+    }
+    Ok(())
+}
+
+/// Decodes one IMA ADPCM block into interleaved samples.
+fn decode_ima(block: &[u8], channels: usize, out: &mut Vec<i16>) -> io::Result<()> {
+    let mut state = Vec::with_capacity(channels); // (预测值, 步长索引)
+    for h in block[..channels * 4].chunks_exact(4) {
+        if h[2] >= IMA_STEP.len() as u8 {
+            return Err(BAD_BLOCK);
+        }
+        state.push((i32::from(i16::from_le_bytes([h[0], h[1]])), h[2]));
+    }
+    out.extend(state.iter().map(|&(p, _)| p as i16));
+
+    // 之后每声道轮流 4 字节 (8 个采样，低半字节在前)
+    let start = out.len();
+    let groups = (block.len() - channels * 4) / (channels * 4);
+    out.resize(start + groups * 8 * channels, 0);
+    for (g, group) in block[channels * 4..].chunks_exact(4).take(groups * channels).enumerate() {
+        let c = g % channels;
+        let (predictor, index) = &mut state[c];
+        let nibbles = group.iter().flat_map(|&b| [b & 0x0f, b >> 4]);
+        for (k, n) in nibbles.enumerate() {
+            let step = IMA_STEP[usize::from(*index)];
+            let mut diff = step >> 3;
+            if n & 1 != 0 { diff += step >> 2; }
+            if n & 2 != 0 { diff += step >> 1; }
+            if n & 4 != 0 { diff += step; }
+            *predictor = i32::from(clamp16(if n & 8 == 0 { *predictor + diff } else { *predictor - diff }));
+            *index = index.saturating_add_signed(IMA_INDEX[usize::from(n)]).min(IMA_STEP.len() as u8 - 1);
+            out[start + ((g / channels) * 8 + k) * channels + c] = *predictor as i16;
+        }
+    }
+    Ok(())
+}
+
+/// Reads MS or IMA ADPCM blocks of a WAV file, expanding them to 16 bits.
+pub struct AdpcmDecoder<R> {
+    reader: R,
+    header: Header,
+    spec: WavSpec,
+    block_frames: u64,
+    raw: Vec<u8>,
+    block: Vec<i16>, // 当前块解码出的交错采样
+    taken: usize, // 当前块中已读出的帧数
+    frames: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> AdpcmDecoder<R> {
+    pub fn new(mut reader: R, header: &Header) -> io::Result<Self> {
+        let spec = header.spec()?;
+        let block_frames = block_frames(header.tag, header.channels, u64::from(header.block_align));
+        reader.seek(SeekFrom::Start(header.begin))?;
+        Ok(Self { reader, header: header.clone(), spec, block_frames, raw: Vec::new(), block: Vec::new(), taken: 0, frames: header.frames(), pos: 0 })
+    }
+
+    /// Reads and decodes the next block, the last one may be short.
+    fn next_block(&mut self) -> io::Result<()> {
+        let block_align = u64::from(self.header.block_align);
+        let begin = self.pos / self.block_frames * block_align;
+        let len = block_align.min(self.header.size - begin);
+        self.raw.resize(len as usize, 0);
+        self.reader.read_exact(&mut self.raw)?;
+        self.block.clear();
+        let channels = usize::from(self.header.channels);
+        if block_frames(self.header.tag, self.header.channels, len) == 0 {
+            return Err(BAD_BLOCK);
+        }
+        match self.header.tag {
+            WAVE_FORMAT_ADPCM => decode_ms(&self.raw, channels, &self.header.coefs, &mut self.block)?,
+            _ => decode_ima(&self.raw, channels, &mut self.block)?,
+        }
+        self.taken = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Decoder for AdpcmDecoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn channel_mask(&self) -> u32 {
+        self.header.channel_mask
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let channels = usize::from(self.header.channels);
+        let buf = decoder::as_bytes(buf, self.spec)?;
+        let want = (buf.len() as u64 / 2 / channels as u64).min(self.frames - self.pos) as usize;
+        let mut done = 0;
+        while done < want {
+            if self.taken * channels >= self.block.len() {
+                self.next_block()?;
+            }
+            let n = (want - done).min(self.block.len() / channels - self.taken);
+            let from = self.taken * channels;
+            for (out, s) in buf[done * channels * 2..].chunks_exact_mut(2).zip(&self.block[from..from + n * channels]) {
+                out.copy_from_slice(&s.to_le_bytes());
+            }
+            self.taken += n;
+            self.pos += n as u64;
+            done += n;
+        }
+        Ok(done)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        // 从所在块的开头解码，丢掉块内之前的帧
+        let frame = frame.min(self.frames);
+        let block = frame / self.block_frames;
+        self.reader.seek(SeekFrom::Start(self.header.begin + block * u64::from(self.header.block_align)))?;
+        self.pos = block * self.block_frames;
+        self.block.clear();
+        self.taken = 0;
+        if frame > self.pos {
+            self.next_block()?;
+            self.taken = (frame - self.pos) as usize;
+            self.pos = frame;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const STANDARD_COEFS: [[i16; 2]; MS_COEFS] = [[256, 0], [512, -256], [0, 0], [192, 64], [240, 0], [460, -208], [392, -232]];
+
+    /// Mono IMA block: predictor 0, step index 0, then nibbles 7, F, 0, 8 and four zeros.
+    const IMA_BLOCK: [u8; 8] = [0, 0, 0, 0, 0xf7, 0x80, 0, 0];
+    const IMA_SAMPLES: [i16; 9] = [0, 11, -19, -15, -18, -15, -12, -10, -8];
+
+    #[test]
+    fn ms_block() {
+        // 系数 (512, -256)，delta 16，块头采样 100 与 50
+        let block = [1, 16, 0, 100, 0, 50, 0, 0x1f, 0x70];
+        let mut out = Vec::new();
+        decode_ms(&block, 1, &STANDARD_COEFS, &mut out).unwrap();
+        assert_eq!(out, [50, 100, 166, 216, 378, 540]);
+        assert_eq!(block_frames(WAVE_FORMAT_ADPCM, 1, block.len() as u64), 6);
+
+        let block = [7, 16, 0, 100, 0, 50, 0, 0x1f, 0x70];
+        assert!(decode_ms(&block, 1, &STANDARD_COEFS, &mut out).is_err());
+
+        // fmt 里多给的系数也可以选
+        let custom = [STANDARD_COEFS.as_slice(), &[[512, -256]]].concat();
+        out.clear();
+        decode_ms(&block, 1, &custom, &mut out).unwrap();
+        assert_eq!(out, [50, 100, 166, 216, 378, 540]);
+    }
+
+    #[test]
+    fn ima_block() {
+        let mut out = Vec::new();
+        decode_ima(&IMA_BLOCK, 1, &mut out).unwrap();
+        assert_eq!(out, IMA_SAMPLES);
+
+        // 双声道每 4 字节轮换一次；右声道停在最小步长，到顶的左声道被钳位
+        let block = [0xf8, 0x7f, 88, 0, 100, 0, 0, 0, 0x77, 0x77, 0x77, 0x77, 0, 0, 0, 0];
+        out.clear();
+        decode_ima(&block, 2, &mut out).unwrap();
+        assert_eq!(out.len(), 2 * 9);
+        assert!(out.chunks_exact(2).skip(1).all(|f| f == [i16::MAX, 100]));
+
+        assert!(decode_ima(&[0, 0, 89, 0], 1, &mut out).is_err());
+    }
+
+    #[test]
+    fn short_last_block() {
+        // 每块 12 字节 17 帧，最后一块只有 8 字节 9 帧
+        let mut block = IMA_BLOCK.to_vec();
+        block.extend([0; 4]);
+        let data = [block.as_slice(), &block, &IMA_BLOCK].concat();
+        let header = Header {
+            tag: WAVE_FORMAT_IMA_ADPCM,
+            channels: 1,
+            rate: 8000,
+            block_align: 12,
+            bits: 4,
+            size: data.len() as u64,
+            ..Header::default()
+        };
+        assert_eq!(header.frames(), 17 + 17 + 9);
+        // fact 去掉了末尾的填充；不够块头的残块不算
+        assert_eq!(Header { samples: 40, ..header.clone() }.frames(), 40);
+        assert_eq!(Header { size: 24 + 3, ..header.clone() }.frames(), 17 + 17);
+
+        let mut decoder = AdpcmDecoder::new(Cursor::new(data), &header).unwrap();
+        decoder.seek(17 + 17 + 3).unwrap();
+        let mut buf = [0i16; 16];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 6);
+        assert_eq!(buf[..6], IMA_SAMPLES[3..]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use hound::{SampleFormat, WavSpec};
+
+use crate::pcm::{Layout, PcmDecoder};
+
+const NOT_AIFF: io::Error = io::const_error!(io::ErrorKind::InvalidData, "not an AIFF or AIFF-C file");
+const BAD_COMM: io::Error = io::const_error!(io::ErrorKind::InvalidData, "invalid AIFF COMM chunk");
+const NO_SOUND: io::Error = io::const_error!(io::ErrorKind::InvalidData, "AIFF file without COMM or SSND chunk");
+const UNSUPPORTED_COMPRESSION: io::Error = io::const_error!(io::ErrorKind::Unsupported, "unsupported AIFF-C compression type");
+
+/// The COMM chunk.
+#[derive(Clone, Copy, Debug)]
+struct Common {
+    channels: u16,
+    frames: u32,
+    bits: u16,
+    rate: u32,
+    compression: [u8; 4], // AIFF 没有这一项，按 NONE 处理
+}
+
+impl Common {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let b = data.get(..18).ok_or(BAD_COMM)?;
+        Ok(Self {
+            channels: u16::from_be_bytes([b[0], b[1]]),
+            frames: u32::from_be_bytes([b[2], b[3], b[4], b[5]]),
+            bits: u16::from_be_bytes([b[6], b[7]]),
+            rate: extended(b[8..18].try_into().map_err(|_| BAD_COMM)?).ok_or(BAD_COMM)?,
+            compression: data.get(18..22).map_or(*b"NONE", |c| c.try_into().unwrap_or(*b"NONE")),
+        })
+    }
+
+    /// The spec the samples are converted to, and how they are stored.
+    fn spec(self) -> io::Result<(WavSpec, Layout)> {
+        let int = |big_endian| {
+            // AIFF 的采样左对齐，按整个容器宽度播放即可
+            let bytes = self.bits.div_ceil(8);
+            (bytes, bytes * 8, SampleFormat::Int, Layout { big_endian, signed8: true })
+        };
+        let (bytes, bits, sample_format, layout) = match &self.compression {
+            b"NONE" | b"twos" | b"in24" | b"in32" => int(true),
+            b"sowt" | b"23ni" | b"42ni" => int(false),
+            b"raw " if self.bits <= 8 => (1, 8, SampleFormat::Int, Layout::default()), // 无符号 8 位，与 WAV 相同
+            b"fl32" | b"FL32" => (4, 32, SampleFormat::Float, Layout { big_endian: true, signed8: false }),
+            b"fl64" | b"FL64" => (8, 64, SampleFormat::Float, Layout { big_endian: true, signed8: false }),
+            _ => return Err(UNSUPPORTED_COMPRESSION),
+        };
+        if self.channels == 0 || bytes == 0 || bytes > 4 && sample_format == SampleFormat::Int {
+            return Err(BAD_COMM);
+        }
+        let spec = WavSpec { channels: self.channels, sample_rate: self.rate, bits_per_sample: bits, bytes_per_sample: bytes, sample_format };
+        Ok((spec, layout))
+    }
+}
+
+/// Converts an 80-bit IEEE 754 extended precision number (the sample rate) to an integer.
+fn extended(b: [u8; 10]) -> Option<u32> {
+    let exp = i32::from(u16::from_be_bytes([b[0], b[1]]));
+    let mantissa = u64::from_be_bytes(b[2..].try_into().ok()?);
+    // 符号位为 0，值为 mantissa * 2^(exp - 16383 - 63)
+    let shift = u32::try_from(16383 + 63 - exp).ok().filter(|&s| s < 64)?;
+    u32::try_from(mantissa >> shift).ok().filter(|&r| r != 0)
+}
+
+/// Walks the chunks, returns the spec, the layout, where the samples begin and the number of frames.
+fn parse<R: Read + Seek>(reader: &mut BufReader<R>) -> io::Result<(WavSpec, Layout, u64, u64)> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut head = [0; 12];
+    reader.read_exact(&mut head)?;
+    if &head[..4] != b"FORM" || !matches!(&head[8..], b"AIFF" | b"AIFC") {
+        return Err(NOT_AIFF);
+    }
+
+    let mut pos = 12;
+    let (mut common, mut sound) = (None, None);
+    while common.is_none() || sound.is_none() {
+        let mut chunk = [0; 8];
+        if pos + 8 > len {
+            break;
+        }
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut chunk)?;
+        let size = u64::from(u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]));
+        match &chunk[..4] {
+            b"COMM" => {
+                let mut data = vec![0; size.min(64) as usize];
+                reader.read_exact(&mut data)?;
+                common = Some(Common::parse(&data)?);
+            }
+            b"SSND" => {
+                let mut offset = [0; 8];
+                reader.read_exact(&mut offset)?;
+                // 偏移之后才是采样，块对齐不用管
+                let begin = pos + 16 + u64::from(u32::from_be_bytes([offset[0], offset[1], offset[2], offset[3]]));
+                let end = (pos + 8 + size).min(len); // 截断的文件放到实际结尾
+                sound = Some((begin, end.saturating_sub(begin)));
+            }
+            _ => (),
+        }
+        pos += 8 + size + (size & 1); // 奇数长度的块后面有一个填充字节
+    }
+
+    let (Some(common), Some((begin, size))) = (common, sound) else { return Err(NO_SOUND) };
+    let (spec, layout) = common.spec()?;
+    let block_align = u64::from(spec.channels) * u64::from(spec.bytes_per_sample);
+    Ok((spec, layout, begin, u64::from(common.frames).min(size / block_align)))
+}
+
+/// Reads the spec and the number of frames from the COMM and SSND chunks.
+pub fn probe<R: Read + Seek>(reader: R) -> io::Result<(WavSpec, u64)> {
+    let (spec, _, _, frames) = parse(&mut BufReader::new(reader))?;
+    Ok((spec, frames))
+}
+
+/// Opens the sound data of an AIFF or AIFF-C file.
+pub fn open<R: Read + Seek>(reader: R) -> io::Result<PcmDecoder<BufReader<R>>> {
+    let mut reader = BufReader::new(reader);
+    let (spec, layout, begin, frames) = parse(&mut reader)?;
+    tracing::info!("AIFF sound data at {begin}: {frames} frames, spec={spec:?}, {layout:?}");
+    PcmDecoder::new(reader, spec, layout, begin, frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn ext(exp: u16, mantissa: u64) -> [u8; 10] {
+        let mut b = [0; 10];
+        b[..2].copy_from_slice(&exp.to_be_bytes());
+        b[2..].copy_from_slice(&mantissa.to_be_bytes());
+        b
+    }
+
+    #[test]
+    fn extended_rates() {
+        assert_eq!(extended(ext(0x400e, 0xac44 << 48)), Some(44100));
+        assert_eq!(extended(ext(0x400e, 0xbb80 << 48)), Some(48000));
+        assert_eq!(extended(ext(0x400b, 0xfa00 << 48)), Some(8000));
+        // 小数部分舍去
+        assert_eq!(extended(ext(0x400c, 0xac44_8000 << 32)), Some(11025));
+        assert_eq!(extended(ext(0, 0)), None);
+        assert_eq!(extended(ext(0xc00e, 0xac44 << 48)), None); // 负数
+        assert_eq!(extended(ext(0x401f, 1 << 63)), None); // 超出 u32
+    }
+
+    #[test]
+    fn probe_aifc() {
+        // 奇数长度的块带填充字节；文件被截断，SSND 比块头和 COMM 说的都短
+        let mut file = b"FORM\0\0\0\0AIFC".to_vec();
+        file.extend(b"ANNO\0\0\0\x03abc\0");
+        file.extend(b"COMM\0\0\0\x18");
+        file.extend(2u16.to_be_bytes());
+        file.extend(100u32.to_be_bytes());
+        file.extend(16u16.to_be_bytes());
+        file.extend(ext(0x400e, 0xac44 << 48));
+        file.extend(b"sowt\0\0");
+        file.extend(b"SSND\0\0\x10\0\0\0\0\x04\0\0\0\0");
+        file.extend([0; 4 + 5 * 4]);
+
+        let (spec, layout, begin, frames) = parse(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        assert_eq!((spec.channels, spec.sample_rate, spec.bits_per_sample), (2, 44100, 16));
+        assert!(!layout.big_endian);
+        assert_eq!(begin, file.len() as u64 - 5 * 4);
+        assert_eq!(frames, 5);
+    }
+}
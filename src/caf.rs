@@ -0,0 +1,157 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use hound::{SampleFormat, WavSpec};
+
+use crate::pcm::{Layout, PcmDecoder};
+
+const NOT_CAF: io::Error = io::const_error!(io::ErrorKind::InvalidData, "not a CAF file");
+const BAD_DESC: io::Error = io::const_error!(io::ErrorKind::InvalidData, "invalid CAF audio description");
+const NO_DATA: io::Error = io::const_error!(io::ErrorKind::InvalidData, "CAF file without desc or data chunk");
+const UNSUPPORTED_CODEC: io::Error = io::const_error!(io::ErrorKind::Unsupported, "only linear PCM CAF files are supported");
+
+/// The `desc` chunk.
+#[derive(Clone, Copy, Debug)]
+struct Description {
+    rate: f64,
+    format: [u8; 4],
+    flags: u32, // 1 浮点，2 小端
+    bytes_per_packet: u32,
+    frames_per_packet: u32,
+    channels: u32,
+    bits: u32,
+}
+
+impl Description {
+    fn parse(b: &[u8; 32]) -> Self {
+        let u32_at = |i: usize| u32::from_be_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        Self {
+            rate: f64::from_bits(u64::from(u32_at(0)) << 32 | u64::from(u32_at(4))),
+            format: [b[8], b[9], b[10], b[11]],
+            flags: u32_at(12),
+            bytes_per_packet: u32_at(16),
+            frames_per_packet: u32_at(20),
+            channels: u32_at(24),
+            bits: u32_at(28),
+        }
+    }
+
+    /// The spec the samples are converted to, and how they are stored.
+    fn spec(self) -> io::Result<(WavSpec, Layout)> {
+        if &self.format != b"lpcm" {
+            return Err(UNSUPPORTED_CODEC);
+        }
+        let channels = u16::try_from(self.channels).ok().filter(|&c| c != 0).ok_or(BAD_DESC)?;
+        let bytes = u16::try_from(self.bytes_per_packet / self.channels).map_err(|_| BAD_DESC)?;
+        let sample_rate = u32::try_from(self.rate.round() as i64).ok().filter(|&r| r != 0).ok_or(BAD_DESC)?;
+        let float = self.flags & 1 != 0;
+        let layout = Layout { big_endian: self.flags & 2 == 0, signed8: !float };
+        let sample_format = if float { SampleFormat::Float } else { SampleFormat::Int };
+        if self.frames_per_packet != 1 || bytes == 0 || u32::from(bytes) * self.channels != self.bytes_per_packet || self.bits > u32::from(bytes) * 8 {
+            return Err(BAD_DESC);
+        }
+        // 整数采样按整个容器宽度播放
+        let spec = WavSpec { channels, sample_rate, bits_per_sample: bytes * 8, bytes_per_sample: bytes, sample_format };
+        Ok((spec, layout))
+    }
+}
+
+/// Walks the chunks, returns the spec, the layout, where the samples begin and the number of frames.
+fn parse<R: Read + Seek>(reader: &mut BufReader<R>) -> io::Result<(WavSpec, Layout, u64, u64)> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut head = [0; 8];
+    reader.read_exact(&mut head)?;
+    if &head[..4] != b"caff" || head[4..6] != [0, 1] {
+        return Err(NOT_CAF);
+    }
+
+    let mut pos = 8;
+    let (mut desc, mut data) = (None, None);
+    while (desc.is_none() || data.is_none()) && pos + 12 <= len {
+        let mut chunk = [0; 12];
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut chunk)?;
+        let size = i64::from_be_bytes(chunk[4..].try_into().map_err(|_| NOT_CAF)?);
+        // -1 表示 data 块一直到文件末尾
+        let end = if size < 0 { len } else { (pos + 12).saturating_add(size.cast_unsigned()).min(len) };
+        match &chunk[..4] {
+            b"desc" => {
+                let mut b = [0; 32];
+                reader.read_exact(&mut b)?;
+                desc = Some(Description::parse(&b));
+            }
+            b"data" => data = Some((pos + 16, end.saturating_sub(pos + 16))), // 前 4 字节是编辑计数
+            _ => (),
+        }
+        pos = end;
+    }
+
+    let (Some(desc), Some((begin, size))) = (desc, data) else { return Err(NO_DATA) };
+    let (spec, layout) = desc.spec()?;
+    Ok((spec, layout, begin, size / u64::from(desc.bytes_per_packet)))
+}
+
+/// Reads the spec and the number of frames from the `desc` and `data` chunks.
+pub fn probe<R: Read + Seek>(reader: R) -> io::Result<(WavSpec, u64)> {
+    let (spec, _, _, frames) = parse(&mut BufReader::new(reader))?;
+    Ok((spec, frames))
+}
+
+/// Opens the audio data of a linear PCM CAF file.
+pub fn open<R: Read + Seek>(reader: R) -> io::Result<PcmDecoder<BufReader<R>>> {
+    let mut reader = BufReader::new(reader);
+    let (spec, layout, begin, frames) = parse(&mut reader)?;
+    tracing::info!("CAF audio data at {begin}: {frames} frames, spec={spec:?}, {layout:?}");
+    PcmDecoder::new(reader, spec, layout, begin, frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn desc(rate: f64, flags: u32, bytes_per_packet: u32, channels: u32, bits: u32) -> Vec<u8> {
+        let mut b = b"desc".to_vec();
+        b.extend(32i64.to_be_bytes());
+        b.extend(rate.to_bits().to_be_bytes());
+        b.extend(b"lpcm");
+        for x in [flags, bytes_per_packet, 1, channels, bits] {
+            b.extend(x.to_be_bytes());
+        }
+        b
+    }
+
+    #[test]
+    fn data_to_the_end() {
+        // data 块长度为 -1，一直到文件末尾，末尾的半帧不算
+        let mut file = b"caff\0\x01\0\0".to_vec();
+        file.extend(desc(44100.0, 0, 6, 2, 20));
+        file.extend(b"free");
+        file.extend(3i64.to_be_bytes());
+        file.extend([0; 3]);
+        file.extend(b"data");
+        file.extend((-1i64).to_be_bytes());
+        file.extend([0; 4 + 6 * 10 + 5]);
+
+        let (spec, layout, begin, frames) = parse(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        assert_eq!((spec.channels, spec.sample_rate, spec.bits_per_sample, spec.bytes_per_sample), (2, 44100, 24, 3));
+        assert_eq!(layout, Layout { big_endian: true, signed8: true });
+        assert_eq!(begin, file.len() as u64 - 6 * 10 - 5);
+        assert_eq!(frames, 10);
+    }
+
+    #[test]
+    fn descriptions() {
+        let parse = |b: &[u8]| Description::parse(b[12..].try_into().unwrap()).spec();
+        let (spec, layout) = parse(&desc(48000.0, 3, 8, 2, 32)).unwrap();
+        assert_eq!((spec.sample_format, spec.sample_rate), (SampleFormat::Float, 48000));
+        assert!(!layout.big_endian);
+        // 采样率四舍五入
+        assert_eq!(parse(&desc(22049.6, 0, 2, 1, 16)).unwrap().0.sample_rate, 22050);
+        assert!(parse(&desc(44100.0, 0, 4, 0, 16)).is_err());
+        assert!(parse(&desc(44100.0, 0, 5, 2, 16)).is_err());
+        assert!(parse(&desc(44100.0, 0, 2, 1, 17)).is_err());
+        assert!(parse(&desc(0.0, 0, 2, 1, 16)).is_err());
+    }
+}
@@ -0,0 +1,590 @@
+use core::{
+    any::Any,
+    cmp::Ordering,
+    f64::consts::{FRAC_1_SQRT_2, PI},
+};
+
+use alsa::pcm::Format;
+use hound::WavSpec;
+
+use crate::{
+    fmt_impl::{Fmt, S18_3, S20_3, S20_4, S24_3, S24_4},
+    resample::{Quality, Resampler},
+    sink::{AudioSink, DeviceSpec, SinkConfig},
+    util::{PlayError, UnsupportedFormatError},
+};
+
+/// Linear gain ramp over the output frames.
+#[derive(Clone, Copy)]
+struct Ramp {
+    gain: f64,
+    step: f64,
+    left: usize, // 剩余帧数，之后保持 gain 不变
+}
+
+/// Speaker positions of the channels when a file does not say, as `WAVE_FORMAT_EXTENSIBLE` assumes; 0 if unknown.
+const fn default_mask(channels: usize) -> u32 {
+    match channels {
+        1 => 0x4, // 中置
+        2 => 0x3,
+        3 => 0x7,
+        4 => 0x33, // 前后各两个
+        5 => 0x37,
+        6 => 0x3f, // 5.1
+        7 => 0x13f, // 6.1，加后中
+        8 => 0x63f, // 7.1，加两侧
+        _ => 0,
+    }
+}
+
+/// Gains into left and right of the speaker at bit `bit` of a channel mask, as in the ITU-R BS.775 downmix:
+/// the center and the surrounds at −3 dB, the LFE dropped.
+const fn fold(bit: u32) -> (f64, f64) {
+    match bit {
+        0 | 6 | 12 => (1.0, 0.0), // 左前、左中前、顶部左前
+        1 | 7 | 14 => (0.0, 1.0),
+        2 | 11 | 13 => (FRAC_1_SQRT_2, FRAC_1_SQRT_2), // 中置、顶部中央、顶部中前
+        4 | 9 | 15 => (FRAC_1_SQRT_2, 0.0), // 左后、左侧、顶部左后
+        5 | 10 | 17 => (0.0, FRAC_1_SQRT_2),
+        8 | 16 => (0.5, 0.5), // 后中、顶部后中
+        _ => (0.0, 0.0),
+    }
+}
+
+/// Gains of `i_n` input channels at the speakers of `mask` into each of `o_n` (fewer) output channels, row by row.
+fn downmix(mask: u32, i_n: usize, o_n: usize) -> Vec<f64> {
+    let mut matrix = vec![0.0; i_n * o_n];
+    // 掩码里的位置不够就按默认的，第 i 个声道是第 i 个置位的
+    let mask = if mask.count_ones() as usize >= i_n { mask } else { default_mask(i_n) };
+    let out = default_mask(o_n);
+    if mask == 0 || out == 0 {
+        // 不知道位置，按顺序轮流混进去
+        for i in 0..i_n {
+            matrix[i % o_n * i_n + i] = 1.0;
+        }
+    } else {
+        for (i, bit) in (0..u32::BITS).filter(|&b| mask & 1 << b != 0).take(i_n).enumerate() {
+            if out & 1 << bit != 0 {
+                // 输出也有这个位置
+                matrix[(out & ((1 << bit) - 1)).count_ones() as usize * i_n + i] = 1.0;
+                continue;
+            }
+            let (l, r) = fold(bit);
+            if o_n == 1 {
+                matrix[i] = f64::midpoint(l, r);
+            } else {
+                matrix[i] = l;
+                matrix[i_n + i] = r;
+            }
+        }
+    }
+    // 每路的增益加起来不超过 1，不会削波
+    for row in matrix.chunks_exact_mut(i_n) {
+        let sum = row.iter().sum::<f64>();
+        if sum > 1.0 {
+            for g in row {
+                *g /= sum;
+            }
+        }
+    }
+    matrix
+}
+
+/// Wraps a sink, converting sample format, channel count and rate to what it negotiated.
+///
+/// It is also where two songs are crossfaded: the outgoing song [`Convert::stash`]es
+/// its tail instead of writing it, and the incoming one is mixed with it.
+pub struct Convert<K: AudioSink> {
+    inner: K,
+    from: DeviceSpec,
+    to: DeviceSpec,
+    quality: Quality,
+    resampler: Option<Resampler>,
+    frame: Vec<f64>,
+    mapped: Vec<f64>, // 声道映射后、重采样前
+    downmix: Vec<f64>, // 下混时每个输出声道的各输入声道增益
+    queue: Vec<f64>, // 已转换、尚未写进设备的样本
+    q_b: usize,
+    out: Box<dyn Any + Send>, // 设备格式的样本 (Vec<D>)，每次写时复用
+    taken: usize, // queue 对应的输入帧数
+    tail: Vec<f64>, // 上一首歌淡出的部分 (设备格式)
+    t_b: usize,
+    t_len: usize, // 淡入淡出的总帧数
+    ramp: Option<Ramp>,
+}
+
+impl<K: AudioSink> Convert<K> {
+    #[inline]
+    fn passthrough(&self) -> bool {
+        // 队列里还有上次没写完的，要先写完，不然会重复
+        self.from == self.to && !self.fading() && self.ramp.is_none() && self.q_b == self.queue.len()
+    }
+
+    fn ramp_frames(&self, ms: u32) -> usize {
+        (u64::from(ms) * u64::from(self.to.rate) / 1000).max(1) as usize
+    }
+
+    /// Fades in from silence over the next `ms` milliseconds.
+    pub fn ramp_in(&mut self, ms: u32) {
+        let left = self.ramp_frames(ms);
+        self.ramp = Some(Ramp { gain: 0.0, step: 1.0 / f64::from(left as u32), left });
+    }
+
+    /// Fades out over the next `ms` milliseconds, then stays silent until [`AudioSink::drop`].
+    pub fn ramp_out(&mut self, ms: u32) {
+        let left = self.ramp_frames(ms);
+        let gain = self.ramp.map_or(1.0, |r| r.gain);
+        self.ramp = Some(Ramp { gain, step: -gain / f64::from(left as u32), left });
+    }
+
+    /// Whether a [`Convert::ramp_out`] has been fully written.
+    #[inline]
+    pub fn ramped_out(&self) -> bool {
+        self.ramp.is_some_and(|r| r.left == 0 && r.gain <= 0.0) && self.q_b == self.queue.len()
+    }
+
+    /// Whether a stashed tail is waiting to be mixed.
+    #[inline]
+    pub const fn fading(&self) -> bool {
+        !self.tail.is_empty()
+    }
+
+    /// Converts `buf` and keeps it to be faded out under the next song, returns the frames taken.
+    pub fn stash<S: Fmt>(&mut self, buf: &[S]) -> usize {
+        let mut tail = core::mem::take(&mut self.tail);
+        self.convert(buf, &mut tail);
+        self.tail = tail;
+        self.t_len = self.tail.len() / usize::from(self.to.channels);
+        buf.len() / usize::from(self.from.channels)
+    }
+
+    /// Continues on this sink with a source of another spec, its channels at the speakers of `mask` (0 if unspecified).
+    pub fn retarget(&mut self, from: DeviceSpec, mask: u32) {
+        let (i_n, o_n) = (usize::from(from.channels), usize::from(self.to.channels));
+        self.downmix = if i_n > o_n { downmix(mask, i_n, o_n) } else { Vec::new() };
+        if from == self.from {
+            return;
+        }
+        if from.rate != self.from.rate {
+            // 旧的滤波器里剩下的也属于要淡出的部分
+            if let Some(r) = &mut self.resampler {
+                r.finish(&mut self.tail);
+                self.t_len = self.tail.len() / usize::from(self.to.channels);
+            }
+            self.resampler = (from.rate != self.to.rate).then(|| Resampler::new(from.rate, self.to.rate, self.to.channels.into(), self.quality));
+        }
+        tracing::info!("Converting {from:?} to {:?}", self.to);
+        self.from = from;
+    }
+
+    /// Maps one normalized input frame onto `self.frame`.
+    fn map_channels<S: Fmt>(&mut self, frame: &[S]) {
+        let (i_n, o_n) = (frame.len(), self.frame.len());
+        match i_n.cmp(&o_n) {
+            Ordering::Equal => {
+                for (o, &s) in self.frame.iter_mut().zip(frame) {
+                    *o = s.to_norm();
+                }
+            }
+            Ordering::Greater => { // 下混：按扬声器位置
+                for (x, gains) in self.frame.iter_mut().zip(self.downmix.chunks_exact(i_n)) {
+                    *x = gains.iter().zip(frame).map(|(&g, &s)| g * s.to_norm()).sum();
+                }
+            }
+            Ordering::Less => { // 上混：循环复制
+                for (o, x) in self.frame.iter_mut().enumerate() {
+                    *x = frame[o % i_n].to_norm();
+                }
+            }
+        }
+    }
+
+    /// Appends `buf` converted to the device spec (still normalized) to `out`.
+    fn convert<S: Fmt>(&mut self, buf: &[S], out: &mut Vec<f64>) {
+        self.mapped.clear();
+        for frame in buf.chunks_exact(usize::from(self.from.channels)) {
+            self.map_channels(frame);
+            self.mapped.extend_from_slice(&self.frame);
+        }
+        match &mut self.resampler {
+            Some(r) => r.process(&self.mapped, out),
+            None => out.extend_from_slice(&self.mapped),
+        }
+    }
+
+    fn enqueue<S: Fmt>(&mut self, buf: &[S]) {
+        let mut queue = core::mem::take(&mut self.queue);
+        queue.clear();
+        self.convert(buf, &mut queue);
+        self.queue = queue;
+        self.q_b = 0;
+        self.mix();
+        self.apply_ramp();
+        self.taken = buf.len() / usize::from(self.from.channels);
+    }
+
+    fn apply_ramp(&mut self) {
+        let Some(mut r) = self.ramp else { return };
+        for frame in self.queue.chunks_exact_mut(usize::from(self.to.channels)) {
+            for x in frame.iter_mut() {
+                *x *= r.gain;
+            }
+            if r.left > 0 {
+                r.left -= 1;
+                r.gain = if r.left == 0 { r.step.signum().max(0.0) } else { (r.gain + r.step).clamp(0.0, 1.0) };
+            }
+        }
+        // 淡入结束后恢复直通
+        self.ramp = if r.left == 0 && r.gain >= 1.0 { None } else { Some(r) };
+    }
+
+    /// Mixes the queue with the stashed tail, Hann-windowed like [`crate::shift`] does.
+    fn mix(&mut self) {
+        if !self.fading() {
+            return;
+        }
+        let channels = usize::from(self.to.channels);
+        let len = f64::from(self.t_len.max(1) as u32);
+        let mut t_b = self.t_b;
+        for (q, t) in self.queue.chunks_exact_mut(channels).zip(self.tail[t_b..].chunks_exact(channels)) {
+            let g = f64::from((t_b / channels) as u32);
+            let w = 0.5f64.mul_add(-(PI * (g + 0.5) / len).cos(), 0.5); // 新歌的权重
+            for (x, &y) in q.iter_mut().zip(t) {
+                *x = (*x - y).mul_add(w, y);
+            }
+            t_b += channels;
+        }
+        if t_b >= self.tail.len() {
+            self.tail.clear();
+            t_b = 0;
+        }
+        self.t_b = t_b;
+    }
+
+    fn flush_as<D: Fmt + Send + 'static>(&mut self) -> Result<bool, PlayError> {
+        let channels = usize::from(self.to.channels);
+        // 设备格式不会变，第一次之后取回的都是同一个缓冲区
+        let mut out = core::mem::replace(&mut self.out, Box::new(())).downcast::<Vec<D>>().unwrap_or_default();
+        let ret = loop {
+            if self.q_b >= self.queue.len() {
+                break Ok(true);
+            }
+            out.clear();
+            out.extend(self.queue[self.q_b..].iter().map(|&f| D::from_norm(f)));
+            match self.inner.writei(&out) {
+                Ok(0) => break Ok(false),
+                Ok(real) => self.q_b += real * channels,
+                Err(e) => break Err(e),
+            }
+        };
+        self.out = out;
+        ret
+    }
+
+    /// Writes out the queue, returns whether it is empty now.
+    fn flush(&mut self) -> Result<bool, PlayError> {
+        match self.to.format {
+            Format::S8 => self.flush_as::<i8>(),
+            Format::U8 => self.flush_as::<u8>(),
+            Format::S16LE => self.flush_as::<i16>(),
+            Format::S183LE => self.flush_as::<S18_3>(),
+            Format::S203LE => self.flush_as::<S20_3>(),
+            Format::S243LE => self.flush_as::<S24_3>(),
+            Format::S20LE => self.flush_as::<S20_4>(),
+            Format::S24LE => self.flush_as::<S24_4>(),
+            Format::S32LE => self.flush_as::<i32>(),
+            Format::FloatLE => self.flush_as::<f32>(),
+            Format::Float64LE => self.flush_as::<f64>(),
+            _ => Err(PlayError::Format(UnsupportedFormatError(WavSpec {
+                channels: self.to.channels,
+                sample_rate: self.to.rate,
+                bits_per_sample: 0,
+                bytes_per_sample: 0,
+                sample_format: hound::SampleFormat::Int,
+            }))),
+        }
+    }
+}
+
+impl<K: AudioSink> AudioSink for Convert<K> {
+    fn open(config: &SinkConfig, spec: WavSpec, format: Format) -> Result<Self, PlayError> {
+        let inner = K::open(config, spec, format)?;
+        let from = DeviceSpec::new(spec, format);
+        let to = inner.device_spec();
+
+        if from != to {
+            tracing::warn!("Device does not accept {from:?}, converting to {to:?}");
+        }
+        let resampler = (from.rate != to.rate).then(|| Resampler::new(from.rate, to.rate, to.channels.into(), config.quality));
+        let (i_n, o_n) = (usize::from(from.channels), usize::from(to.channels));
+
+        Ok(Self {
+            inner,
+            from,
+            to,
+            quality: config.quality,
+            resampler,
+            frame: vec![0.0; to.channels.into()],
+            mapped: Vec::new(),
+            downmix: if i_n > o_n { downmix(0, i_n, o_n) } else { Vec::new() },
+            queue: Vec::new(),
+            q_b: 0,
+            out: Box::new(()),
+            taken: 0,
+            tail: Vec::new(),
+            t_b: 0,
+            t_len: 0,
+            ramp: None,
+        })
+    }
+
+    #[inline]
+    fn device_spec(&self) -> DeviceSpec {
+        self.from
+    }
+
+    fn writei<S: Fmt>(&mut self, buf: &[S]) -> Result<usize, PlayError> {
+        if self.passthrough() {
+            return self.inner.writei(buf);
+        }
+
+        // 上次没写完的，buf 与上次相同，不再重新转换
+        if self.q_b == self.queue.len() {
+            self.enqueue(buf);
+        }
+        Ok(if self.flush()? { self.taken } else { 0 })
+    }
+
+    fn delay(&self) -> Result<isize, PlayError> {
+        let delay = self.inner.delay()?;
+        let Some(r) = &self.resampler else { return Ok(delay) };
+        // 设备中的帧换算回输入帧，加上滤波器中尚未输出的
+        let delay = (delay as i64 * i64::from(self.from.rate) / i64::from(self.to.rate)) as isize;
+        Ok(delay + r.delay().cast_signed())
+    }
+
+    fn drop(&mut self) -> Result<(), PlayError> {
+        self.queue.clear();
+        self.q_b = 0;
+        self.tail.clear();
+        self.t_b = 0;
+        self.ramp = None;
+        if let Some(r) = &mut self.resampler {
+            r.reset();
+        }
+        self.inner.drop()
+    }
+
+    #[inline]
+    fn prepare(&mut self) -> Result<(), PlayError> {
+        self.inner.prepare()
+    }
+
+    fn drain(&mut self) -> Result<(), PlayError> {
+        while !self.flush()? {
+            core::hint::spin_loop();
+        }
+        self.queue.clear();
+        self.q_b = 0;
+        if let Some(r) = &mut self.resampler {
+            r.finish(&mut self.queue);
+        }
+        // 没有下一首歌接上，淡出部分单独放完
+        let rest = self.tail.len() - self.t_b;
+        if self.queue.len() < rest {
+            self.queue.resize(rest, 0.0);
+        }
+        self.mix();
+        // 滤波器里剩下的也要跟着淡出，不然淡出到静音后还会响一下
+        self.apply_ramp();
+        while !self.flush()? {
+            core::hint::spin_loop();
+        }
+        self.inner.drain()
+    }
+
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.inner.is_running()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::SampleFormat;
+
+    use super::*;
+    use crate::sink::SinkKind;
+
+    /// Keeps what is written, taking `ROOM` frames at a time and then refusing one write, like a full device.
+    struct Short {
+        spec: DeviceSpec,
+        written: Vec<f64>,
+        room: usize,
+    }
+
+    impl Short {
+        const ROOM: usize = 100;
+    }
+
+    impl AudioSink for Short {
+        fn open(config: &SinkConfig, spec: WavSpec, format: Format) -> Result<Self, PlayError> {
+            let spec = WavSpec { sample_rate: config.rate_for(spec), ..spec };
+            Ok(Self { spec: DeviceSpec::new(spec, format), written: Vec::new(), room: Self::ROOM })
+        }
+
+        fn device_spec(&self) -> DeviceSpec {
+            self.spec
+        }
+
+        fn writei<S: Fmt>(&mut self, buf: &[S]) -> Result<usize, PlayError> {
+            if self.room == 0 {
+                self.room = Self::ROOM;
+                return Ok(0);
+            }
+            let frames = buf.len().min(self.room);
+            self.room -= frames;
+            self.written.extend(buf[..frames].iter().map(|&s| s.to_norm()));
+            Ok(frames)
+        }
+
+        fn delay(&self) -> Result<isize, PlayError> {
+            Ok(0)
+        }
+
+        fn drop(&mut self) -> Result<(), PlayError> {
+            Ok(())
+        }
+
+        fn prepare(&mut self) -> Result<(), PlayError> {
+            Ok(())
+        }
+
+        fn drain(&mut self) -> Result<(), PlayError> {
+            Ok(())
+        }
+
+        fn is_running(&self) -> bool {
+            false
+        }
+    }
+
+    /// A mono 16-bit 1 kHz source (so that milliseconds are frames), resampled to `rate` if given.
+    fn open(rate: Option<u32>) -> Convert<Short> {
+        let config = SinkConfig::new(SinkKind::Null, String::new(), None, None, None, rate, Quality::Fast);
+        let spec = WavSpec { channels: 1, sample_rate: 1000, bits_per_sample: 16, bytes_per_sample: 2, sample_format: SampleFormat::Int };
+        Convert::open(&config, spec, Format::S16LE).unwrap()
+    }
+
+    /// Writes `buf` in chunks the way the player does, retrying what was not taken.
+    fn write_all(sink: &mut Convert<Short>, buf: &[i16]) {
+        for chunk in buf.chunks(256) {
+            let mut b = 0;
+            while b < chunk.len() {
+                b += sink.writei(&chunk[b..]).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn itu_downmix() {
+        let h = FRAC_1_SQRT_2;
+        let close = |a: &[f64], b: &[f64]| a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-12);
+
+        // 5.1 (两侧环绕)：L C Ls 进左边，LFE 不要，归一化到不削波
+        let sum = 2.0f64.mul_add(h, 1.0);
+        let m = downmix(0x60f, 6, 2);
+        assert!(close(&m[..6], &[1.0 / sum, 0.0, h / sum, 0.0, h / sum, 0.0]));
+        assert!(close(&m[6..], &[0.0, 1.0 / sum, h / sum, 0.0, 0.0, h / sum]));
+        // 没有掩码按默认的 5.1 (后环绕)，结果一样
+        assert!(close(&downmix(0, 6, 2), &downmix(0x3f, 6, 2)));
+
+        // 单声道：左右各一半
+        assert!(close(&downmix(0x3, 2, 1), &[0.5, 0.5]));
+        // 不知道位置的按顺序轮流取平均
+        assert!(close(&downmix(0, 10, 2), &[0.2, 0.0, 0.2, 0.0, 0.2, 0.0, 0.2, 0.0, 0.2, 0.0, 0.0, 0.2, 0.0, 0.2, 0.0, 0.2, 0.0, 0.2, 0.0, 0.2]));
+    }
+
+    #[test]
+    fn crossfade_short_writes() {
+        let mut sink = open(None);
+        sink.stash(&[-8192i16; 300]);
+        let song: Vec<i16> = (0..1000).collect();
+        write_all(&mut sink, &song);
+
+        let written = &sink.inner.written;
+        assert_eq!(written.len(), song.len());
+        // 开头几乎全是上一首，淡化结束后原样直通
+        assert!((written[0] + 0.25).abs() < 1e-3);
+        assert!(written[150] > -0.25 && written[150] < f64::from(song[150]) / 32768.0);
+        for (&x, &s) in written.iter().zip(&song).skip(300) {
+            assert_eq!(i16::from_norm(x), s);
+        }
+    }
+
+    #[test]
+    fn crossfade_weights() {
+        // 权重对称，两首的和始终为 1
+        let mut sink = open(None);
+        sink.stash(&[0i16; 8]);
+        write_all(&mut sink, &[16384; 8]);
+        let written = &sink.inner.written;
+        assert!(written.is_sorted());
+        for g in 0..8 {
+            assert!((written[g] + written[7 - g] - 0.5).abs() < 1e-9, "frame {g}");
+        }
+
+        // 同样的信号淡化后不变
+        let mut sink = open(None);
+        sink.stash(&[8192i16; 100]);
+        write_all(&mut sink, &[8192; 100]);
+        assert!(sink.inner.written.iter().all(|&x| i16::from_norm(x) == 8192));
+    }
+
+    #[test]
+    fn ramp_in_short_writes() {
+        let mut sink = open(None);
+        sink.ramp_in(100);
+        write_all(&mut sink, &[16384; 500]);
+
+        let written = &sink.inner.written;
+        assert_eq!(written.len(), 500);
+        assert!(written[0].abs() < f64::EPSILON);
+        assert!(written[..100].is_sorted());
+        assert!(written[100..].iter().all(|&x| i16::from_norm(x) == 16384));
+    }
+
+    #[test]
+    fn ramp_out_during_ramp_in() {
+        let mut sink = open(None);
+        sink.ramp_in(100);
+        write_all(&mut sink, &[16384; 50]);
+        sink.ramp_out(100);
+        write_all(&mut sink, &[16384; 50]);
+        assert!(!sink.ramped_out());
+        write_all(&mut sink, &[16384; 150]);
+        assert!(sink.ramped_out());
+
+        // 从淡入到一半的音量接着淡出，不会跳变，结束后保持静音
+        let written = &sink.inner.written;
+        assert!(written[..50].is_sorted());
+        assert!((written[50] - written[49]).abs() <= 0.5 / 100.0 + 1.0 / 32768.0);
+        assert!(written[50..150].is_sorted_by(|a, b| a >= b));
+        assert!(written[149] > 0.0 && written[150..].iter().all(|x| x.abs() < f64::EPSILON));
+    }
+
+    #[test]
+    fn drain_after_ramp_out() {
+        let mut sink = open(Some(1500));
+        write_all(&mut sink, &[16384; 300]);
+        sink.ramp_out(100);
+        write_all(&mut sink, &[16384; 200]);
+        let faded = sink.inner.written.len();
+        sink.drain().unwrap();
+
+        // 淡出已经结束，滤波器里剩下的也是静音
+        let written = &sink.inner.written;
+        assert!(written.len() > faded);
+        assert!(written[faded - 1..].iter().all(|x| x.abs() < f64::EPSILON));
+    }
+}
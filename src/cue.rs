@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+
+use crate::tags::{self, Tags};
+
+/// CD 帧，每秒 75 个
+const CD_FRAMES: u64 = 75;
+
+/// One audio `TRACK` of a cue sheet.
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub file: PathBuf,
+    pub begin: u64, // INDEX 01，以 CD 帧计
+    pub tags: Tags,
+}
+
+impl Track {
+    /// The first frame of the track at `rate` Hz.
+    #[inline]
+    pub const fn begin_at(&self, rate: u32) -> u64 {
+        self.begin * rate as u64 / CD_FRAMES
+    }
+}
+
+/// The argument of a command, quoted or not.
+fn arg(rest: &str) -> String {
+    let rest = rest.trim_start();
+    match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_owned(),
+        None => rest.split_whitespace().next().unwrap_or_default().to_owned(),
+    }
+}
+
+/// Parses `mm:ss:ff` into CD frames.
+fn msf(s: &str) -> Option<u64> {
+    let mut it = s.split(':').map(|x| x.parse::<u64>().ok());
+    let (m, s, f) = (it.next()??, it.next()??, it.next()??);
+    (s < 60 && f < CD_FRAMES && it.next().is_none()).then_some((m * 60 + s) * CD_FRAMES + f)
+}
+
+/// Parses a cue sheet, resolving its `FILE`s against `dir`.
+///
+/// Tracks without `INDEX 01` are dropped; untitled ones are named by their number.
+pub fn parse(b: &[u8], dir: &Path) -> Vec<Track> {
+    let Some(text) = tags::text(b) else { return Vec::new() };
+    let mut disc = Tags::default(); // 第一个 TRACK 之前的 TITLE/PERFORMER 属于整张专辑
+    let mut file = None;
+    let mut tracks: Vec<(u32, Option<u64>, Tags, PathBuf)> = Vec::new();
+    let mut in_track = false;
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let cmd = cmd.to_ascii_uppercase();
+        match cmd.as_str() {
+            "FILE" => {
+                file = Some(dir.join(arg(rest)));
+                in_track = false;
+            }
+            "TRACK" => {
+                let mut words = rest.split_whitespace();
+                let number = words.next().and_then(|n| n.parse().ok()).unwrap_or_default();
+                in_track = false;
+                // 数据轨之类的不要
+                if words.next().is_some_and(|t| t.eq_ignore_ascii_case("AUDIO")) && let Some(file) = &file {
+                    tracks.push((number, None, Tags::default(), file.clone()));
+                    in_track = true;
+                }
+            }
+            "TITLE" | "PERFORMER" => {
+                let before_tracks = tracks.is_empty();
+                let tags = match tracks.last_mut() {
+                    Some((_, _, tags, _)) if in_track => tags,
+                    _ if before_tracks => &mut disc,
+                    _ => continue, // 跳过的轨道的信息
+                };
+                let field = match (cmd.as_str(), in_track) {
+                    ("TITLE", true) => &mut tags.title,
+                    ("TITLE", false) => &mut tags.album,
+                    _ => &mut tags.artist,
+                };
+                *field = Some(arg(rest)).filter(|s| !s.is_empty());
+            }
+            "INDEX" if in_track => {
+                let mut words = rest.split_whitespace();
+                if words.next().and_then(|n| n.parse::<u32>().ok()) == Some(1) && let Some(last) = tracks.last_mut() {
+                    last.1 = words.next().and_then(msf);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    tracks
+        .into_iter()
+        .filter_map(|(number, begin, tags, file)| {
+            let title = tags.title.or_else(|| Some(format!("Track {number:02}")));
+            Some(Track { file, begin: begin?, tags: Tags { title, ..tags }.or(disc.clone()) })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\u{feff}REM GENRE Rock
+PERFORMER \"The Band\"
+TITLE \"Live at Home\"
+FILE \"side a.wav\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Opening\"
+    INDEX 00 00:00:00
+    INDEX 01 00:02:37
+  TRACK 02 MODE1/2352
+    TITLE \"Data\"
+    INDEX 01 03:00:00
+  track 03 audio
+    performer Guest
+    index 01 04:10:74
+FILE side_b.flac WAVE
+  TRACK 04 AUDIO
+    TITLE \"No index\"
+  TRACK 05 AUDIO
+    INDEX 01 00:00:00
+";
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(msf("00:02:37"), Some(187));
+        assert_eq!(msf("74:59:74"), Some(((74 * 60) + 59) * 75 + 74));
+        assert_eq!(msf("100:00:00"), Some(100 * 60 * 75));
+        assert_eq!(msf("00:60:00"), None);
+        assert_eq!(msf("00:00:75"), None);
+        assert_eq!(msf("00:00"), None);
+        assert_eq!(msf("00:00:00:00"), None);
+        assert_eq!(msf("0a:00:00"), None);
+    }
+
+    #[test]
+    fn sheet() {
+        let tracks = parse(SHEET.as_bytes(), Path::new("/music"));
+        let summary: Vec<_> = tracks.iter().map(|t| (t.file.to_str().unwrap(), t.begin, t.tags.title.as_deref(), t.tags.artist.as_deref())).collect();
+        assert_eq!(summary, [
+            ("/music/side a.wav", 187, Some("Opening"), Some("The Band")),
+            ("/music/side a.wav", (4 * 60 + 10) * 75 + 74, Some("Track 03"), Some("Guest")),
+            ("/music/side_b.flac", 0, Some("Track 05"), Some("The Band")),
+        ]);
+        assert!(tracks.iter().all(|t| t.tags.album.as_deref() == Some("Live at Home")));
+        assert_eq!(tracks[0].begin_at(44100), 187 * 588);
+        assert!(parse(b"", Path::new("/")).is_empty());
+    }
+}
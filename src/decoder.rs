@@ -0,0 +1,332 @@
+use core::{ops::Range, time::Duration};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use hound::WavSpec;
+
+use crate::{
+    adpcm::AdpcmDecoder,
+    aiff, caf,
+    flac::FlacDecoder,
+    fmt_impl::Fmt,
+    g711::G711Decoder,
+    http::{self, HttpFile, OnTitle, Remote},
+    mp3::{LoadOptions, SongKind},
+    mpeg::{Mp3Decoder, Mp3Stream},
+    ogg::{OggDecoder, OggStream},
+    pcm::PcmDecoder,
+    raw::{self, RawFormat},
+    stream::{self, Stream},
+    util::{PlayError, UnsupportedFormatError},
+    wav::{self, WavDecoder},
+};
+
+/// A source of interleaved PCM frames, at the sample format its spec describes.
+pub trait Decoder {
+    /// `bits_per_sample`, `bytes_per_sample` and `sample_format` select the [`Fmt`] of [`Decoder::read`].
+    fn spec(&self) -> WavSpec;
+
+    /// Total number of frames, [`UNKNOWN_FRAMES`] for a stream.
+    fn frames(&self) -> u64;
+
+    /// Speaker positions of the channels, as in the `dwChannelMask` of `WAVE_FORMAT_EXTENSIBLE`; 0 if unspecified.
+    fn channel_mask(&self) -> u32 {
+        0
+    }
+
+    /// The frames [`Decoder::seek`] can go to.
+    fn reachable(&self) -> Range<u64> {
+        0..self.frames()
+    }
+
+    /// Reads whole frames into `buf`, returns how many; 0 only at the end.
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError>;
+
+    /// Continues reading from the `frame`-th frame.
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError>;
+}
+
+/// [`Decoder::frames`] of a stream, which plays until its input ends.
+pub const UNKNOWN_FRAMES: u64 = u64::MAX;
+
+/// Checks that `S` is the sample format of `spec`, and views `buf` as its bytes.
+pub fn as_bytes<S: Fmt>(buf: &mut [S], spec: WavSpec) -> Result<&mut [u8], PlayError> {
+    if usize::from(spec.bytes_per_sample) != size_of::<S>() || crate::util::cvt_format(spec)? != S::FORMAT {
+        return Err(PlayError::Format(UnsupportedFormatError(spec)));
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), size_of_val(buf)) })
+}
+
+/// Reads as many whole frames of `block_align` bytes into `buf` as there are, returns how many.
+///
+/// Unlike `read_exact`, the end of the input is not an error; a partial frame there is dropped.
+pub fn read_frames<R: Read>(reader: &mut R, buf: &mut [u8], block_align: u64) -> io::Result<u64> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled as u64 / block_align)
+}
+
+struct Shelf<D> {
+    decoder: Option<(PathBuf, D)>,
+    pending: bool, // 正在等待一个被停下的播放器留下解码器
+}
+
+/// An open decoder left behind by a finished song, for the next virtual
+/// track of the same file to read on without reopening it.
+pub struct Leftover<D>(Arc<(Mutex<Shelf<D>>, Condvar)>);
+
+impl<D> Clone for Leftover<D> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<D> Default for Leftover<D> {
+    #[inline]
+    fn default() -> Self {
+        Self(Arc::new((Mutex::new(Shelf { decoder: None, pending: false }), Condvar::new())))
+    }
+}
+
+impl<D> Leftover<D> {
+    /// How long [`Leftover::take`] waits for a [`Leftover::reserve`]d decoder.
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Shelf<D>> {
+        self.0.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Leaves the decoder of `path`, replacing any older one.
+    pub fn put(&self, path: PathBuf, decoder: D) {
+        let old = {
+            let mut shelf = self.lock();
+            shelf.pending = false;
+            shelf.decoder.replace((path, decoder))
+        };
+        self.0.1.notify_all();
+        drop(old);
+    }
+
+    /// Makes the next [`Leftover::take`] wait for a decoder about to be left.
+    pub fn reserve(&self) {
+        self.lock().pending = true;
+    }
+
+    /// Cancels [`Leftover::reserve`], nothing will be left.
+    pub fn release(&self) {
+        self.lock().pending = false;
+        self.0.1.notify_all();
+    }
+
+    /// Takes the decoder left for `path`, dropping one left for another file.
+    pub fn take(&self, path: &Path) -> Option<D> {
+        let (mut shelf, _) = self.0.1.wait_timeout_while(self.lock(), Self::TIMEOUT, |shelf| shelf.pending).unwrap_or_else(PoisonError::into_inner);
+        shelf.pending = false;
+        let (left, decoder) = shelf.decoder.take()?;
+        drop(shelf);
+        (left == path).then_some(decoder)
+    }
+}
+
+/// Where the bytes of a song come from, a local file or one on an HTTP server.
+pub enum Source {
+    File(File),
+    Http(HttpFile),
+}
+
+impl Source {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        const LIVE: io::Error = io::const_error!(io::ErrorKind::InvalidData, "the URL is now a live stream");
+
+        if http::is_url(path) {
+            match http::open(&path.to_string_lossy())? {
+                Remote::File(file) => Ok(Self::Http(file)),
+                Remote::Live(_) => Err(LIVE),
+            }
+        } else {
+            Ok(Self::File(File::open(path)?))
+        }
+    }
+
+    /// Length in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            Self::File(f) => Ok(f.metadata()?.len()),
+            Self::Http(f) => Ok(f.size()),
+        }
+    }
+}
+
+impl Read for Source {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(f) => f.read(buf),
+            Self::Http(f) => f.read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(f) => f.seek(pos),
+            Self::Http(f) => f.seek(pos),
+        }
+    }
+}
+
+/// Every decoder a song can be played with.
+pub enum AnyDecoder {
+    Wav(WavDecoder<BufReader<Source>>),
+    Mp3(Box<Mp3Decoder<Source>>),
+    Flac(Box<FlacDecoder<Source>>),
+    Ogg(Box<OggDecoder<Source>>),
+    Pcm(PcmDecoder<BufReader<Source>>),
+    G711(G711Decoder<BufReader<Source>>),
+    Adpcm(Box<AdpcmDecoder<BufReader<Source>>>),
+    Stream(PcmDecoder<Stream<stream::Input>>),
+    Mp3Stream(Box<Mp3Stream<stream::Input>>),
+    OggStream(Box<OggStream<stream::Input>>),
+}
+
+impl AnyDecoder {
+    /// Opens the song at `path`; `on_title` hears of the titles a live stream announces.
+    pub fn open(path: &Path, kind: SongKind, options: LoadOptions, on_title: OnTitle) -> Result<Self, PlayError> {
+        Ok(match kind {
+            SongKind::Wav => Self::open_wav(Source::open(path)?)?,
+            SongKind::Mp3 => Self::Mp3(Box::new(Mp3Decoder::new(Source::open(path)?)?)),
+            SongKind::Flac => Self::Flac(Box::new(FlacDecoder::new(Source::open(path)?)?)),
+            SongKind::Ogg => Self::Ogg(Box::new(OggDecoder::new(Source::open(path)?)?)),
+            SongKind::Aiff => Self::Pcm(aiff::open(Source::open(path)?)?),
+            SongKind::Caf => Self::Pcm(caf::open(Source::open(path)?)?),
+            SongKind::Raw(format) => Self::Pcm(raw::open(Source::open(path)?, format)?),
+            SongKind::Stream(raw) => {
+                let (input, content_type) = stream::input(path, options.prebuffer, on_title)?;
+                Self::open_stream(input, content_type.as_deref(), raw)?
+            }
+        })
+    }
+
+    /// Picks the decoder of a stream by its first bytes or its `Content-Type`, MPEG audio unless it is a WAVE or Ogg (or `raw` PCM).
+    fn open_stream(mut input: stream::Input, content_type: Option<&str>, raw: Option<RawFormat>) -> io::Result<Self> {
+        if raw.is_some() {
+            return Ok(Self::Stream(stream::open(input, raw)?));
+        }
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        let input: stream::Input = Box::new(io::Cursor::new(magic).chain(input));
+        Ok(match &magic {
+            b"RIFF" | b"RF64" | b"BW64" => Self::Stream(stream::open(input, None)?),
+            b"OggS" => Self::OggStream(Box::new(OggStream::new(input)?)),
+            _ if matches!(content_type, Some("application/ogg" | "audio/ogg" | "audio/opus" | "audio/vorbis")) => Self::OggStream(Box::new(OggStream::new(input)?)),
+            _ => Self::Mp3Stream(Box::new(Mp3Stream::new(input)?)),
+        })
+    }
+
+    /// Picks the decoder by the format tag of the fmt chunk.
+    fn open_wav(file: Source) -> io::Result<Self> {
+        let mut reader = BufReader::new(file);
+        let header = wav::read_header(&mut reader)?;
+        tracing::info!("WAVE data at {}: {} frames, format tag {:#06x}, channel mask {:#x}", header.begin, header.frames(), header.tag, header.channel_mask);
+        Ok(match header.tag {
+            wav::WAVE_FORMAT_ALAW | wav::WAVE_FORMAT_MULAW => Self::G711(G711Decoder::new(reader, &header)?),
+            wav::WAVE_FORMAT_ADPCM | wav::WAVE_FORMAT_IMA_ADPCM => Self::Adpcm(Box::new(AdpcmDecoder::new(reader, &header)?)),
+            _ => Self::Wav(WavDecoder::new(reader, &header)?),
+        })
+    }
+}
+
+impl Decoder for AnyDecoder {
+    fn spec(&self) -> WavSpec {
+        match self {
+            Self::Wav(d) => d.spec(),
+            Self::Mp3(d) => d.spec(),
+            Self::Flac(d) => d.spec(),
+            Self::Ogg(d) => d.spec(),
+            Self::Pcm(d) => d.spec(),
+            Self::G711(d) => d.spec(),
+            Self::Adpcm(d) => d.spec(),
+            Self::Stream(d) => d.spec(),
+            Self::Mp3Stream(d) => d.spec(),
+            Self::OggStream(d) => d.spec(),
+        }
+    }
+
+    fn frames(&self) -> u64 {
+        match self {
+            Self::Wav(d) => d.frames(),
+            Self::Mp3(d) => d.frames(),
+            Self::Flac(d) => d.frames(),
+            Self::Ogg(d) => d.frames(),
+            Self::Pcm(d) => d.frames(),
+            Self::G711(d) => d.frames(),
+            Self::Adpcm(d) => d.frames(),
+            Self::Stream(d) => d.frames(),
+            Self::Mp3Stream(d) => d.frames(),
+            Self::OggStream(d) => d.frames(),
+        }
+    }
+
+    fn channel_mask(&self) -> u32 {
+        match self {
+            Self::Wav(d) => d.channel_mask(),
+            Self::G711(d) => d.channel_mask(),
+            Self::Adpcm(d) => d.channel_mask(),
+            _ => 0,
+        }
+    }
+
+    fn reachable(&self) -> Range<u64> {
+        match self {
+            Self::Stream(d) => d.window(),
+            Self::Mp3Stream(d) => d.window(),
+            Self::OggStream(d) => d.window(),
+            _ => 0..self.frames(),
+        }
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        match self {
+            Self::Wav(d) => d.read(buf),
+            Self::Mp3(d) => d.read(buf),
+            Self::Flac(d) => d.read(buf),
+            Self::Ogg(d) => d.read(buf),
+            Self::Pcm(d) => d.read(buf),
+            Self::G711(d) => d.read(buf),
+            Self::Adpcm(d) => d.read(buf),
+            Self::Stream(d) => d.read(buf),
+            Self::Mp3Stream(d) => d.read(buf),
+            Self::OggStream(d) => d.read(buf),
+        }
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        match self {
+            Self::Wav(d) => d.seek(frame),
+            Self::Mp3(d) => d.seek(frame),
+            Self::Flac(d) => d.seek(frame),
+            Self::Ogg(d) => d.seek(frame),
+            Self::Pcm(d) => d.seek(frame),
+            Self::G711(d) => d.seek(frame),
+            Self::Adpcm(d) => d.seek(frame),
+            Self::Stream(d) => d.seek(frame),
+            Self::Mp3Stream(d) => d.seek(frame),
+            Self::OggStream(d) => d.seek(frame),
+        }
+    }
+}
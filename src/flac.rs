@@ -0,0 +1,825 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use hound::{SampleFormat, WavSpec};
+
+use crate::{
+    decoder::{self, Decoder},
+    fmt_impl::Fmt,
+    util::PlayError,
+};
+
+const NOT_FLAC: io::Error = io::const_error!(io::ErrorKind::InvalidData, "not a FLAC stream");
+const BAD_STREAMINFO: io::Error = io::const_error!(io::ErrorKind::InvalidData, "invalid FLAC STREAMINFO block");
+const BAD_FRAME: io::Error = io::const_error!(io::ErrorKind::InvalidData, "corrupted FLAC frame");
+
+/// Bisection stops once the range is this small, and decodes on linearly from there.
+const LINEAR_SEEK: u64 = 64 << 10;
+
+/// The STREAMINFO metadata block.
+#[derive(Clone, Copy, Debug)]
+struct StreamInfo {
+    max_block: u16,
+    max_frame: u32, // 0 表示未知
+    sample_rate: u32,
+    channels: u16,
+    bps: u16,
+    total: u64, // 0 表示未知
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SeekPoint {
+    sample: u64,
+    offset: u64, // 相对第一帧
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FrameHeader {
+    sample: u64, // 第一个采样的序号
+    block: usize,
+    channels: u8, // 0..=7 独立声道数减一，8 左/差，9 差/右，10 中/差
+    bps: u16,
+}
+
+impl FrameHeader {
+    #[inline]
+    const fn channel_count(self) -> usize {
+        if self.channels < 8 { self.channels as usize + 1 } else { 2 }
+    }
+
+    /// Bits per sample of channel `c`, one more for a side channel.
+    #[inline]
+    const fn channel_bps(self, c: usize) -> u32 {
+        let side = matches!((self.channels, c), (8 | 10, 1) | (9, 0));
+        self.bps as u32 + side as u32
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &b| {
+        (0..8).fold(crc ^ b, |crc, _| if crc & 0x80 != 0 { crc << 1 ^ 0x07 } else { crc << 1 })
+    })
+}
+
+fn crc16(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &b| {
+        (0..8).fold(crc ^ u16::from(b) << 8, |crc, _| if crc & 0x8000 != 0 { crc << 1 ^ 0x8005 } else { crc << 1 })
+    })
+}
+
+/// Reads through, keeping the CRC-16 of everything read, which ends a frame.
+struct Crc16<R> {
+    inner: R,
+    crc: u16,
+}
+
+impl<R: Read> Read for Crc16<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc16(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Reads a frame header, `None` if there is none (or a corrupted one) here.
+fn read_header<R: Read>(reader: &mut R, info: &StreamInfo) -> io::Result<Option<FrameHeader>> {
+    match parse_header(reader, info) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        r => r,
+    }
+}
+
+fn parse_header<R: Read>(reader: &mut R, info: &StreamInfo) -> io::Result<Option<FrameHeader>> {
+    let mut bytes = Vec::with_capacity(16);
+    let mut next = || -> io::Result<u32> {
+        let mut b = [0];
+        reader.read_exact(&mut b)?;
+        bytes.push(b[0]);
+        Ok(b[0].into())
+    };
+
+    let head = (0..4).try_fold(0, |acc, _| next().map(|b| acc << 8 | b))?;
+    // 同步码 0b11111111111110，保留位 0
+    if head >> 17 != 0x7ffc || head & 1 != 0 {
+        return Ok(None);
+    }
+    let variable = head >> 16 & 1 != 0;
+    let (block_code, rate_code) = (head >> 12 & 0xf, head >> 8 & 0xf);
+    let channels = (head >> 4 & 0xf) as u8;
+    let bps = match head >> 1 & 7 {
+        0 => info.bps,
+        1 => 8,
+        2 => 12,
+        4 => 16,
+        5 => 20,
+        6 => 24,
+        7 => 32,
+        _ => return Ok(None),
+    };
+    if channels > 10 || rate_code == 15 || block_code == 0 {
+        return Ok(None);
+    }
+
+    // 类 UTF-8 编码的帧号 (固定块大小) 或采样号 (可变块大小)
+    let first = next()?;
+    let len = (first as u8).leading_ones();
+    if len == 1 || len > 7 {
+        return Ok(None);
+    }
+    let mut number = u64::from(first & (0x7f >> len));
+    for _ in 1..len {
+        let b = next()?;
+        if b & 0xc0 != 0x80 {
+            return Ok(None);
+        }
+        number = number << 6 | u64::from(b & 0x3f);
+    }
+
+    let block = match block_code {
+        1 => 192,
+        2..=5 => 576 << (block_code - 2),
+        6 => next()? + 1,
+        7 => (next()? << 8 | next()?) + 1,
+        _ => 256 << (block_code - 8),
+    };
+    let sample_rate = match rate_code {
+        12 => next()? * 1000,
+        13 => next()? << 8 | next()?,
+        14 => (next()? << 8 | next()?) * 10,
+        _ => info.sample_rate, // 其余的与 STREAMINFO 对照即可
+    };
+    let crc = next()?;
+
+    let header = FrameHeader {
+        sample: if variable { number } else { number * u64::from(info.max_block) },
+        block: block as usize,
+        channels,
+        bps,
+    };
+    // 与 STREAMINFO 不一致的多半是误认的同步码
+    let valid = u32::from(crc8(&bytes[..bytes.len() - 1])) == crc
+        && header.channel_count() == usize::from(info.channels)
+        && bps == info.bps
+        && sample_rate == info.sample_rate;
+    Ok(valid.then_some(header))
+}
+
+/// MSB-first bit reader that never reads past the byte holding its last bit.
+struct Bits<'a, R> {
+    inner: &'a mut R,
+    cache: u64,
+    avail: u32, // cache 中有效的位数
+}
+
+impl<'a, R: Read> Bits<'a, R> {
+    const fn new(inner: &'a mut R) -> Self {
+        Self { inner, cache: 0, avail: 0 }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let mut b = [0];
+        self.inner.read_exact(&mut b)?;
+        self.cache = self.cache << 8 | u64::from(b[0]);
+        self.avail += 8;
+        Ok(())
+    }
+
+    #[inline]
+    const fn drop_consumed(&mut self) {
+        self.cache &= (1 << self.avail) - 1;
+    }
+
+    /// Reads `n` (at most 40) bits.
+    fn read(&mut self, n: u32) -> io::Result<u64> {
+        while self.avail < n {
+            self.refill()?;
+        }
+        self.avail -= n;
+        let ret = self.cache >> self.avail;
+        self.drop_consumed();
+        Ok(ret)
+    }
+
+    fn read_signed(&mut self, n: u32) -> io::Result<i64> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let v = self.read(n)?;
+        Ok((v << (64 - n)).cast_signed() >> (64 - n))
+    }
+
+    /// Counts zeros up to the next one bit.
+    fn unary(&mut self) -> io::Result<u32> {
+        let mut n = 0;
+        loop {
+            if self.avail == 0 {
+                self.refill()?;
+            }
+            if self.cache == 0 {
+                n += self.avail;
+                self.avail = 0;
+                continue;
+            }
+            let zeros = self.cache.leading_zeros() - (64 - self.avail);
+            self.avail -= zeros + 1;
+            self.drop_consumed();
+            return Ok(n + zeros);
+        }
+    }
+
+    const fn align(&mut self) {
+        self.avail -= self.avail % 8;
+        self.drop_consumed();
+    }
+}
+
+/// Decodes one subframe of `out.len()` samples.
+fn subframe<R: Read>(bits: &mut Bits<'_, R>, bps: u32, out: &mut [i64]) -> io::Result<()> {
+    if bits.read(1)? != 0 {
+        return Err(BAD_FRAME);
+    }
+    let kind = bits.read(6)? as u32;
+    let wasted = if bits.read(1)? != 0 { bits.unary()? + 1 } else { 0 };
+    let bps = bps.checked_sub(wasted).ok_or(BAD_FRAME)?;
+
+    match kind {
+        0 => out.fill(bits.read_signed(bps)?), // CONSTANT
+        1 => { // VERBATIM
+            for s in out.iter_mut() {
+                *s = bits.read_signed(bps)?;
+            }
+        }
+        8..=12 => { // FIXED
+            const COEFFS: [&[i64]; 5] = [&[], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]];
+            let order = (kind - 8) as usize;
+            warm_up(bits, bps, order, out)?;
+            residual(bits, order, out)?;
+            predict(COEFFS[order], 0, out);
+        }
+        32..=63 => { // LPC
+            let order = (kind - 31) as usize;
+            warm_up(bits, bps, order, out)?;
+            let precision = bits.read(4)? as u32 + 1;
+            let shift = u32::try_from(bits.read_signed(5)?).map_err(|_| BAD_FRAME)?;
+            if precision == 16 {
+                return Err(BAD_FRAME);
+            }
+            let mut coeffs = [0; 32];
+            for c in &mut coeffs[..order] {
+                *c = bits.read_signed(precision)?;
+            }
+            residual(bits, order, out)?;
+            predict(&coeffs[..order], shift, out);
+        }
+        _ => return Err(BAD_FRAME),
+    }
+
+    if wasted != 0 {
+        for s in out.iter_mut() {
+            *s <<= wasted;
+        }
+    }
+    Ok(())
+}
+
+fn warm_up<R: Read>(bits: &mut Bits<'_, R>, bps: u32, order: usize, out: &mut [i64]) -> io::Result<()> {
+    for s in out.get_mut(..order).ok_or(BAD_FRAME)? {
+        *s = bits.read_signed(bps)?;
+    }
+    Ok(())
+}
+
+/// Reads the Rice-coded residual into `out[order..]`.
+fn residual<R: Read>(bits: &mut Bits<'_, R>, order: usize, out: &mut [i64]) -> io::Result<()> {
+    let (param_bits, escape) = match bits.read(2)? {
+        0 => (4, 15),
+        1 => (5, 31),
+        _ => return Err(BAD_FRAME),
+    };
+    let partition_order = bits.read(4)? as u32;
+    let len = out.len() >> partition_order;
+    if len < order || len << partition_order != out.len() {
+        return Err(BAD_FRAME);
+    }
+
+    let mut i = order;
+    for p in 0..1usize << partition_order {
+        let end = (p + 1) * len;
+        let k = bits.read(param_bits)? as u32;
+        if k == escape {
+            let n = bits.read(5)? as u32;
+            for s in &mut out[i..end] {
+                *s = bits.read_signed(n)?;
+            }
+        } else {
+            for s in &mut out[i..end] {
+                let v = u64::from(bits.unary()?) << k | bits.read(k)?;
+                *s = (v >> 1).cast_signed() ^ -((v & 1).cast_signed()); // zigzag
+            }
+        }
+        i = end;
+    }
+    Ok(())
+}
+
+/// Adds the linear prediction to the residual in place.
+fn predict(coeffs: &[i64], shift: u32, out: &mut [i64]) {
+    for i in coeffs.len()..out.len() {
+        let sum: i64 = coeffs.iter().enumerate().map(|(j, &c)| c * out[i - 1 - j]).sum();
+        out[i] += sum >> shift;
+    }
+}
+
+/// Restores left and right from the stereo channel assignment of a frame.
+fn decorrelate(channels: u8, left: &mut [i64], right: &mut [i64]) {
+    match channels {
+        8 => left.iter().zip(right.iter_mut()).for_each(|(&l, s)| *s = l - *s),
+        9 => left.iter_mut().zip(right.iter()).for_each(|(s, &r)| *s += r),
+        10 => left.iter_mut().zip(right.iter_mut()).for_each(|(m, s)| {
+            let mid = *m << 1 | (*s & 1);
+            (*m, *s) = ((mid + *s) >> 1, (mid - *s) >> 1);
+        }),
+        _ => (),
+    }
+}
+
+/// Container width the samples are left-justified into.
+const fn container(bps: u16) -> (u16, u16) {
+    match bps {
+        ..=8 => (8, 1),
+        9..=16 => (16, 2),
+        17..=24 => (24, 3),
+        _ => (32, 4),
+    }
+}
+
+/// Reads the metadata blocks, returns where the first frame is.
+fn read_metadata<R: Read + Seek>(reader: &mut BufReader<R>) -> io::Result<(StreamInfo, Vec<SeekPoint>, u64)> {
+    let mut magic = [0; 4];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut magic)?;
+    let mut pos = 0;
+    // 前面可能有 ID3v2 标签
+    while magic.starts_with(b"ID3") {
+        let mut rest = [0; 6];
+        reader.read_exact(&mut rest)?;
+        let size = rest[2..].iter().fold(0, |acc, &b| acc << 7 | u64::from(b & 0x7f));
+        pos += 10 + size + if rest[1] & 0x10 != 0 { 10 } else { 0 };
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut magic)?;
+    }
+    if &magic != b"fLaC" {
+        return Err(NOT_FLAC);
+    }
+    pos += 4;
+
+    let mut info = None;
+    let mut seek_table = Vec::new();
+    loop {
+        let mut head = [0; 4];
+        reader.read_exact(&mut head)?;
+        let len = u32::from_be_bytes([0, head[1], head[2], head[3]]) as usize;
+        let mut block = vec![0; len];
+        reader.read_exact(&mut block)?;
+        pos += 4 + len as u64;
+
+        match head[0] & 0x7f {
+            0 => { // STREAMINFO
+                let b = block.get(..18).ok_or(BAD_STREAMINFO)?;
+                let packed = u64::from_be_bytes(b[10..18].try_into().map_err(|_| BAD_STREAMINFO)?);
+                let stream = StreamInfo {
+                    max_block: u16::from_be_bytes([b[2], b[3]]),
+                    max_frame: u32::from_be_bytes([0, b[7], b[8], b[9]]),
+                    sample_rate: (packed >> 44) as u32,
+                    channels: (packed >> 41 & 7) as u16 + 1,
+                    bps: (packed >> 36 & 0x1f) as u16 + 1,
+                    total: packed & 0xf_ffff_ffff,
+                };
+                if stream.sample_rate == 0 || stream.max_block < 16 || stream.bps < 4 {
+                    return Err(BAD_STREAMINFO);
+                }
+                info = Some(stream);
+            }
+            3 => { // SEEKTABLE
+                for p in block.chunks_exact(18) {
+                    let sample = u64::from_be_bytes(p[..8].try_into().map_err(|_| NOT_FLAC)?);
+                    let offset = u64::from_be_bytes(p[8..16].try_into().map_err(|_| NOT_FLAC)?);
+                    if sample != u64::MAX { // 占位点
+                        seek_table.push(SeekPoint { sample, offset });
+                    }
+                }
+            }
+            _ => (),
+        }
+        if head[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    Ok((info.ok_or(BAD_STREAMINFO)?, seek_table, pos))
+}
+
+/// Reads the spec and the number of frames from the STREAMINFO block.
+pub fn probe<R: Read + Seek>(reader: R) -> io::Result<(WavSpec, u64)> {
+    let mut reader = BufReader::new(reader);
+    let (info, _, _) = read_metadata(&mut reader)?;
+    let (bits, bytes) = container(info.bps);
+    let spec = WavSpec {
+        channels: info.channels,
+        sample_rate: info.sample_rate,
+        bits_per_sample: bits,
+        bytes_per_sample: bytes,
+        sample_format: SampleFormat::Int,
+    };
+    Ok((spec, info.total))
+}
+
+/// Decodes a native FLAC stream, left-justifying the samples into 8, 16, 24 or 32 bits.
+pub struct FlacDecoder<R> {
+    reader: BufReader<R>,
+    info: StreamInfo,
+    spec: WavSpec,
+    seek_table: Vec<SeekPoint>,
+    first: u64, // 第一帧的位置
+    origin: u64, // 第一帧的采样号，从流中间截下的文件不为 0
+    len: u64,
+    frames: u64,
+    channel: Vec<i64>,
+    block: Vec<i32>, // 当前帧解码后的交错采样
+    block_start: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> FlacDecoder<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let (info, seek_table, first) = read_metadata(&mut reader)?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        let (bits, bytes) = container(info.bps);
+        let spec = WavSpec {
+            channels: info.channels,
+            sample_rate: info.sample_rate,
+            bits_per_sample: bits,
+            bytes_per_sample: bytes,
+            sample_format: SampleFormat::Int,
+        };
+
+        let mut ret = Self {
+            reader,
+            info,
+            spec,
+            seek_table,
+            first,
+            origin: 0,
+            len,
+            frames: info.total,
+            channel: Vec::new(),
+            block: Vec::new(),
+            block_start: 0,
+            pos: 0,
+        };
+        if let Some((_, header)) = ret.sync_from(first)? {
+            ret.origin = header.sample;
+        }
+        if ret.frames == 0 {
+            // STREAMINFO 中没有总长度，找最后一帧
+            let mut at = len.saturating_sub(LINEAR_SEEK).max(first);
+            while let Some((found, header)) = ret.sync_from(at)? {
+                ret.frames = header.sample + header.block as u64;
+                at = found + 1;
+            }
+        }
+        tracing::info!("FLAC stream: {info:?}, {} seek points, {} frames", ret.seek_table.len(), ret.frames);
+        ret.reader.seek(SeekFrom::Start(first))?;
+        Ok(ret)
+    }
+
+    /// Reads a frame header, numbering samples from the first frame.
+    fn read_header(&mut self) -> io::Result<Option<FrameHeader>> {
+        let header = read_header(&mut self.reader, &self.info)?;
+        Ok(header.map(|h| FrameHeader { sample: h.sample.saturating_sub(self.origin), ..h }))
+    }
+
+    /// Finds the first frame header at or after `pos`, leaving the reader at it.
+    fn sync_from(&mut self, mut pos: u64) -> io::Result<Option<(u64, FrameHeader)>> {
+        self.reader.seek(SeekFrom::Start(pos))?;
+        let mut prev = 0;
+        loop {
+            let mut b = [0];
+            match self.reader.read_exact(&mut b) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                r => r?,
+            }
+            pos += 1;
+            if prev == 0xff && b[0] & 0xfe == 0xf8 {
+                let at = pos - 2;
+                self.reader.seek(SeekFrom::Start(at))?;
+                if let Some(header) = self.read_header()? {
+                    self.reader.seek(SeekFrom::Start(at))?;
+                    return Ok(Some((at, header)));
+                }
+                self.reader.seek(SeekFrom::Start(pos))?;
+            }
+            prev = b[0];
+        }
+    }
+
+    /// Leaves the reader at a frame starting at or before `target`.
+    fn seek_to(&mut self, target: u64) -> io::Result<()> {
+        let (mut lo, mut hi) = (self.first, self.len);
+        // SEEKTABLE 缩小范围，剩下的二分帧头
+        if let Some(i) = self.seek_table.iter().rposition(|p| p.sample <= target) {
+            lo = self.first + self.seek_table[i].offset;
+            if let Some(p) = self.seek_table.get(i + 1) {
+                hi = self.first + p.offset;
+            }
+        }
+        let linear = LINEAR_SEEK.max(2 * u64::from(self.info.max_frame));
+        while hi - lo > linear {
+            let mid = lo + (hi - lo) / 2;
+            match self.sync_from(mid)? {
+                Some((at, header)) if at < hi && header.sample <= target => lo = at,
+                _ => hi = mid,
+            }
+        }
+        self.reader.seek(SeekFrom::Start(lo))?;
+        self.block.clear();
+        self.block_start = 0;
+        Ok(())
+    }
+
+    /// Decodes the frame at the reader into `self.block`, returns `false` at the end.
+    fn decode_frame(&mut self) -> io::Result<bool> {
+        let expected = self.block_start + (self.block.len() / usize::from(self.info.channels)) as u64;
+        let mut frame = Crc16 { inner: &mut self.reader, crc: 0 };
+        let header = read_header(&mut frame, &self.info)?.map(|h| FrameHeader { sample: h.sample.saturating_sub(self.origin), ..h });
+        let Some(header) = header else {
+            let here = self.reader.stream_position()?;
+            let Some((_, header)) = self.sync_from(here)? else { return Ok(false) };
+            // 跳过了损坏的部分，以静音补齐
+            tracing::warn!("FLAC stream resynchronized at sample {} (expected {expected})", header.sample);
+            self.silence(expected, header.sample.saturating_sub(expected) as usize);
+            return Ok(true);
+        };
+
+        let channels = header.channel_count();
+        self.channel.resize(header.block * channels, 0);
+        let decoded = (|| -> io::Result<bool> {
+            let mut bits = Bits::new(&mut frame);
+            for (c, out) in self.channel.chunks_exact_mut(header.block).enumerate() {
+                subframe(&mut bits, header.channel_bps(c), out)?;
+            }
+            bits.align();
+            let crc = bits.inner.crc;
+            Ok(bits.read(16)? == u64::from(crc))
+        })();
+        // 与帧头 CRC-8 不对一样，损坏的帧以静音代替
+        match decoded {
+            Ok(true) => (),
+            Ok(false) => {
+                tracing::warn!("FLAC frame at sample {}: CRC-16 mismatch", header.sample);
+                self.silence(header.sample, header.block);
+                return Ok(true);
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => {
+                tracing::warn!("FLAC frame at sample {}: {e}", header.sample);
+                self.silence(header.sample, header.block);
+                return Ok(true);
+            }
+        }
+
+        let (left, right) = self.channel.split_at_mut(header.block);
+        decorrelate(header.channels, left, right);
+
+        let shift = self.spec.bits_per_sample - self.info.bps;
+        self.block.clear();
+        for i in 0..header.block {
+            for c in 0..channels {
+                self.block.push((self.channel[c * header.block + i] << shift) as i32);
+            }
+        }
+        self.block_start = header.sample;
+        Ok(true)
+    }
+
+    fn silence(&mut self, start: u64, frames: usize) {
+        self.block.clear();
+        self.block.resize(frames * usize::from(self.info.channels), 0);
+        self.block_start = start;
+    }
+
+    /// Makes `self.block` hold `self.pos`, returns `false` at the end.
+    fn locate(&mut self) -> io::Result<bool> {
+        let channels = usize::from(self.info.channels);
+        let end = self.block_start + (self.block.len() / channels) as u64;
+        if (self.block_start..end).contains(&self.pos) {
+            return Ok(true);
+        }
+        // 往回或跳得很远时才真正定位，否则接着往下解码
+        if self.pos < self.block_start || self.pos - end > 4 * u64::from(self.info.max_block) {
+            self.seek_to(self.pos)?;
+        }
+        loop {
+            if !self.decode_frame()? {
+                return Ok(false);
+            }
+            let end = self.block_start + (self.block.len() / channels) as u64;
+            if self.pos < end {
+                return Ok(self.pos >= self.block_start);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Decoder for FlacDecoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let channels = usize::from(self.info.channels);
+        let bytes = usize::from(self.spec.bytes_per_sample);
+        let spec = self.spec;
+        let buf = decoder::as_bytes(buf, spec)?;
+        let want = (buf.len() / (bytes * channels)).min(self.frames.saturating_sub(self.pos) as usize);
+
+        let mut done = 0;
+        while done < want && self.locate()? {
+            let from = (self.pos - self.block_start) as usize * channels;
+            let n = ((self.block.len() - from) / channels).min(want - done);
+            for (out, &s) in buf[done * channels * bytes..].chunks_exact_mut(bytes).zip(&self.block[from..from + n * channels]) {
+                if bytes == 1 {
+                    out[0] = (s as i8).cast_unsigned() ^ 0x80; // 与 WAV 一样，8 位是无符号的
+                } else {
+                    out.copy_from_slice(&s.to_le_bytes()[..bytes]);
+                }
+            }
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+
+    #[inline]
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        self.pos = frame.min(self.frames);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MSB-first bit writer, the inverse of [`Bits`].
+    #[derive(Default)]
+    struct Writer {
+        bytes: Vec<u8>,
+        acc: u8,
+        n: u32,
+    }
+
+    impl Writer {
+        fn put(&mut self, v: u64, n: u32) {
+            for i in (0..n).rev() {
+                self.acc = self.acc << 1 | (v >> i & 1) as u8;
+                self.n += 1;
+                if self.n == 8 {
+                    self.bytes.push(self.acc);
+                    (self.acc, self.n) = (0, 0);
+                }
+            }
+        }
+
+        fn signed(&mut self, v: i64, n: u32) {
+            self.put(v.cast_unsigned() & ((1 << n) - 1), n);
+        }
+
+        fn rice(&mut self, r: i64, k: u32) {
+            let u = (r << 1 ^ r >> 63).cast_unsigned();
+            for _ in 0..u >> k {
+                self.put(0, 1);
+            }
+            self.put(1, 1);
+            self.put(u & ((1 << k) - 1), k);
+        }
+
+        /// Subframe header without wasted bits.
+        fn header(&mut self, kind: u64) {
+            self.put(0, 1);
+            self.put(kind, 6);
+            self.put(0, 1);
+        }
+
+        fn decode(mut self, bps: u32, len: usize) -> Vec<i64> {
+            self.put(0, (8 - self.n) % 8);
+            let mut data = self.bytes.as_slice();
+            let mut out = vec![0; len];
+            subframe(&mut Bits::new(&mut data), bps, &mut out).unwrap();
+            assert!(data.is_empty());
+            out
+        }
+    }
+
+    #[test]
+    fn fixed_with_escape() {
+        // 二阶固定预测，前半用 Rice 编码，后半用转义的原始残差
+        let x: Vec<i64> = (0..16).zip([0, 5, -9, 2].into_iter().cycle()).map(|(i, d)| 3 * i * i - 50 * i + 7 + d).collect();
+        let mut w = Writer::default();
+        w.header(8 + 2);
+        w.signed(x[0], 16);
+        w.signed(x[1], 16);
+        w.put(0, 2);
+        w.put(1, 4);
+        w.put(3, 4);
+        for i in 2..8 {
+            w.rice(x[i] - 2 * x[i - 1] + x[i - 2], 3);
+        }
+        w.put(15, 4);
+        w.put(7, 5);
+        for i in 8..16 {
+            w.signed(x[i] - 2 * x[i - 1] + x[i - 2], 7);
+        }
+        assert_eq!(w.decode(16, 16), x);
+    }
+
+    #[test]
+    fn lpc() {
+        // 系数 [3, -1]，右移 2 位
+        let residual = [0, 0, 4, -3, 0, 17, -1, 2];
+        let mut x = vec![-1200i64, 913];
+        for &r in &residual[2..] {
+            x.push(r + ((3 * x[x.len() - 1] - x[x.len() - 2]) >> 2));
+        }
+        let mut w = Writer::default();
+        w.header(32 + 1);
+        w.signed(x[0], 12);
+        w.signed(x[1], 12);
+        w.put(4, 4); // 精度 5 位
+        w.signed(2, 5);
+        w.signed(3, 5);
+        w.signed(-1, 5);
+        w.put(1, 2);
+        w.put(0, 4);
+        w.put(2, 5);
+        for &r in &residual[2..] {
+            w.rice(r, 2);
+        }
+        assert_eq!(w.decode(12, 8), x);
+    }
+
+    #[test]
+    fn wasted_bits() {
+        let mut w = Writer::default();
+        w.put(0, 1);
+        w.put(0, 6); // CONSTANT
+        w.put(1, 1);
+        w.put(0b01, 2); // 浪费 2 位
+        w.signed(-3, 14);
+        assert_eq!(w.decode(16, 4), [-12; 4]);
+    }
+
+    /// A mono 16-bit frame of 16 samples, all `value`, with both checksums.
+    fn constant_frame(number: u8, value: i16) -> Vec<u8> {
+        let mut frame = vec![0xff, 0xf8, 0x60, 0x08, number, 15];
+        frame.push(crc8(&frame));
+        frame.push(0); // CONSTANT 子帧
+        frame.extend(value.to_be_bytes());
+        frame.extend(crc16(0, &frame).to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn frame_crc16() {
+        let mut file = b"fLaC\x80\0\0\x22".to_vec();
+        file.extend([0, 16, 0, 16, 0, 0, 0, 0, 0, 0]);
+        // 1000 Hz 单声道 16 位，共 48 个采样
+        file.extend((u64::from(0x3e8u32) << 44 | 0xf << 36 | 0x30).to_be_bytes());
+        file.extend([0; 16]);
+        let mut bad = constant_frame(1, -500);
+        *bad.last_mut().unwrap() ^= 1;
+        file.extend([constant_frame(0, 1000), bad, constant_frame(2, 7)].concat());
+
+        // 校验和不对的帧以静音代替，之后的照常解码
+        let mut decoder = FlacDecoder::new(std::io::Cursor::new(file)).unwrap();
+        let mut buf = [0i16; 64];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 48);
+        assert_eq!(buf[..48], [[1000; 16], [0; 16], [7; 16]].concat());
+    }
+
+    #[test]
+    fn stereo_decorrelation() {
+        let (l, r) = ([100i64, -7, 3, 0], [40i64, -8, -2, -1]);
+        let side: Vec<i64> = l.iter().zip(&r).map(|(a, b)| a - b).collect();
+        let mid: Vec<i64> = l.iter().zip(&r).map(|(a, b)| (a + b) >> 1).collect();
+        for (channels, mut left, mut right) in [(8, l.to_vec(), side.clone()), (9, side.clone(), r.to_vec()), (10, mid, side)] {
+            decorrelate(channels, &mut left, &mut right);
+            assert_eq!((left.as_slice(), right.as_slice()), (&l[..], &r[..]), "channels {channels}");
+        }
+    }
+}
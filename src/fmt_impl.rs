@@ -41,14 +41,31 @@ impl IoFormat for S24_4 {
 }
 
 pub trait Fmt: IoFormat {
+    /// Magnitude of the most negative value of [`Fmt::to_f64`] (1.0 for floats)
+    const FULL_SCALE: f64;
+
     fn to_f64(self) -> f64;
     fn from_f64(f: f64) -> Self;
+
+    /// Value normalized to [-1, 1)
+    #[inline(always)]
+    fn to_norm(self) -> f64 {
+        self.to_f64() / Self::FULL_SCALE
+    }
+
+    /// Inverse of [`Fmt::to_norm`], rounded and clamped for integer formats
+    #[inline(always)]
+    fn from_norm(f: f64) -> Self {
+        Self::from_f64((f * Self::FULL_SCALE).round().clamp(-Self::FULL_SCALE, Self::FULL_SCALE - 1.0))
+    }
 }
 
 macro_rules! impl_simple {
     ($($t:ty),+) => {
         $(
             impl Fmt for $t {
+                const FULL_SCALE: f64 = -(<$t>::MIN as f64);
+
                 #[inline(always)]
                 fn to_f64(self) -> f64 {
                     self.into()
@@ -63,9 +80,51 @@ macro_rules! impl_simple {
     };
 }
 
+/// WAV 8-bit PCM is unsigned, biased by 128.
+impl Fmt for u8 {
+    const FULL_SCALE: f64 = 128.0;
+
+    #[inline(always)]
+    fn to_f64(self) -> f64 {
+        f64::from(self) - 128.0
+    }
+
+    #[inline(always)]
+    fn from_f64(f: f64) -> Self {
+        (f as i8).cast_unsigned() ^ 0x80
+    }
+}
+
+macro_rules! impl_float {
+    ($($t:ty),+) => {
+        $(
+            impl Fmt for $t {
+                const FULL_SCALE: f64 = 1.0;
+
+                #[inline(always)]
+                fn to_f64(self) -> f64 {
+                    self.into()
+                }
+
+                #[inline(always)]
+                fn from_f64(f: f64) -> Self {
+                    f as Self
+                }
+
+                #[inline(always)]
+                fn from_norm(f: f64) -> Self {
+                    f as Self
+                }
+            }
+        )+
+    };
+}
+
 macro_rules! impl_3_bytes {
     ($t:ty, $shift_amt:literal) => {
         impl Fmt for $t {
+            const FULL_SCALE: f64 = (1 << ($shift_amt - 1)) as f64;
+
             #[inline]
             fn to_f64(self) -> f64 {
                 let i32 = i32::from_le_bytes([self.0[0], self.0[1], self.0[2], 0]);
@@ -84,6 +143,8 @@ macro_rules! impl_3_bytes {
 macro_rules! impl_4_bytes {
     ($t:ty, $shift_amt:literal) => {
         impl Fmt for $t {
+            const FULL_SCALE: f64 = (1 << ($shift_amt - 1)) as f64;
+
             #[inline]
             fn to_f64(self) -> f64 {
                 f64::from(self.0 << const { 32 - $shift_amt } >> const { 32 - $shift_amt })
@@ -97,7 +158,8 @@ macro_rules! impl_4_bytes {
     };
 }
 
-impl_simple!(i8, i16, i32, f32, f64);
+impl_simple!(i8, i16, i32);
+impl_float!(f32, f64);
 impl_3_bytes!(S18_3, 18);
 impl_3_bytes!(S20_3, 20);
 impl_3_bytes!(S24_3, 24);
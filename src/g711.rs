@@ -0,0 +1,124 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use hound::WavSpec;
+
+use crate::{
+    decoder::{self, Decoder},
+    fmt_impl::Fmt,
+    util::PlayError,
+    wav::{Header, WAVE_FORMAT_ALAW},
+};
+
+/// Expands one A-law byte (ITU-T G.711).
+const fn alaw(byte: u8) -> i16 {
+    let a = byte ^ 0x55;
+    let exponent = (a >> 4) & 7;
+    let mantissa = (a & 0x0f) as i16;
+    let magnitude = match exponent {
+        0 => (mantissa << 4) + 8,
+        e => ((mantissa << 4) + 0x108) << (e - 1),
+    };
+    // A 律的符号位为 1 表示正数
+    if a & 0x80 == 0 { -magnitude } else { magnitude }
+}
+
+/// Expands one µ-law byte (ITU-T G.711).
+const fn mulaw(byte: u8) -> i16 {
+    let u = !byte;
+    let exponent = (u >> 4) & 7;
+    let mantissa = (u & 0x0f) as i16;
+    let magnitude = ((mantissa << 3) + 0x84) << exponent;
+    if u & 0x80 == 0 { magnitude - 0x84 } else { 0x84 - magnitude }
+}
+
+const fn table(a_law: bool) -> [i16; 256] {
+    let mut ret = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        ret[i] = if a_law { alaw(i as u8) } else { mulaw(i as u8) };
+        i += 1;
+    }
+    ret
+}
+
+const ALAW_TABLE: [i16; 256] = table(true);
+const MULAW_TABLE: [i16; 256] = table(false);
+
+/// Reads A-law or µ-law samples of a WAV file, expanding them to 16 bits.
+pub struct G711Decoder<R> {
+    reader: R,
+    spec: WavSpec,
+    table: &'static [i16; 256],
+    raw: Vec<u8>,
+    begin: u64,
+    frames: u64,
+    channel_mask: u32,
+    pos: u64,
+}
+
+impl<R: Read + Seek> G711Decoder<R> {
+    pub fn new(mut reader: R, header: &Header) -> io::Result<Self> {
+        let spec = header.spec()?;
+        let table = if header.tag == WAVE_FORMAT_ALAW { &ALAW_TABLE } else { &MULAW_TABLE };
+        reader.seek(SeekFrom::Start(header.begin))?;
+        Ok(Self { reader, spec, table, raw: Vec::new(), begin: header.begin, frames: header.frames(), channel_mask: header.channel_mask, pos: 0 })
+    }
+}
+
+impl<R: Read + Seek> Decoder for G711Decoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn channel_mask(&self) -> u32 {
+        self.channel_mask
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let channels = usize::from(self.spec.channels);
+        let buf = decoder::as_bytes(buf, self.spec)?;
+        let n = (buf.len() as u64 / 2 / channels as u64).min(self.frames - self.pos) as usize;
+        // 每个采样一个字节，展开成两个字节
+        self.raw.resize(n * channels, 0);
+        self.reader.read_exact(&mut self.raw)?;
+        for (out, &b) in buf.chunks_exact_mut(2).zip(&self.raw) {
+            out.copy_from_slice(&self.table[usize::from(b)].to_le_bytes());
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        let frame = frame.min(self.frames);
+        self.reader.seek(SeekFrom::Start(self.begin + frame * u64::from(self.spec.channels)))?;
+        self.pos = frame;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!([0xd5, 0x55, 0xaa, 0x2a].map(alaw), [8, -8, 32256, -32256]);
+        assert_eq!([0xff, 0x7f, 0x80, 0x00].map(mulaw), [0, 0, 32124, -32124]);
+    }
+
+    #[test]
+    fn symmetric() {
+        // 符号位翻转时幅度不变
+        for b in 0..=255u8 {
+            assert_eq!(ALAW_TABLE[usize::from(b ^ 0x80)], -ALAW_TABLE[usize::from(b)]);
+            assert_eq!(MULAW_TABLE[usize::from(b ^ 0x80)], -MULAW_TABLE[usize::from(b)]);
+        }
+    }
+}
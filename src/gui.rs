@@ -15,7 +15,10 @@ use lvgl::{
 };
 
 use crate::{
+    markers::Markers,
     mp3::Song,
+    shift::{MAX_SPEED, MIN_SPEED, SPEED_ONE, Speed},
+    tags::Tags,
     util::{GUIEvent, Handle, MP3Event, PlayerEvent},
 };
 
@@ -39,10 +42,16 @@ pub struct GUI {
     window: Display,
     screen: Obj,
     song_labels: Vec<Label>,
-    speeds: Vec<Btn>,
+    speed: Option<Slider>,
+    speed_label: Option<Label>,
     progress: Option<Bar>,
     pl: Option<Label>,
     pn: Option<Label>,
+    now_playing: Vec<Label>, // 标题、艺术家、专辑
+    song_infos: Vec<[CString; 3]>,
+    song_markers: Vec<Markers>,
+    song_seekable: Vec<bool>,
+    seekers: Vec<Btn>, // 快进快退和跳到标记，流不能用
 }
 
 unsafe impl Send for GUI {}
@@ -72,6 +81,37 @@ extern "C" fn set_volume(event: *mut lvgl_sys::lv_event_t) {
     }
 }
 
+extern "C" fn set_crossfade(event: *mut lvgl_sys::lv_event_t) {
+    unsafe {
+        let cb = (*event).target;
+        let enabled = lvgl_sys::lv_obj_has_state(cb, lvgl_sys::LV_STATE_CHECKED);
+        let tx = (*event).user_data as *const Sender<MP3Event>;
+        let _ = (*tx).send(MP3Event::SetCrossfade { enabled });
+    }
+}
+
+/// Speed as shown next to the slider, like `0.85x`.
+fn speed_text(speed: i32) -> CString {
+    CString::new(format!("{}.{:02}x", speed / 100, speed % 100)).unwrap_or_default()
+}
+
+extern "C" fn show_speed(event: *mut lvgl_sys::lv_event_t) {
+    unsafe {
+        let speed = lvgl_sys::lv_bar_get_value((*event).target);
+        let lbl = (*event).user_data.cast::<lvgl_sys::lv_obj_t>();
+        lvgl_sys::lv_label_set_text(lbl, speed_text(speed).as_ptr());
+    }
+}
+
+extern "C" fn set_speed(event: *mut lvgl_sys::lv_event_t) {
+    unsafe {
+        let speed = lvgl_sys::lv_bar_get_value((*event).target);
+        let tx = (*event).user_data as *const Sender<MP3Event>;
+        // 拖动时只更新标签，松手才换倍速
+        let _ = (*tx).send(PlayerEvent::SetSpeed { speed: Speed::try_from(speed).unwrap_or(SPEED_ONE) }.into());
+    }
+}
+
 impl GUI {
     extern "C" fn on_close(_: *mut lvgl_sys::lv_disp_t) -> bool {
         if let Some(tx) = unsafe { &*TX_ONLY_USE_IT_FOR_CLOSE.get() } {
@@ -102,10 +142,16 @@ impl GUI {
             window,
             screen,
             song_labels: Vec::new(),
-            speeds: Vec::new(),
+            speed: None,
+            speed_label: None,
             progress: None,
             pl: None,
             pn: None,
+            now_playing: Vec::new(),
+            song_infos: Vec::new(),
+            song_markers: Vec::new(),
+            song_seekable: Vec::new(),
+            seekers: Vec::new(),
         })
     }
 
@@ -118,6 +164,15 @@ impl GUI {
         lbl.set_align(Align::Center, 0, 0)
     }
 
+    fn button(&self, x: i16, y: i16, width: i16, text: &'static str, event: MP3Event) -> LvResult<Btn> {
+        let mut btn = Btn::new()?;
+        btn.set_pos(x, y)?;
+        btn.set_size(width, 20)?;
+        Self::set_label(&mut btn, text.into())?;
+        btn.on_event(ConstDispatcher(self.tx.clone(), event))?;
+        Ok(btn)
+    }
+
     fn add_entry(list: &mut List, text: &[u8]) -> LvResult<Label> {
         let text = CString::new(text).map_err(|_| LvError::InvalidReference)?;
         let lbl = unsafe { lvgl_sys::lv_list_add_text(list.raw()?.as_ptr(), text.as_ptr()) };
@@ -128,69 +183,72 @@ impl GUI {
         }
     }
 
-    pub fn draw(&mut self, songs: &[Song], initial_volume: i32) -> LvResult<()> {
+    pub fn draw(&mut self, songs: &[Song], initial_volume: i32, crossfade: bool) -> LvResult<()> {
         let mut list = List::new()?;
         list.set_pos(340, 25)?;
         list.set_size(200, 270)?;
         for (i, song) in songs.iter().enumerate() {
             let p = song.get_path();
-            let mut lbl = Self::add_entry(&mut list, p.file_name().unwrap_or(p.as_os_str()).as_encoded_bytes())?;
+            let file_name = p.file_name().unwrap_or(p.as_os_str()).as_encoded_bytes();
+            let tags = song.get_tags();
+            // 文件名是编号，有标签就显示标题
+            let entry = match tags {
+                Tags { title: Some(title), artist: Some(artist), .. } => Cow::Owned(format!("{title} - {artist}").into_bytes()),
+                Tags { title: Some(title), .. } => Cow::Borrowed(title.as_bytes()),
+                _ => Cow::Borrowed(file_name),
+            };
+            let mut lbl = Self::add_entry(&mut list, &entry)?;
             lbl.on_event(ConstDispatcher(self.tx.clone(), MP3Event::SwitchSong { seek: SeekFrom::Start(i as u64) }))?;
             self.song_labels.push(lbl);
+
+            let text = |s: Option<&str>| CString::new(s.unwrap_or_default()).unwrap_or_default();
+            let title = CString::new(tags.title.as_deref().map_or(file_name, str::as_bytes)).unwrap_or_default();
+            self.song_infos.push([title, text(tags.artist.as_deref()), text(tags.album.as_deref())]);
+            self.song_markers.push(song.get_markers().clone());
+            self.song_seekable.push(song.is_seekable());
         }
 
-        let mut last_song = Btn::new()?;
-        last_song.set_pos(25, 245)?;
-        last_song.set_size(40, 20)?;
-        Self::set_label(&mut last_song, "\u{f048}\0" /* "⏮\0" */.into())?;
-        last_song.on_event(ConstDispatcher(self.tx.clone(), MP3Event::SwitchSong { seek: SeekFrom::Current(-1) }))?;
-
-        let mut next_song = Btn::new()?;
-        next_song.set_pos(275, 245)?;
-        next_song.set_size(40, 20)?;
-        Self::set_label(&mut next_song, "\u{f051}\0" /* "⏭\0" */.into())?;
-        next_song.on_event(ConstDispatcher(self.tx.clone(), MP3Event::SwitchSong { seek: SeekFrom::Current(1) }))?;
-
-        let mut fast_rewind = Btn::new()?;
-        fast_rewind.set_pos(75, 245)?;
-        fast_rewind.set_size(40, 20)?;
-        Self::set_label(&mut fast_rewind, "\u{f053}\0" /* "⏪\0" */.into())?;
-        fast_rewind.on_event(ConstDispatcher(self.tx.clone(), PlayerEvent::Move { offset: -5 }.into()))?;
-
-        let mut fast_forward = Btn::new()?;
-        fast_forward.set_pos(225, 245)?;
-        fast_forward.set_size(40, 20)?;
-        Self::set_label(&mut fast_forward, "\u{f054}\0" /* "⏩\0" */.into())?;
-        fast_forward.on_event(ConstDispatcher(self.tx.clone(), PlayerEvent::Move { offset: 5 }.into()))?;
-
-        let mut pause = Btn::new()?;
-        pause.set_pos(175, 245)?;
-        pause.set_size(40, 20)?;
-        Self::set_label(&mut pause, "\u{f04c}\0" /* "⏸\0" */.into())?;
-        pause.on_event(ConstDispatcher(self.tx.clone(), PlayerEvent::Pause.into()))?;
-
-        let mut resume = Btn::new()?;
-        resume.set_pos(125, 245)?;
-        resume.set_size(40, 20)?;
-        Self::set_label(&mut resume, "\u{f04b}\0" /* "▶\0" */.into())?;
-        resume.on_event(ConstDispatcher(self.tx.clone(), PlayerEvent::Resume.into()))?;
-
-        for multiplier in 1..=4 {
-            let mut speed = Btn::new()?;
-            speed.set_pos(i16::from(multiplier) * 75 - 50, 275)?;
-            speed.set_size(65, 20)?;
-            Self::set_label(&mut speed, format!("{}x", f32::from(multiplier) * 0.5).into())?;
-            speed.on_event(ConstDispatcher(self.tx.clone(), PlayerEvent::SetMultiplier { multiplier }.into()))?;
-            if let Ok(r) = speed.raw() && multiplier == 2 {
-                Self::highlight(r);
-            }
-            self.speeds.push(speed);
+        for y in [35, 60, 85] {
+            let mut lbl = Label::new()?;
+            lbl.set_pos(25, y)?;
+            lbl.set_size(255, 20)?;
+            lbl.set_text_static(Default::default())?;
+            unsafe { lvgl_sys::lv_label_set_long_mode(lbl.raw()?.as_ptr(), lvgl_sys::lv_label_long_mode_t_LV_LABEL_LONG_DOT); }
+            self.now_playing.push(lbl);
         }
 
+        self.seekers = vec![
+            self.button(25, 130, 70, "\u{f053} Cue\0", PlayerEvent::Jump { offset: -1 }.into())?,
+            self.button(105, 130, 70, "Cue \u{f054}\0", PlayerEvent::Jump { offset: 1 }.into())?,
+            self.button(75, 245, 40, "\u{f053}\0" /* "⏪\0" */, PlayerEvent::Move { offset: -5 }.into())?,
+            self.button(225, 245, 40, "\u{f054}\0" /* "⏩\0" */, PlayerEvent::Move { offset: 5 }.into())?,
+        ];
+
+        self.button(25, 245, 40, "\u{f048}\0" /* "⏮\0" */, MP3Event::SwitchSong { seek: SeekFrom::Current(-1) })?;
+        self.button(275, 245, 40, "\u{f051}\0" /* "⏭\0" */, MP3Event::SwitchSong { seek: SeekFrom::Current(1) })?;
+        self.button(175, 245, 40, "\u{f04c}\0" /* "⏸\0" */, PlayerEvent::Pause.into())?;
+        self.button(125, 245, 40, "\u{f04b}\0" /* "▶\0" */, PlayerEvent::Resume.into())?;
+
+        let mut speed_label = Label::new()?;
+        speed_label.set_pos(25, 277)?;
+        speed_label.set_text(&speed_text(SPEED_ONE.into()))?;
+
+        let mut speed = Slider::new()?;
+        speed.set_pos(85, 282)?;
+        speed.set_size(225, 10)?;
+        let leaked_tx = Box::into_raw(Box::new(self.tx.clone()));
+        unsafe {
+            lvgl_sys::lv_bar_set_range(speed.raw()?.as_ptr(), MIN_SPEED.into(), MAX_SPEED.into());
+            lvgl_sys::lv_bar_set_value(speed.raw()?.as_ptr(), SPEED_ONE.into(), 0);
+            lvgl_sys::lv_obj_add_event_cb(speed.raw()?.as_ptr(), Some(show_speed), lvgl_sys::lv_event_code_t_LV_EVENT_VALUE_CHANGED, speed_label.raw()?.as_ptr().cast());
+            lvgl_sys::lv_obj_add_event_cb(speed.raw()?.as_ptr(), Some(set_speed), lvgl_sys::lv_event_code_t_LV_EVENT_RELEASED, leaked_tx.cast());
+        }
+        self.speed = Some(speed);
+        self.speed_label = Some(speed_label);
+
         let mut vol = Slider::new()?;
         vol.set_pos(290, 25)?;
         vol.set_size(15, 150)?;
-        let leaked_tx = Box::into_raw(Box::new(self.tx.clone()));
         unsafe {
             lvgl_sys::lv_bar_set_range(vol.raw()?.as_ptr(), 0, 512);
             lvgl_sys::lv_bar_set_value(vol.raw()?.as_ptr(), initial_volume, 0);
@@ -198,6 +256,16 @@ impl GUI {
             lvgl_sys::lv_obj_add_event_cb(vol.raw()?.as_ptr(), Some(set_volume), lvgl_sys::lv_event_code_t_LV_EVENT_VALUE_CHANGED, leaked_tx.cast());
         }
 
+        unsafe {
+            let cb = lvgl_sys::lv_checkbox_create(self.screen.raw()?.as_ptr());
+            lvgl_sys::lv_checkbox_set_text(cb, c"Crossfade".as_ptr());
+            lvgl_sys::lv_obj_set_pos(cb, 25, 165);
+            if crossfade {
+                lvgl_sys::lv_obj_add_state(cb, lvgl_sys::LV_STATE_CHECKED);
+            }
+            lvgl_sys::lv_obj_add_event_cb(cb, Some(set_crossfade), lvgl_sys::lv_event_code_t_LV_EVENT_VALUE_CHANGED, leaked_tx.cast());
+        }
+
         let mut progress = Bar::new()?;
         progress.set_pos(25, 195)?;
         progress.set_size(290, 15)?;
@@ -217,6 +285,31 @@ impl GUI {
         Ok(())
     }
 
+    /// Marks the cue points and the loop on the progress bar, replacing the old marks.
+    fn draw_marks(bar: NonNull<lvgl_sys::lv_obj_t>, markers: &Markers, begin: u64, duration: u64) {
+        const CUE: lvgl_sys::lv_palette_t = 13; // LV_PALETTE_AMBER
+        const LOOP: lvgl_sys::lv_palette_t = 8; // LV_PALETTE_TEAL
+        const WIDTH: u64 = 288; // 进度条宽度减去刻度宽度
+
+        unsafe { lvgl_sys::lv_obj_clean(bar.as_ptr()); }
+        if duration == 0 {
+            return;
+        }
+        let cues = markers.cues.iter().map(|c| (c.frame, CUE));
+        let sustain = markers.sustain.into_iter().flat_map(|l| [(l.start, LOOP), (l.end, LOOP)]);
+        for (frame, color) in cues.chain(sustain) {
+            let x = i16::try_from(frame.saturating_sub(begin).min(duration) * WIDTH / duration).unwrap_or_default();
+            unsafe {
+                let tick = lvgl_sys::lv_obj_create(bar.as_ptr());
+                lvgl_sys::lv_obj_remove_style(tick, core::ptr::null_mut(), lvgl_sys::LV_PART_ANY | lvgl_sys::LV_STATE_ANY);
+                lvgl_sys::lv_obj_set_size(tick, 2, 15);
+                lvgl_sys::lv_obj_set_pos(tick, x, 0);
+                lvgl_sys::lv_obj_set_style_bg_color(tick, lvgl_sys::lv_palette_main(color), 0);
+                lvgl_sys::lv_obj_set_style_bg_opa(tick, 255, 0);
+            }
+        }
+    }
+
     fn highlight(obj: NonNull<lvgl_sys::lv_obj_t>) {
         unsafe {
             lvgl_sys::lv_obj_set_style_bg_color(obj.as_ptr(), lvgl_sys::lv_palette_main(1), 0);
@@ -248,23 +341,52 @@ impl GUI {
                         if let Some(l) = self.song_labels.get(index) && let Ok(l) = l.raw() {
                             Self::highlight(l);
                         }
+                        if let Some(info) = self.song_infos.get(index) {
+                            for (lbl, text) in self.now_playing.iter_mut().zip(info) {
+                                let _ = lbl.set_text(text);
+                            }
+                        }
+                        if let Some(progress) = &self.progress && let Ok(progress) = progress.raw() {
+                            unsafe { lvgl_sys::lv_obj_clean(progress.as_ptr()); }
+                        }
+                        let seekable = self.song_seekable.get(index).copied().unwrap_or(true);
+                        for b in &self.seekers {
+                            if let Ok(b) = b.raw() {
+                                if seekable {
+                                    unsafe { lvgl_sys::lv_obj_clear_state(b.as_ptr(), lvgl_sys::LV_STATE_DISABLED); }
+                                } else {
+                                    unsafe { lvgl_sys::lv_obj_add_state(b.as_ptr(), lvgl_sys::LV_STATE_DISABLED); }
+                                }
+                            }
+                        }
                         last_index = index;
                         cur_handle = handle;
                         pa = None;
                     }
                     GUIEvent::ProgressAccess { access, handle } =>
                         if cur_handle == handle {
+                            // 时长可能变了 (文件被截断)，重画标记
+                            if let Some(access) = access
+                                && let Some(progress) = &self.progress && let Ok(progress) = progress.raw()
+                                && let Some(markers) = self.song_markers.get(last_index)
+                            {
+                                Self::draw_marks(progress, markers, access.begin, access.duration);
+                            }
                             pa = access;
                         }
-                    GUIEvent::SetMultiplier { multiplier } =>
-                        for (i, b) in self.speeds.iter().enumerate() {
-                            if let Ok(b) = b.raw() {
-                                if i + 1 == usize::from(multiplier) {
-                                    Self::highlight(b);
-                                } else {
-                                    Self::de_highlight(b);
-                                }
-                            }
+                    GUIEvent::SetSpeed { speed } => {
+                        if let Some(slider) = &self.speed && let Ok(slider) = slider.raw() {
+                            unsafe { lvgl_sys::lv_bar_set_value(slider.as_ptr(), speed.into(), 0); }
+                        }
+                        if let Some(lbl) = &mut self.speed_label {
+                            let _ = lbl.set_text(&speed_text(speed.into()));
+                        }
+                    }
+                    GUIEvent::StreamTitle { index, title } =>
+                        // 标题行换成正在播的，电台名挪到专辑行
+                        if index == last_index && let Some(info) = self.song_infos.get(index) && let [first, _, third] = &mut self.now_playing[..] {
+                            let _ = first.set_text(&CString::new(title).unwrap_or_default());
+                            let _ = third.set_text(&info[0]);
                         }
                 }
             }
@@ -0,0 +1,479 @@
+use core::{fmt::Write as _, time::Duration};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::tags::{self, Tags};
+
+const BAD_URL: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "only http://host[:port]/path URLs are supported");
+const BAD_RESPONSE: io::Error = io::const_error!(io::ErrorKind::InvalidData, "malformed HTTP response");
+const TOO_MANY_REDIRECTS: io::Error = io::const_error!(io::ErrorKind::InvalidData, "too many HTTP redirects");
+const NO_LENGTH: io::Error = io::const_error!(io::ErrorKind::InvalidData, "HTTP response without Content-Length");
+const CUT_SHORT: io::Error = io::const_error!(io::ErrorKind::UnexpectedEof, "HTTP connection closed before the end of the file");
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 5;
+/// Seeking forward by less than this reads on instead of making a new request.
+const MAX_SKIP: u64 = 64 << 10;
+
+/// Whether `path` is an `http://` URL rather than a local path.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|s| s.get(..7).is_some_and(|p| p.eq_ignore_ascii_case("http://")))
+}
+
+#[derive(Clone, Debug)]
+struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(s: &str) -> io::Result<Self> {
+        let rest = s.get(..7).filter(|p| p.eq_ignore_ascii_case("http://")).and_then(|_| s.get(7..)).ok_or(BAD_URL)?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse().map_err(|_| BAD_URL)?), // 不是 IPv6 地址里的冒号
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(BAD_URL);
+        }
+        Ok(Self { host: host.to_owned(), port, path: path.to_owned() })
+    }
+
+    /// Resolves the `Location` of a redirect (or an entry of a playlist) against this URL.
+    fn join(&self, location: &str) -> io::Result<Self> {
+        if location.get(..7).is_some_and(|p| p.eq_ignore_ascii_case("http://")) {
+            Self::parse(location)
+        } else if location.starts_with('/') {
+            Ok(Self { path: location.to_owned(), ..self.clone() })
+        } else {
+            let dir = &self.path[..self.path.rfind('/').map_or(0, |i| i + 1)];
+            Ok(Self { path: format!("{dir}{location}"), ..self.clone() })
+        }
+    }
+}
+
+/// The status line and headers we care about, and the body after them.
+struct Response {
+    length: Option<u64>,
+    ranges: bool,
+    metaint: Option<usize>, // ICY 元数据的间隔
+    name: Option<String>,
+    content_type: Option<String>, // 不带参数，小写
+    partial: bool, // 206，按 Range 从中间开始
+    body: BufReader<TcpStream>,
+}
+
+impl Response {
+    /// Whether this is a live stream rather than a file.
+    #[inline]
+    const fn is_live(&self) -> bool {
+        self.length.is_none() || self.metaint.is_some()
+    }
+}
+
+/// Sends a `GET` (following redirects), starting from byte `from` of the body.
+fn get(mut url: Url, from: u64, icy: bool) -> io::Result<Response> {
+    for _ in 0..MAX_REDIRECTS {
+        let addr = (url.host.trim_start_matches('[').trim_end_matches(']'), url.port).to_socket_addrs()?.next().ok_or(BAD_URL)?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        // HTTP/1.0 不会有分块传输
+        let mut request = format!("GET {} HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: player/{}\r\n", url.path, url.host, url.port, env!("CARGO_PKG_VERSION"));
+        if from != 0 {
+            let _ = write!(request, "Range: bytes={from}-\r\n");
+        }
+        if icy {
+            request += "Icy-MetaData: 1\r\n";
+        }
+        request += "\r\n";
+        stream.write_all(request.as_bytes())?;
+
+        let mut body = BufReader::new(stream);
+        let mut line = Vec::new();
+        body.read_until(b'\n', &mut line)?;
+        // Icecast 以外的 SHOUTcast 服务器回的是 "ICY 200 OK"
+        let status: u16 = String::from_utf8_lossy(&line).split_whitespace().nth(1).and_then(|s| s.parse().ok()).ok_or(BAD_RESPONSE)?;
+        let mut response = Response { length: None, ranges: false, metaint: None, name: None, content_type: None, partial: status == 206, body };
+        let mut location = None;
+        loop {
+            line.clear();
+            if response.body.read_until(b'\n', &mut line)? == 0 {
+                return Err(BAD_RESPONSE);
+            }
+            let line = String::from_utf8_lossy(&line);
+            let Some((name, value)) = line.split_once(':') else { break }; // 空行
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => response.length = value.parse().ok(),
+                "accept-ranges" => response.ranges = value.eq_ignore_ascii_case("bytes"),
+                "icy-metaint" => response.metaint = value.parse().ok().filter(|&n| n != 0),
+                "icy-name" => response.name = tags::text(value.as_bytes()),
+                "content-type" => response.content_type = value.split(';').next().map(|t| t.trim().to_ascii_lowercase()),
+                "location" => location = Some(value.to_owned()),
+                _ => (),
+            }
+        }
+        match status {
+            200 | 206 => return Ok(response),
+            301 | 302 | 303 | 307 | 308 => {
+                url = url.join(&location.ok_or(BAD_RESPONSE)?)?;
+                tracing::info!("Redirected to \x1b[32mhttp://{}:{}{}\x1b[0m.", url.host, url.port, url.path);
+            }
+            _ => return Err(io::Error::other(format!("HTTP status {status}"))),
+        }
+    }
+    Err(TOO_MANY_REDIRECTS)
+}
+
+/// Fetches a whole (small) file, such as a playlist.
+pub fn fetch(url: &str) -> io::Result<Vec<u8>> {
+    let mut b = Vec::new();
+    get(Url::parse(url)?, 0, false)?.body.read_to_end(&mut b)?;
+    Ok(b)
+}
+
+/// Resolves an entry of the playlist at `base` into a URL.
+pub fn join(base: &str, location: &str) -> io::Result<String> {
+    let url = Url::parse(base)?.join(location)?;
+    Ok(format!("http://{}:{}{}", url.host, url.port, url.path))
+}
+
+/// A file on an HTTP server, read with `Range` requests from wherever it seeks to,
+/// and prefetched from there on like a live stream.
+///
+/// Servers ignoring `Range` still work, the bytes before are read and thrown away.
+pub struct HttpFile {
+    url: Url,
+    len: u64,
+    pos: u64,
+    body: Option<Prefetch>, // 正读到 pos 的连接，定位后重新请求
+}
+
+/// What an `http://` URL turned out to be from the first response.
+pub enum Remote {
+    File(HttpFile),
+    /// A live stream with its tags (the `icy-name`), connected to again when played
+    Live(Tags),
+}
+
+/// Requests `url`, telling a file from a live stream by the response.
+pub fn open(url: &str) -> io::Result<Remote> {
+    let url = Url::parse(url)?;
+    let response = get(url.clone(), 0, true)?;
+    if response.is_live() {
+        return Ok(Remote::Live(Tags { title: response.name, ..Tags::default() }));
+    }
+    let len = response.length.ok_or(NO_LENGTH)?;
+    if !response.ranges {
+        tracing::warn!("\x1b[33mhttp://{}:{}{}\x1b[0m: server does not accept ranges, seeking will be slow.", url.host, url.port, url.path);
+    }
+    Ok(Remote::File(HttpFile { url, len, pos: 0, body: Some(Prefetch::new(response.body, 0)) }))
+}
+
+impl HttpFile {
+    /// Length in bytes, the `Content-Length`.
+    #[inline]
+    pub const fn size(&self) -> u64 {
+        self.len
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        let mut response = get(self.url.clone(), self.pos, false)?;
+        if !response.partial && self.pos != 0 {
+            let skipped = io::copy(&mut response.body.by_ref().take(self.pos), &mut io::sink())?;
+            if skipped != self.pos {
+                return Err(CUT_SHORT);
+            }
+        }
+        // 文件不用等缓冲，有多少读多少
+        self.body = Some(Prefetch::new(response.body, 0));
+        Ok(())
+    }
+}
+
+impl Read for HttpFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        if left == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = usize::try_from(left).unwrap_or(usize::MAX).min(buf.len());
+        let mut retried = false;
+        loop {
+            if self.body.is_none() {
+                self.reconnect()?;
+            }
+            let body = self.body.as_mut().ok_or(CUT_SHORT)?;
+            let err = match body.read(&mut buf[..len]) {
+                Ok(0) => CUT_SHORT,
+                Ok(n) => {
+                    self.pos += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return Err(e),
+                Err(e) => e,
+            };
+            // 连接断了 (超时、被重置) 就从断开处再请求一次
+            self.body = None;
+            if retried {
+                return Err(err);
+            }
+            tracing::warn!("\x1b[33mhttp://{}:{}{}\x1b[0m: {err}, resuming from byte {}.", self.url.host, self.url.port, self.url.path, self.pos);
+            retried = true;
+        }
+    }
+}
+
+impl Seek for HttpFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        // 往前一点就接着读，省一次请求
+        if let Some(body) = &mut self.body
+            && target > self.pos
+            && target - self.pos <= MAX_SKIP
+            && io::copy(&mut body.by_ref().take(target - self.pos), &mut io::sink())? == target - self.pos
+        {
+            self.pos = target;
+        } else if target != self.pos {
+            self.body = None;
+            self.pos = target;
+        }
+        Ok(target)
+    }
+}
+
+/// Called with each new `StreamTitle` of a live stream.
+pub type OnTitle = Box<dyn FnMut(String) + Send>;
+
+/// Strips the ICY metadata sent every `metaint` bytes of a live stream, passing on the titles in it.
+struct Icy<R> {
+    inner: R,
+    metaint: usize,
+    left: usize, // 到下一块元数据前的字节数
+    title: String,
+    on_title: OnTitle,
+}
+
+impl<R: Read> Icy<R> {
+    fn metadata(&mut self) -> io::Result<()> {
+        let mut len = [0];
+        self.inner.read_exact(&mut len)?;
+        let mut b = vec![0; usize::from(len[0]) * 16];
+        self.inner.read_exact(&mut b)?;
+        // StreamTitle='歌名';StreamUrl='';
+        let title = b
+            .windows(13)
+            .position(|w| w == b"StreamTitle='")
+            .map(|i| &b[i + 13..])
+            .and_then(|rest| tags::text(&rest[..rest.windows(2).position(|w| w == b"';").unwrap_or(rest.len())]));
+        if let Some(title) = title && title != self.title {
+            tracing::info!("Stream title: \x1b[36m{title}\x1b[0m");
+            (self.on_title)(title.clone());
+            self.title = title;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Icy<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 {
+            self.metadata()?;
+            self.left = self.metaint;
+        }
+        let len = buf.len().min(self.left);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.left -= n;
+        Ok(n)
+    }
+}
+
+struct Queue {
+    bytes: VecDeque<u8>,
+    end: Option<io::Result<()>>, // 输入结束或出错
+    closed: bool, // 读的一方不要了
+}
+
+/// Reads a live stream (or a file from where it was sought to) on a thread of its own,
+/// so that the network can run ahead of playback.
+///
+/// Reading waits until `prebuffer` bytes have arrived, at the start and whenever the queue runs dry.
+pub struct Prefetch {
+    shared: Arc<(Mutex<Queue>, Condvar)>,
+    prebuffer: usize,
+    buffering: bool,
+}
+
+impl Prefetch {
+    fn new<R: Read + Send + 'static>(mut inner: R, prebuffer: usize) -> Self {
+        let shared = Arc::new((Mutex::new(Queue { bytes: VecDeque::new(), end: None, closed: false }), Condvar::new()));
+        let capacity = (prebuffer * 4).max(256 << 10);
+        let queue = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            let (mutex, cvar) = &*queue;
+            let lock = || mutex.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut chunk = vec![0; 16 << 10];
+            let end = loop {
+                let n = match inner.read(&mut chunk) {
+                    Ok(0) => break Ok(()),
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => break Err(e),
+                };
+                let mut q = cvar.wait_while(lock(), |q| !q.closed && q.bytes.len() >= capacity).unwrap_or_else(PoisonError::into_inner);
+                if q.closed {
+                    return;
+                }
+                q.bytes.extend(&chunk[..n]);
+                drop(q);
+                cvar.notify_all();
+            };
+            lock().end = Some(end);
+            cvar.notify_all();
+        });
+        Self { shared, prebuffer, buffering: true }
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.shared.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        self.lock().closed = true;
+        self.shared.1.notify_all();
+    }
+}
+
+impl Read for Prefetch {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (mutex, cvar) = &*self.shared;
+        let mut q = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+        if q.bytes.is_empty() && q.end.is_none() && !self.buffering && self.prebuffer != 0 {
+            tracing::warn!("Network stream ran dry, buffering \x1b[36m{}\x1b[0m bytes again.", self.prebuffer);
+            self.buffering = true;
+        }
+        let want = if self.buffering { self.prebuffer.max(1) } else { 1 };
+        q = cvar.wait_while(q, |q| q.bytes.len() < want && q.end.is_none()).unwrap_or_else(PoisonError::into_inner);
+        self.buffering = false;
+        if q.bytes.is_empty() {
+            // 错误只报一次，之后都是结尾
+            return match q.end.replace(Ok(())) {
+                Some(Err(e)) => Err(e),
+                _ => Ok(0),
+            };
+        }
+        let n = q.bytes.len().min(buf.len());
+        for (d, s) in buf.iter_mut().zip(q.bytes.drain(..n)) {
+            *d = s;
+        }
+        drop(q);
+        cvar.notify_all();
+        Ok(n)
+    }
+}
+
+/// Connects to the live stream at `url`, prefetching `prebuffer` bytes of it; returns it with its `Content-Type`.
+pub fn open_live(url: &str, prebuffer: usize, on_title: OnTitle) -> io::Result<(Prefetch, Option<String>)> {
+    let response = get(Url::parse(url)?, 0, true)?;
+    tracing::info!("Connected to \x1b[32m{url}\x1b[0m ({}, {}), metadata every {:?} bytes.", response.name.as_deref().unwrap_or("unnamed"), response.content_type.as_deref().unwrap_or("no type"), response.metaint);
+    let prefetch = match response.metaint {
+        Some(metaint) => Prefetch::new(Icy { inner: response.body, metaint, left: metaint, title: String::new(), on_title }, prebuffer),
+        None => Prefetch::new(response.body, prebuffer),
+    };
+    Ok((prefetch, response.content_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn url_join() {
+        let url = Url::parse("http://[::1]:8000/radio/list.m3u").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("[::1]", 8000, "/radio/list.m3u"));
+        assert_eq!(url.join("a.mp3").unwrap().path, "/radio/a.mp3");
+        assert_eq!(url.join("/b.mp3").unwrap().path, "/b.mp3");
+        let other = url.join("HTTP://example.com/c").unwrap();
+        assert_eq!((other.host.as_str(), other.port), ("example.com", 80));
+        assert!(Url::parse("https://example.com/").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+    }
+
+    /// Serves `data` honouring `Range`, closing the first connection after `cut` bytes.
+    fn serve(data: &'static [u8], cut: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut reader = BufReader::new(&stream);
+                while !request.ends_with(b"\r\n\r\n") {
+                    reader.read_until(b'\n', &mut request).unwrap();
+                }
+                let request = String::from_utf8(request).unwrap();
+                let from = request.split_once("bytes=").map_or(0, |(_, r)| r[..r.find('-').unwrap()].parse().unwrap());
+                let status = if from == 0 { "200 OK" } else { "206 Partial Content" };
+                let head = format!("HTTP/1.0 {status}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n\r\n", data.len() - from);
+                stream.write_all(head.as_bytes()).unwrap();
+                let end = if i == 0 { cut } else { data.len() };
+                let _ = stream.write_all(&data[from..end]);
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn resume_after_cut() {
+        static DATA: [u8; 5000] = {
+            let mut data = [0; 5000];
+            let mut i = 0;
+            while i < data.len() {
+                data[i] = (i * 7 % 251) as u8;
+                i += 1;
+            }
+            data
+        };
+        let Remote::File(mut file) = open(&serve(&DATA, 1234)).unwrap() else { panic!("not a file") };
+        assert_eq!(file.size(), 5000);
+        let mut got = Vec::new();
+        file.read_to_end(&mut got).unwrap();
+        assert_eq!(got, DATA);
+
+        file.seek(SeekFrom::Start(4000)).unwrap();
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, DATA[4000..]);
+    }
+
+    #[test]
+    fn live_from_first_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/radio", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            BufReader::new(&stream).read_until(b'\n', &mut request).unwrap();
+            stream.write_all(b"ICY 200 OK\r\nicy-name: Test Radio\r\nicy-metaint: 8192\r\n\r\n").unwrap();
+        });
+        let Remote::Live(tags) = open(&url).unwrap() else { panic!("not live") };
+        assert_eq!(tags.title.as_deref(), Some("Test Radio"));
+    }
+}
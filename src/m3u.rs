@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use crate::tags;
+
+/// One entry of an M3U playlist, titled by the `#EXTINF` line before it.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub location: String,
+    pub title: Option<String>,
+}
+
+/// Whether `path` is named like an M3U playlist.
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("m3u") || e.eq_ignore_ascii_case("m3u8"))
+}
+
+/// Parses an M3U (or extended M3U) playlist; other `#` lines are ignored.
+pub fn parse(b: &[u8]) -> Vec<Entry> {
+    let Some(text) = tags::text(b) else { return Vec::new() };
+    let mut entries = Vec::new();
+    let mut title = None;
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:时长,标题
+            title = info.split_once(',').and_then(|(_, t)| tags::text(t.as_bytes()));
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(Entry { location: line.to_owned(), title: title.take() });
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_playlist() {
+        // 标题只给紧跟着的那一项
+        let b = "\u{feff}#EXTM3U\r\n#EXTINF:-1,Radio One\r\nhttp://radio.example/live\r\n\r\n# comment\r\nlocal/song.wav\r\n#EXTINF:123\r\n/abs/other.flac\r\n";
+        let entries: Vec<_> = parse(b.as_bytes()).into_iter().map(|e| (e.location, e.title)).collect();
+        assert_eq!(entries, [
+            ("http://radio.example/live".to_owned(), Some("Radio One".to_owned())),
+            ("local/song.wav".to_owned(), None),
+            ("/abs/other.flac".to_owned(), None),
+        ]);
+        assert!(is_playlist(Path::new("list.M3U8")));
+        assert!(!is_playlist(Path::new("song.mp3")));
+    }
+}
@@ -14,19 +14,44 @@
     unboxed_closures,
 )]
 
+mod adpcm;
+mod aiff;
+mod caf;
+mod convert;
+mod cue;
+mod decoder;
+mod flac;
 mod fmt_impl;
+mod g711;
 mod gui;
+mod http;
 mod log;
+mod m3u;
+mod markers;
 mod mp3;
+mod mpeg;
+mod ogg;
+mod pcm;
+mod raw;
+mod resample;
 mod shift;
+mod sink;
+mod stream;
+mod tags;
 mod util;
 mod wav;
 
 #[derive(clap::Parser)]
 #[command(version)]
 struct Args {
-    #[arg(help = "Music list directory name")]
-    dir: std::path::PathBuf,
+    #[arg(required_unless_present = "list_devices", help = "Music list directory name, an http:// URL of a song or an M3U playlist, or - / a FIFO to play a stream")]
+    dir: Option<std::path::PathBuf>,
+    #[arg(long, env = "PLAYER_LENIENT", help = "Also load WAV files with unknown trailing chunks or a truncated data chunk")]
+    lenient: bool,
+    #[arg(long, env = "PLAYER_RAW", help = "Format of .pcm/.raw files without a .fmt sidecar and of raw streams, as sample format,channels,rate (e.g. s16le,2,48000)")]
+    raw: Option<raw::RawFormat>,
+    #[arg(long, env = "PLAYER_PREBUFFER", default_value_t = 64, help = "KiB of a live network stream to buffer before playing and after it runs dry")]
+    prebuffer: usize,
     #[arg(
         long,
         short,
@@ -35,6 +60,39 @@ struct Args {
         help = "Volume level (0-4)",
     )]
     volume: u8,
+    #[arg(long, value_enum, default_value_t, help = "Audio output backend")]
+    sink: sink::SinkKind,
+    #[arg(
+        long,
+        short,
+        required_if_eq("sink", "wav"),
+        help = "Directory to record the played songs into (with --sink wav)",
+    )]
+    output: Option<std::path::PathBuf>,
+    #[arg(long, env = "PLAYER_DEVICE", default_value = "default", help = "ALSA PCM device name")]
+    device: String,
+    #[arg(long, env = "PLAYER_PERIOD_TIME", help = "ALSA period time in microseconds (driver default if unset)")]
+    period_time: Option<u32>,
+    #[arg(long, env = "PLAYER_BUFFER_TIME", help = "ALSA buffer time in microseconds (driver default if unset)")]
+    buffer_time: Option<u32>,
+    #[arg(long, env = "PLAYER_RATE", help = "Output sample rate in Hz, resampling every song to it (source rate if unset)")]
+    rate: Option<u32>,
+    #[arg(long, env = "PLAYER_RESAMPLE_QUALITY", value_enum, default_value_t, help = "Sample-rate converter quality")]
+    resample_quality: resample::Quality,
+    #[arg(long, env = "PLAYER_CROSSFADE", default_value_t = 0.0, help = "Crossfade between songs in seconds (0 to disable, can be toggled in the GUI)")]
+    crossfade: f32,
+    #[arg(long, env = "PLAYER_SKIP_FADE", default_value_t = 0, help = "Fade length in milliseconds when skipping songs with ⏮/⏭ (0 for a hard cut)")]
+    skip_fade: u32,
+    #[arg(long, env = "PLAYER_RAMP", default_value_t = 10, help = "Fade length in milliseconds on pause, resume, seek and stop (0 for a hard cut)")]
+    ramp: u32,
+    #[arg(long, env = "PLAYER_MIXER", default_value = "default", help = "ALSA mixer card name")]
+    mixer: String,
+    #[arg(long, env = "PLAYER_SELEM", default_value = "Master", help = "ALSA mixer control used for volume")]
+    selem: String,
+    #[arg(long, env = "PLAYER_SELEM_INDEX", default_value_t = 0, help = "Index of the ALSA mixer control")]
+    selem_index: u32,
+    #[arg(long, help = "List ALSA cards, PCM devices and playback mixer controls, then exit")]
+    list_devices: bool,
 }
 
 fn main() -> std::io::Result<()> {
@@ -45,7 +103,26 @@ fn main() -> std::io::Result<()> {
     env_logger::builder().format(log::format).init();
     let args = Args::parse();
 
-    let mut mp3 = MP3::load(args.dir)?;
+    if args.list_devices {
+        return sink::list_devices().map_err(std::io::Error::other);
+    }
+
+    let mut sink = sink::SinkConfig::new(
+        args.sink,
+        args.device,
+        args.output,
+        args.period_time,
+        args.buffer_time,
+        args.rate,
+        args.resample_quality,
+    );
+    sink.crossfade = core::time::Duration::try_from_secs_f32(args.crossfade).map_or(0, |d| d.as_millis() as u32);
+    sink.skip_fade = args.skip_fade;
+    sink.ramp = args.ramp;
+    let crossfade = sink.crossfade != 0;
+    let mixer = mp3::MixerConfig { card: args.mixer, selem: args.selem, index: args.selem_index };
+    let options = mp3::LoadOptions { lenient: args.lenient, raw: args.raw, prebuffer: args.prebuffer << 10 };
+    let mut mp3 = MP3::load(args.dir.unwrap_or_default(), options, sink, mixer)?;
     mp3.set_volume(i32::from(args.volume) * 128).map_err(std::io::Error::other)?;
     let mtx = mp3.mtx.clone();
 
@@ -53,7 +130,7 @@ fn main() -> std::io::Result<()> {
 
     let mut gui = GUI::new(mtx).map_err(gui::cvt_lvgl_err)?;
     tracing::info!("GUI initialized.");
-    gui.draw(mp3.get_songs(), i32::from(args.volume) * 128).map_err(gui::cvt_lvgl_err)?;
+    gui.draw(mp3.get_songs(), i32::from(args.volume) * 128, crossfade).map_err(gui::cvt_lvgl_err)?;
     tracing::info!("GUI drawing finished.");
 
     let (gtx, grx) = std::sync::mpsc::channel();
@@ -0,0 +1,153 @@
+use core::{cmp::Ordering, ops::Range};
+
+use crate::tags;
+
+/// A cue point, with its label from the `LIST/adtl` chunk if any.
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub frame: u64,
+    pub label: Option<String>,
+}
+
+/// A forward loop of a `smpl` chunk.
+#[derive(Clone, Copy, Debug)]
+pub struct Loop {
+    pub start: u64,
+    pub end: u64, // 不含，smpl 里记的是最后一帧
+    pub count: u32, // 总共放几遍，0 表示一直循环
+}
+
+/// Cue points and the sustain loop of a song.
+#[derive(Clone, Debug, Default)]
+pub struct Markers {
+    pub cues: Vec<Cue>, // 按位置排好序
+    pub sustain: Option<Loop>,
+}
+
+impl Markers {
+    /// Sorts the cue points and drops what lies outside of `region`.
+    #[must_use]
+    pub fn fit(mut self, region: Range<u64>) -> Self {
+        self.cues.retain(|c| region.contains(&c.frame));
+        self.cues.sort_by_key(|c| c.frame);
+        self.cues.dedup_by_key(|c| c.frame);
+        self.sustain = self.sustain.filter(|l| region.start <= l.start && l.start < l.end && l.end <= region.end);
+        self
+    }
+
+    /// The `offset`-th cue point after `pos`, or before it if negative; going back past the first one gives 0.
+    ///
+    /// Going back from less than `grace` frames after a cue point skips it.
+    pub fn jump(&self, pos: u64, offset: isize, grace: u64) -> Option<&Cue> {
+        const START: Cue = Cue { frame: 0, label: None };
+
+        match offset.cmp(&0) {
+            Ordering::Greater => self.cues.iter().filter(|c| c.frame > pos).nth(offset.unsigned_abs() - 1),
+            // 刚过一个标记时往回跳到它前面那个，和“上一首”一样
+            Ordering::Less => Some(self.cues.iter().rev().filter(|c| c.frame + grace < pos).nth(offset.unsigned_abs() - 1).unwrap_or(&START)),
+            Ordering::Equal => None,
+        }
+    }
+}
+
+#[inline]
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    b.get(i..i + 4).map_or(0, |x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+}
+
+/// Parses the body of a `cue ` chunk into cue point IDs and their frames in the data chunk.
+pub fn parse_cue(b: &[u8]) -> Vec<(u32, u64)> {
+    let n = u32_at(b, 0) as usize;
+    b.get(4..).unwrap_or_default().chunks_exact(24).take(n)
+        // dwSampleOffset 是 data 块中的位置；dwChunkStart/dwBlockStart 只对 wavl 列表有意义
+        .filter(|p| &p[8..12] == b"data")
+        .map(|p| (u32_at(p, 0), u64::from(u32_at(p, 20))))
+        .collect()
+}
+
+/// Parses the labels (`labl` sub-chunks) of a `LIST` chunk of type `adtl`.
+pub fn parse_adtl(b: &[u8]) -> Vec<(u32, String)> {
+    let mut labels = Vec::new();
+    let mut pos = 4; // 跳过 "adtl"
+    while let Some(head) = b.get(pos..pos + 8) {
+        let size = u32_at(head, 4) as usize;
+        let body = b.get(pos + 8..).map_or(&[][..], |v| &v[..size.min(v.len())]);
+        if &head[..4] == b"labl" && let Some(text) = tags::text(body.get(4..).unwrap_or_default()) {
+            labels.push((u32_at(body, 0), text));
+        }
+        pos += 8 + size + (size & 1);
+    }
+    labels
+}
+
+/// Takes the first forward loop of a `smpl` chunk.
+pub fn parse_smpl(b: &[u8]) -> Option<Loop> {
+    let n = u32_at(b, 28) as usize;
+    b.get(36..)?.chunks_exact(24).take(n)
+        .find(|l| u32_at(l, 4) == 0) // 0 是正向循环，不支持往返与反向
+        .map(|l| Loop { start: u64::from(u32_at(l, 8)), end: u64::from(u32_at(l, 12)) + 1, count: u32_at(l, 20) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(id: u32, chunk: [u8; 4], offset: u32) -> Vec<u8> {
+        [&id.to_le_bytes()[..], &[0; 4], &chunk, &[0; 8], &offset.to_le_bytes()].concat()
+    }
+
+    fn markers(frames: &[u64]) -> Markers {
+        let cues = frames.iter().map(|&frame| Cue { frame, label: None }).collect();
+        Markers { cues, sustain: None }.fit(0..u64::MAX)
+    }
+
+    #[test]
+    fn cue_chunk() {
+        // 只认 data 块里的点，个数以开头记的为准
+        let b = [&3u32.to_le_bytes()[..], &cue(1, *b"data", 4410), &cue(2, *b"slnt", 10), &cue(3, *b"data", 100), &cue(4, *b"data", 7)].concat();
+        assert_eq!(parse_cue(&b), [(1, 4410), (3, 100)]);
+        assert!(parse_cue(&[]).is_empty());
+    }
+
+    #[test]
+    fn adtl_labels() {
+        // 奇数长度的子块后有填充字节，note 不是标签
+        let b = [&b"adtl"[..], b"labl\x07\0\0\0\x01\0\0\0Hi!\0", b"note\x06\0\0\0\x02\0\0\0x\0", b"labl\x05\0\0\0\x03\0\0\0\0\0"].concat();
+        assert_eq!(parse_adtl(&b), [(1, "Hi!".to_owned())]);
+    }
+
+    #[test]
+    fn smpl_loops() {
+        // 第一个是往返循环，取第二个；结束位置存的是最后一帧
+        let looped = |kind: u32, start: u32, end: u32, count: u32| [&[0; 4][..], &kind.to_le_bytes(), &start.to_le_bytes(), &end.to_le_bytes(), &[0; 4], &count.to_le_bytes()].concat();
+        let b = [&[0; 28][..], &2u32.to_le_bytes(), &[0; 4], &looped(1, 10, 20, 0), &looped(0, 100, 199, 3)].concat();
+        let l = parse_smpl(&b).unwrap();
+        assert_eq!((l.start, l.end, l.count), (100, 200, 3));
+        assert!(parse_smpl(&b[..36 + 24]).is_none());
+    }
+
+    #[test]
+    fn fit_region() {
+        let mut m = markers(&[500, 100, 900, 100]);
+        m.sustain = Some(Loop { start: 200, end: 400, count: 0 });
+        let m = m.fit(100..800);
+        assert_eq!(m.cues.iter().map(|c| c.frame).collect::<Vec<_>>(), [100, 500]);
+        assert!(m.sustain.is_some());
+        assert!(m.fit(300..800).sustain.is_none());
+    }
+
+    #[test]
+    fn jump() {
+        let m = markers(&[100, 200, 300]);
+        let at = |pos, offset| m.jump(pos, offset, 10).map(|c| c.frame);
+        assert_eq!(at(150, 1), Some(200));
+        assert_eq!(at(150, 2), Some(300));
+        assert_eq!(at(150, 3), None);
+        assert_eq!(at(200, 1), Some(300));
+        assert_eq!(at(250, -1), Some(200));
+        // 刚过 200，往回跳到 100
+        assert_eq!(at(205, -1), Some(100));
+        assert_eq!(at(250, -5), Some(0));
+        assert_eq!(at(250, 0), None);
+    }
+}
@@ -1,24 +1,59 @@
-use core::hint::unlikely;
+use core::{hint::unlikely, ops::Range};
 use std::{
+    collections::HashSet,
     fs,
-    io::{self, Seek, SeekFrom},
+    io::{self, Read, SeekFrom},
     path::{Path, PathBuf},
     sync::mpsc::{Receiver, Sender, channel},
 };
 
 use alsa::{Mixer, mixer::SelemId};
-use hound::{WavReader, WavSpec};
+use hound::{SampleFormat, WavSpec};
 
 use crate::{
-    util::{GUIEvent, Handle, MP3Event, PlayerEvent, cvt_err, get_channel_handle},
+    decoder::{AnyDecoder, Leftover, Source, UNKNOWN_FRAMES},
+    aiff, caf, cue, flac, http::{self, OnTitle, Remote}, m3u, mpeg, ogg, raw, stream, wav,
+    sink::{Handoff, SinkConfig, SinkKind},
+    markers::Markers,
+    raw::RawFormat,
+    shift::{SPEED_ONE, Speed},
+    tags::Tags,
+    util::{GUIEvent, Handle, MP3Event, PlayError, PlayerEvent, get_channel_handle},
     wav::Player,
 };
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SongKind {
+    Wav,
+    Mp3,
+    Flac,
+    Ogg,
+    Aiff,
+    Caf,
+    Raw(RawFormat),
+    Stream(Option<RawFormat>), // 标准输入或 FIFO，没有 --raw 时按 WAVE 读
+}
+
+/// How the files of the music directory are read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    /// Also accept WAV files with unknown trailing chunks or a truncated data chunk
+    pub lenient: bool,
+    /// Format of `.pcm`/`.raw` files without a sidecar
+    pub raw: Option<RawFormat>,
+    /// Bytes of a live network stream to buffer before playing
+    pub prebuffer: usize,
+}
+
 #[derive(Clone)]
 pub struct Song {
     path: PathBuf,
+    kind: SongKind,
     spec: WavSpec,
-    num_samples: u32,
+    frames: u64,
+    tags: Tags,
+    markers: Markers,
+    region: Range<u64>, // 分轨时是这一轨在文件里的范围
 }
 
 impl Song {
@@ -27,33 +62,145 @@ impl Song {
         &self.path
     }
 
-    pub fn load(path: PathBuf) -> Result<Self, PathBuf> {
-        let Ok(tmp_reader) = WavReader::open(&path) else { return Err(path) };
-        let spec = tmp_reader.spec();
-        let num_samples = tmp_reader.len();
-        let file_size = u64::from(num_samples) * u64::from(spec.bytes_per_sample);
-        let mut inner = tmp_reader.into_inner();
-        if let Ok(cur) = inner.stream_position()
-            && let Ok(metadata) = inner.into_inner().metadata()
-            && metadata.len() == cur + file_size
-        {
-            Ok(Self {
-                path,
-                spec,
-                num_samples,
-            })
+    #[inline]
+    pub const fn get_tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    #[inline]
+    pub const fn get_markers(&self) -> &Markers {
+        &self.markers
+    }
+
+    #[inline]
+    pub fn get_region(&self) -> Range<u64> {
+        self.region.clone()
+    }
+
+    /// Whether the player can seek in the song; a stream only within what it still buffers.
+    #[inline]
+    pub const fn is_seekable(&self) -> bool {
+        !matches!(self.kind, SongKind::Stream(_))
+    }
+
+    /// A song read from standard input, a FIFO or a live network stream, only opened when played.
+    fn stream(path: PathBuf, raw: Option<RawFormat>, tags: Tags) -> Self {
+        // 流打开后才知道格式
+        let spec = raw.map_or(WavSpec { channels: 0, sample_rate: 0, bits_per_sample: 0, bytes_per_sample: 0, sample_format: SampleFormat::Int }, |f| f.spec);
+        let title = (path == Path::new(stream::STDIN)).then(|| "Standard input".to_owned());
+        Self {
+            path,
+            kind: SongKind::Stream(raw),
+            spec,
+            frames: UNKNOWN_FRAMES,
+            tags: tags.or(Tags { title, ..Tags::default() }),
+            markers: Markers::default(),
+            region: 0..UNKNOWN_FRAMES,
+        }
+    }
+
+    pub fn load(path: PathBuf, options: LoadOptions) -> Result<Self, (PathBuf, io::Error)> {
+        Self::probe(&path, options).map_err(|e| (path, e))
+    }
+
+    fn probe(path: &Path, options: LoadOptions) -> io::Result<Self> {
+        const TRUNCATED: io::Error = io::const_error!(io::ErrorKind::InvalidData, "WAVE data chunk runs past the end of the file (see --lenient)");
+        const TRAILING: io::Error = io::const_error!(io::ErrorKind::InvalidData, "extra bytes after the WAVE data chunk (see --lenient)");
+        const UNRECOGNIZED: io::Error = io::const_error!(io::ErrorKind::InvalidData, "unrecognized audio format");
+        const EMPTY: io::Error = io::const_error!(io::ErrorKind::InvalidData, "no audio frames");
+
+        let mut file = if http::is_url(path) {
+            // 同一个请求就能看出是不是直播流，电台之类的没有长度，播放时再连上
+            match http::open(&path.to_string_lossy())? {
+                Remote::File(file) => Source::Http(file),
+                Remote::Live(tags) => return Ok(Self::stream(path.to_owned(), options.raw, tags)),
+            }
         } else {
-            Err(path)
+            Source::open(path)?
+        };
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic).map_err(|_| UNRECOGNIZED)?;
+        let with = |kind| move |(spec, frames)| (kind, spec, frames, Tags::default(), Markers::default());
+        // 有魔数的格式按魔数认，报告这个格式自己的错误；MPEG 只能靠帧同步，FLAC 前面也可能有 ID3v2 标签
+        let (kind, spec, frames, tags, markers) = match &magic {
+            // 没有文件头的按扩展名认
+            _ if raw::is_raw(path) => {
+                let format = raw::format_of(path, options.raw)?;
+                (SongKind::Raw(format), format.spec, raw::frames(file.size()?, format), Tags::default(), Markers::default())
+            }
+            b"RIFF" | b"RF64" | b"BW64" => {
+                let header = wav::read_header(&mut file)?;
+                let spec = header.spec()?;
+                if header.missing != 0 {
+                    if !options.lenient { return Err(TRUNCATED); }
+                    tracing::warn!("\x1b[33m{}\x1b[0m is truncated, {} bytes of audio data missing.", path.display(), header.missing);
+                }
+                if !options.lenient && !wav::meta_after_data(&mut file, &header)? {
+                    return Err(TRAILING);
+                }
+                let (tags, markers) = wav::read_meta(&mut file, &header).unwrap_or_default();
+                (SongKind::Wav, spec, header.frames(), tags, markers)
+            }
+            b"fLaC" => flac::probe(&mut file).map(with(SongKind::Flac))?,
+            b"OggS" => ogg::probe(&mut file).map(with(SongKind::Ogg))?,
+            b"FORM" => aiff::probe(&mut file).map(with(SongKind::Aiff))?,
+            b"caff" => caf::probe(&mut file).map(with(SongKind::Caf))?,
+            _ => flac::probe(&mut file)
+                .map(with(SongKind::Flac))
+                .or_else(|_| mpeg::probe(&mut file).map(with(SongKind::Mp3)))
+                .map_err(|_| UNRECOGNIZED)?,
+        };
+        if frames == 0 {
+            return Err(EMPTY);
         }
+        Ok(Self { path: path.to_owned(), kind, spec, frames, tags, markers, region: 0..frames })
     }
+
+    /// Splits the song into the virtual tracks of a cue sheet, each ending where the next one begins.
+    fn split(&self, tracks: &[&cue::Track]) -> Vec<Self> {
+        let begins: Vec<_> = tracks.iter().map(|t| t.begin_at(self.spec.sample_rate).min(self.frames)).collect();
+        let ends = begins.iter().skip(1).copied().chain([self.frames]);
+        tracks
+            .iter()
+            .zip(begins.iter().copied().zip(ends))
+            .filter(|&(_, (begin, end))| begin < end)
+            .map(|(track, (begin, end))| Self {
+                tags: track.tags.clone().or(self.tags.clone()),
+                markers: self.markers.clone().fit(begin..end),
+                region: begin..end,
+                ..self.clone()
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn open(&self, options: LoadOptions, on_title: OnTitle) -> Result<AnyDecoder, PlayError> {
+        AnyDecoder::open(&self.path, self.kind, options, on_title)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MixerConfig {
+    /// ALSA mixer card name
+    pub card: String,
+    /// Simple element (control) name and index
+    pub selem: String,
+    pub index: u32,
 }
 
 pub struct MP3 {
     songs: Vec<Song>,
     current_idx: usize,
-    multiplier: u8, // 倍速 * 0.5
-    mixer: Mixer,
+    speed: Speed,
+    sink: SinkConfig,
+    handoff: Handoff,
+    leftover: Leftover<AnyDecoder>,
+    options: LoadOptions,
+    crossfade: u32, // 打开交叉淡化时使用的长度 (ms)
+    mixer_config: MixerConfig,
+    mixer: Option<Mixer>,
     elem: *mut alsa_sys::snd_mixer_elem_t,
+    volume_range: (i64, i64),
     tx: Option<Sender<PlayerEvent>>,
     pub mtx: Sender<MP3Event>,
     mrx: Receiver<MP3Event>,
@@ -64,42 +211,161 @@ impl Drop for MP3 {
         if let Some(tx) = self.tx.take() {
             let _ = tx.send(PlayerEvent::Terminate);
         }
+        // 列表不再放了，没人接的设备也关掉
+        self.handoff.close();
     }
 }
 
 impl MP3 {
+    const DEFAULT_CROSSFADE: u32 = 5000;
+
     #[inline]
     pub fn get_songs(&self) -> &[Song] {
         &self.songs
     }
 
-    pub fn load(dir: PathBuf) -> io::Result<Self> {
-        const NO_SONGS_FOUND: io::Error = io::const_error!(io::ErrorKind::NotFound, "No songs found in the specified directory");
+    /// Loads the song at `path` into `songs`, titled `title` (from a playlist) if given.
+    fn load_song(path: PathBuf, title: Option<String>, options: LoadOptions, songs: &mut Vec<Song>) {
+        match Song::load(path, options) {
+            Ok(mut song) => {
+                song.tags = Tags { title, ..Tags::default() }.or(song.tags);
+                tracing::info!("\x1b[32m{}\x1b[0m {:?} sanity check passed (spec={:?}, frames={}, {:?}, cues={}, loop={:?}).", song.path.display(), song.kind, song.spec, song.frames, song.tags, song.markers.cues.len(), song.markers.sustain);
+                songs.push(song);
+            }
+            Err((path, e)) => tracing::warn!("\x1b[33m{}\x1b[0m skipped: {e}.", path.display()),
+        }
+    }
 
-        let mixer = Mixer::new("default", false).map_err(io::Error::other)?;
+    /// Loads the songs at `url`, an M3U playlist or a single song (or live stream).
+    fn load_remote(url: &str, options: LoadOptions) -> io::Result<Vec<Song>> {
+        const NO_SONGS_FOUND: io::Error = io::const_error!(io::ErrorKind::NotFound, "No songs found at the specified URL");
 
         let mut songs = Vec::new();
-        for entry in fs::read_dir(&dir)? {
+        if m3u::is_playlist(Path::new(url.split(['?', '#']).next().unwrap_or(url))) {
+            for entry in m3u::parse(&http::fetch(url)?) {
+                match http::join(url, &entry.location) {
+                    Ok(location) => Self::load_song(location.into(), entry.title, options, &mut songs),
+                    Err(e) => tracing::warn!("\x1b[33m{}\x1b[0m in \x1b[33m{url}\x1b[0m skipped: {e}.", entry.location),
+                }
+            }
+        } else {
+            Self::load_song(url.into(), None, options, &mut songs);
+        }
+        if songs.is_empty() { return Err(NO_SONGS_FOUND); }
+        tracing::info!("successfully load \x1b[36m{}\x1b[0m songs.", songs.len());
+        Ok(songs)
+    }
+
+    /// Loads every song of the music directory, splitting files by the cue sheets next to them.
+    fn scan(dir: &Path, options: LoadOptions) -> io::Result<Vec<Song>> {
+        const NO_SONGS_FOUND: io::Error = io::const_error!(io::ErrorKind::NotFound, "No songs found in the specified directory");
+
+        let mut paths = Vec::new();
+        let mut sheets = Vec::new();
+        let mut playlists = Vec::new();
+        for entry in fs::read_dir(dir)? {
             let path = entry?.path();
-            match Song::load(path) {
-                Ok(song) => {
-                    tracing::info!("\x1b[32m{}\x1b[0m WAV sanity check passed (spec={:?}, num_samples={}).", song.path.display(), song.spec, song.num_samples);
-                    songs.push(song);
+            if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("cue")) {
+                sheets.push(path);
+            } else if m3u::is_playlist(&path) {
+                playlists.push(path);
+            } else if path.extension().is_some_and(|e| e == raw::SIDECAR) && path.file_stem().is_some_and(|s| raw::is_raw(Path::new(s))) {
+                // 原始 PCM 文件的格式说明
+            } else {
+                paths.push(path);
+            }
+        }
+
+        // 分轨的文件只按 cue 表出现，不再整个出现
+        let mut songs = Vec::new();
+        let mut split = HashSet::new();
+        for sheet in sheets {
+            let tracks = match fs::read(&sheet) {
+                Ok(b) => cue::parse(&b, dir),
+                Err(e) => {
+                    tracing::warn!("\x1b[33m{}\x1b[0m skipped: {e}.", sheet.display());
+                    continue;
+                }
+            };
+            let mut files: Vec<_> = tracks.iter().map(|t| &t.file).collect();
+            files.sort_unstable();
+            files.dedup();
+            for file in files {
+                let of_file: Vec<_> = tracks.iter().filter(|t| &t.file == file).collect();
+                match Song::load(file.clone(), options) {
+                    Ok(song) => {
+                        let tracks = song.split(&of_file);
+                        tracing::info!("\x1b[32m{}\x1b[0m split into \x1b[36m{}\x1b[0m tracks by \x1b[32m{}\x1b[0m.", file.display(), tracks.len(), sheet.display());
+                        songs.extend(tracks);
+                        split.insert(file.clone());
+                    }
+                    Err((path, e)) => tracing::warn!("\x1b[33m{}\x1b[0m in \x1b[33m{}\x1b[0m skipped: {e}.", path.display(), sheet.display()),
+                }
+            }
+        }
+
+        for path in paths {
+            if !split.contains(&path) {
+                Self::load_song(path, None, options, &mut songs);
+            }
+        }
+
+        // 目录里的文件已经加载过了，播放列表只添上网络上的
+        for playlist in playlists {
+            match fs::read(&playlist) {
+                Ok(b) => {
+                    for entry in m3u::parse(&b).into_iter().filter(|e| http::is_url(Path::new(&e.location))) {
+                        Self::load_song(entry.location.into(), entry.title, options, &mut songs);
+                    }
                 }
-                Err(path) => tracing::warn!("\x1b[33m{}\x1b[0m is not a WAV file, skipped.", path.display()),
+                Err(e) => tracing::warn!("\x1b[33m{}\x1b[0m skipped: {e}.", playlist.display()),
             }
         }
         if songs.is_empty() { return Err(NO_SONGS_FOUND); }
         tracing::info!("successfully load \x1b[36m{}\x1b[0m songs.", songs.len());
-        songs.sort_unstable_by(|lhs, rhs| lhs.path.as_os_str().cmp(rhs.path.as_os_str()));
+        // 同一个文件的各轨按位置排
+        songs.sort_unstable_by(|lhs, rhs| lhs.path.as_os_str().cmp(rhs.path.as_os_str()).then(lhs.region.start.cmp(&rhs.region.start)));
+        Ok(songs)
+    }
+
+    pub fn load(dir: PathBuf, options: LoadOptions, sink: SinkConfig, mixer_config: MixerConfig) -> io::Result<Self> {
+        const OUTPUT_IS_INPUT: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "Output directory must differ from the music directory");
 
+        if let Some(output) = &sink.output
+            && fs::canonicalize(output).is_ok_and(|output| fs::canonicalize(&dir).is_ok_and(|dir| output == dir))
+        {
+            return Err(OUTPUT_IS_INPUT);
+        }
+
+        let mixer = match sink.kind {
+            SinkKind::Alsa => Some(Mixer::new(&mixer_config.card, false).map_err(io::Error::other)?),
+            SinkKind::Null | SinkKind::Wav => None,
+        };
+
+        let songs = if stream::is_stream(&dir) {
+            tracing::info!("Playing the stream from \x1b[32m{}\x1b[0m, seeking limited to what is buffered.", dir.display());
+            vec![Song::stream(dir, options.raw, Tags::default())]
+        } else if http::is_url(&dir) {
+            Self::load_remote(&dir.to_string_lossy(), options)?
+        } else {
+            Self::scan(&dir, options)?
+        };
+
+        let crossfade = if sink.crossfade == 0 { Self::DEFAULT_CROSSFADE } else { sink.crossfade };
         let (mtx, mrx) = channel();
         Ok(Self {
             songs,
             current_idx: usize::MAX,
-            multiplier: 2,
+            speed: SPEED_ONE,
+            sink,
+            handoff: Handoff::default(),
+            leftover: Leftover::default(),
+            options,
+            crossfade,
+            mixer_config,
             mixer,
             elem: core::ptr::null_mut(),
+            volume_range: (0, 0),
             tx: None,
             mtx,
             mrx,
@@ -108,13 +374,31 @@ impl MP3 {
 
     pub fn set_volume(&mut self, volume: i32) -> alsa::Result<()> {
         const E: alsa::Error = alsa::Error::new("set_volume failed", -1);
+        const MAX_VOLUME: i64 = 512; // GUI 音量条的范围
+
+        let Some(mixer) = &self.mixer else {
+            tracing::info!("No mixer for {:?} sink, volume ignored.", self.sink.kind);
+            return Ok(());
+        };
 
         if unlikely(self.elem.is_null()) {
-            let selem_id = SelemId::new("Master", 0);
-            self.elem = self.mixer.find_selem(&selem_id).ok_or(E)?.handle;
+            let MixerConfig { card, selem, index } = &self.mixer_config;
+            let selem_id = SelemId::new(selem, *index);
+            let Some(elem) = mixer.find_selem(&selem_id) else {
+                tracing::warn!("Mixer control \x1b[33m'{selem}',{index}\x1b[0m not found on \x1b[33m{card}\x1b[0m, see --list-devices.");
+                return Err(E);
+            };
+            if !elem.has_playback_volume() {
+                tracing::warn!("Mixer control \x1b[33m'{selem}',{index}\x1b[0m has no playback volume, see --list-devices.");
+                return Err(E);
+            }
+            self.volume_range = elem.get_playback_volume_range();
+            self.elem = elem.handle;
         }
 
-        let ret = unsafe { alsa_sys::snd_mixer_selem_set_playback_volume_all(self.elem, volume) };
+        let (min, max) = self.volume_range;
+        let value = min + (max - min) * i64::from(volume) / MAX_VOLUME;
+        let ret = unsafe { alsa_sys::snd_mixer_selem_set_playback_volume_all(self.elem, value as core::ffi::c_long) };
         if ret < 0 {
             return Err(E);
         }
@@ -131,13 +415,37 @@ impl MP3 {
         }
 
         let song = self.songs.get(idx).ok_or(OUT_OF_BOUNDS)?;
-        let mut player = Player::new(
-            WavReader::open(&song.path).map_err(cvt_err)?,
-            self.multiplier,
-        )?;
+
+        // 换到同一个文件的另一轨时先停下旧的播放器，接着用它的解码器与设备
+        if self.songs.get(self.current_idx).is_some_and(|cur| cur.path == song.path) && let Some(tx) = self.tx.take() {
+            let old = get_channel_handle(&raw const tx);
+            self.leftover.reserve();
+            self.handoff.reserve(old);
+            if tx.send(PlayerEvent::Park).is_err() {
+                self.leftover.release();
+                self.handoff.release(old);
+            }
+        }
+        let decoder = if let Some(decoder) = self.leftover.take(&song.path) {
+            tracing::info!("Reading on with the decoder left by the last track.");
+            decoder
+        } else {
+            let gtx = gtx.clone();
+            song.open(self.options, Box::new(move |title| { let _ = gtx.send(GUIEvent::StreamTitle { index: idx, title }); }))?
+        };
+        let player = Player::new(decoder, self.speed, song)?;
 
         if let Some(tx) = self.tx.take() {
-            let _ = tx.send(PlayerEvent::Terminate);
+            if self.sink.skip_fade == 0 {
+                let _ = tx.send(PlayerEvent::Terminate);
+            } else {
+                // 新的播放器等旧的留下淡出部分
+                let old = get_channel_handle(&raw const tx);
+                self.handoff.reserve(old);
+                if tx.send(PlayerEvent::Skip).is_err() {
+                    self.handoff.release(old);
+                }
+            }
         }
 
         let (tx, rx) = channel();
@@ -148,12 +456,32 @@ impl MP3 {
         tracing::info!("switch to song #{idx}: \x1b[36m{}\x1b[0m", song.path.file_name().unwrap_or(song.path.as_os_str()).display());
         {
             let mtx = self.mtx.clone();
-            std::thread::spawn(move || player.play(mtx, gtx, rx).unwrap());
+            let sink = self.sink.for_song(&song.path);
+            let handoff = self.handoff.clone();
+            let leftover = self.leftover.clone();
+            // 出错 (比如网络断了) 只结束这一首，照样报告结束，换到下一首
+            std::thread::spawn(move || if let Err(e) = player.play(&sink, &handoff, &leftover, mtx, gtx, rx) {
+                tracing::error!("Player stopped: {e}");
+            });
         }
 
         Ok(())
     }
 
+    /// Switches to the song after the current one, skipping those that fail to open.
+    fn switch_next(&mut self, gtx: Sender<GUIEvent>) -> io::Result<()> {
+        let mut last = Ok(());
+        for step in 1..=self.songs.len() {
+            let idx = (self.current_idx + step) % self.songs.len();
+            last = self.switch_song(idx, gtx.clone());
+            match &last {
+                Ok(()) => break,
+                Err(e) => tracing::warn!("Skipping song #{idx}: {e}"),
+            }
+        }
+        last
+    }
+
     const fn get_current_handle(&self) -> Handle {
         if let Some(tx) = &self.tx {
             get_channel_handle(core::ptr::from_ref(tx))
@@ -169,9 +497,14 @@ impl MP3 {
             match self.mrx.recv() {
                 Ok(MP3Event::PlayerEnd { player }) => {
                     let cur_handle = self.get_current_handle();
-                    if cur_handle == player {
+                    if cur_handle == player && self.songs.len() == 1 && !self.songs[0].is_seekable() {
+                        // 只放一个流，读完就没有了，不再循环
+                        tracing::info!("The stream ended, exiting main loop.");
+                        return Ok(());
+                    } else if cur_handle == player {
                         tracing::info!("song #{} play finished, switch to next song.", self.current_idx);
-                        self.switch_song((self.current_idx + 1) % self.songs.len(), gtx.clone())?;
+                        self.tx = None; // 已经结束，不必再通知
+                        self.switch_next(gtx.clone())?;
                         if let Some(tx) = &self.tx {
                             let _ = tx.send(PlayerEvent::Resume);
                         }
@@ -188,9 +521,9 @@ impl MP3 {
                     if let Some(tx) = &self.tx {
                         let _ = tx.send(sub);
                     }
-                    if let PlayerEvent::SetMultiplier { multiplier } = sub {
-                        self.multiplier = multiplier;
-                        let _ = gtx.send(GUIEvent::SetMultiplier { multiplier });
+                    if let PlayerEvent::SetSpeed { speed } = sub {
+                        self.speed = speed;
+                        let _ = gtx.send(GUIEvent::SetSpeed { speed });
                     }
                 }
                 Ok(MP3Event::SwitchSong { seek }) => {
@@ -205,8 +538,89 @@ impl MP3 {
                     }
                 }
                 Ok(MP3Event::SetVolume { volume }) => self.set_volume(volume).map_err(io::Error::other)?,
+                Ok(MP3Event::SetCrossfade { enabled }) => {
+                    // 从下一首歌开始生效
+                    self.sink.crossfade = if enabled { self.crossfade } else { 0 };
+                    tracing::info!("Crossfade set to {} ms.", self.sink.crossfade);
+                }
                 Err(e) => return Err(io::Error::other(e)),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hound::WavWriter;
+
+    use super::*;
+    use crate::ogg::tests::page;
+
+    /// FLAC metadata with only a STREAMINFO block: 44.1 kHz stereo 16-bit, 1000 frames.
+    fn flac() -> Vec<u8> {
+        let packed = (u64::from(44100u32) << 44 | 1 << 41 | 15 << 36) + 1000;
+        let info = [&4096u16.to_be_bytes()[..], &4096u16.to_be_bytes(), &[0; 6], &packed.to_be_bytes(), &[0; 16]].concat();
+        [&b"fLaC\x80\0\0\x22"[..], &info].concat()
+    }
+
+    fn files() -> Vec<(&'static str, Vec<u8>, SongKind, u64)> {
+        let mut wav = io::Cursor::new(Vec::new());
+        let spec = WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, bytes_per_sample: 2, sample_format: SampleFormat::Int };
+        let mut writer = WavWriter::new(&mut wav, spec).unwrap();
+        (0..10).for_each(|i| writer.write_sample(i as i16).unwrap());
+        writer.finalize().unwrap();
+
+        let mut opus = b"OpusHead\x01\x02".to_vec();
+        opus.extend([0; 2]);
+        opus.extend(48000u32.to_le_bytes());
+        opus.extend([0; 3]);
+        let ogg = [page(2, 0, 1, &[19], &opus), page(0, 0, 1, &[8], b"OpusTags"), page(4, 960, 1, &[1], &[0])].concat();
+
+        let mut aiff = b"FORM\0\0\0\0AIFFCOMM\0\0\0\x12\0\x01\0\0\0\x04\0\x10\x40\x0e\xac\x44".to_vec();
+        aiff.extend([0; 6]);
+        aiff.extend(b"SSND\0\0\0\x10\0\0\0\0\0\0\0\0");
+        aiff.extend([0; 8]);
+
+        let mut caf = b"caff\0\x01\0\0desc".to_vec();
+        caf.extend(32i64.to_be_bytes());
+        caf.extend(44100f64.to_bits().to_be_bytes());
+        caf.extend(b"lpcm");
+        for x in [0u32, 4, 1, 2, 16] {
+            caf.extend(x.to_be_bytes());
+        }
+        caf.extend(b"data");
+        caf.extend(12i64.to_be_bytes());
+        caf.extend([0; 12]);
+
+        // 128 kbps 44.1 kHz 的 CBR 帧，没有 Xing 标签
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&0xfffb_9064u32.to_be_bytes());
+
+        vec![
+            ("a.wav", wav.into_inner(), SongKind::Wav, 10),
+            ("a.flac", flac(), SongKind::Flac, 1000),
+            ("id3.flac", [&b"ID3\x04\0\0\0\0\0\x0a"[..], &[0; 10], &flac()].concat(), SongKind::Flac, 1000),
+            ("a.opus", ogg, SongKind::Ogg, 960),
+            ("a.aiff", aiff, SongKind::Aiff, 4),
+            ("a.caf", caf, SongKind::Caf, 2),
+            ("a.mp3", frame.repeat(5), SongKind::Mp3, 5 * 1152),
+            ("a.pcm", vec![0; 40], SongKind::Raw("s16le,2,8000".parse().unwrap()), 10),
+        ]
+    }
+
+    #[test]
+    fn probe_every_format() {
+        let dir = std::env::temp_dir().join(format!("player-probe-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let options = LoadOptions { raw: "s16le,2,8000".parse().ok(), ..LoadOptions::default() };
+        for (name, data, kind, frames) in files() {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            let song = Song::probe(&path, options).unwrap_or_else(|e| panic!("{name}: {e}"));
+            assert_eq!((song.kind, song.frames), (kind, frames), "{name}");
+        }
+        fs::write(dir.join("junk.bin"), [0x12; 64]).unwrap();
+        assert!(Song::probe(&dir.join("junk.bin"), options).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
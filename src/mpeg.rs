@@ -0,0 +1,697 @@
+use core::ops::Range;
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+use hound::{SampleFormat, WavSpec};
+use symphonia_bundle_mp3::MpaDecoder;
+use symphonia_core::{
+    audio::{AudioBufferRef, Signal},
+    codecs::{CODEC_TYPE_MP3, CodecParameters, Decoder as _, DecoderOptions},
+    formats::Packet,
+};
+
+use crate::{
+    decoder::{self, Decoder},
+    fmt_impl::Fmt,
+    util::PlayError,
+};
+
+const NO_FRAME: io::Error = io::const_error!(io::ErrorKind::InvalidData, "no MPEG audio layer III frame found");
+
+/// Frames decoded and thrown away before a seek target, to refill the bit reservoir and the MDCT overlap.
+const PREROLL: usize = 4;
+/// Junk tolerated before the first frame, so that other files are rejected quickly.
+const MAX_JUNK: u64 = 64 << 10;
+/// Delay of the layer III decoder itself, on top of the encoder delay in the LAME tag.
+const DECODER_DELAY: u32 = 529;
+
+const BITRATE_V1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const BITRATE_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+/// MPEG-1/2/2.5 layer III frame header.
+#[derive(Clone, Copy, Debug)]
+struct FrameHeader {
+    mpeg1: bool,
+    sample_rate: u32,
+    channels: u16,
+    crc: bool,
+    size: usize,
+}
+
+impl FrameHeader {
+    const LEN: usize = 4;
+
+    const fn parse(word: u32) -> Option<Self> {
+        if word >> 21 != 0x7ff || (word >> 17) & 3 != 1 { // 帧同步，Layer III
+            return None;
+        }
+        let shift = match (word >> 19) & 3 {
+            3 => 0, // MPEG-1
+            2 => 1, // MPEG-2
+            0 => 2, // MPEG-2.5
+            _ => return None,
+        };
+        let bitrate = ((word >> 12) & 0xf) as usize;
+        let rate = ((word >> 10) & 3) as usize;
+        // free format 不支持
+        if bitrate == 0 || bitrate == 15 || rate == 3 {
+            return None;
+        }
+
+        let mpeg1 = shift == 0;
+        let sample_rate = [44100, 48000, 32000][rate] >> shift;
+        let kbps = if mpeg1 { BITRATE_V1[bitrate] } else { BITRATE_V2[bitrate] };
+        let samples = if mpeg1 { 1152 } else { 576 };
+        let padding = (word >> 9) & 1;
+        Some(Self {
+            mpeg1,
+            sample_rate,
+            channels: if (word >> 6) & 3 == 3 { 1 } else { 2 },
+            crc: (word >> 16) & 1 == 0,
+            size: (samples / 8 * kbps * 1000 / sample_rate + padding) as usize,
+        })
+    }
+
+    /// PCM frames per MPEG frame.
+    #[inline]
+    const fn samples(self) -> u32 {
+        if self.mpeg1 { 1152 } else { 576 }
+    }
+
+    /// Offset of the data after the side information, where a Xing/Info tag would be.
+    const fn side_info_end(self) -> usize {
+        let side_info = match (self.mpeg1, self.channels) {
+            (true, 1) | (false, 2) => 17,
+            (true, _) => 32,
+            (false, _) => 9,
+        };
+        Self::LEN + if self.crc { 2 } else { 0 } + side_info
+    }
+
+    /// Whether both frames can belong to the same stream.
+    #[inline]
+    const fn compatible(self, other: Self) -> bool {
+        self.mpeg1 == other.mpeg1 && self.sample_rate == other.sample_rate && self.channels == other.channels
+    }
+
+    #[inline]
+    const fn spec(self) -> WavSpec {
+        WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            bytes_per_sample: 2,
+            sample_format: SampleFormat::Int,
+        }
+    }
+}
+
+/// Xing/Info tag with the LAME extension, or VBRI tag, in the first frame of VBR (and LAME CBR) files.
+#[derive(Clone, Copy, Debug, Default)]
+struct Xing {
+    frames: Option<u32>,
+    delay: u32, // 开头要裁掉的帧数，包括解码器延迟
+    padding: u32, // 结尾要裁掉的帧数
+}
+
+impl Xing {
+    fn parse(frame: &[u8], header: FrameHeader) -> Option<Self> {
+        // VBRI (Fraunhofer) 总在帧头后 32 字节处：版本、延迟、质量、字节数、帧数
+        if let Some(vbri) = frame.get(FrameHeader::LEN + 32..FrameHeader::LEN + 32 + 18)
+            && vbri.starts_with(b"VBRI")
+        {
+            return Some(Self { frames: Some(u32::from_be_bytes(vbri[14..].try_into().ok()?)), ..Self::default() });
+        }
+
+        let mut tag = frame.get(header.side_info_end()..)?;
+        let mut take = |n: usize| tag.split_off(..n);
+
+        let id = take(4)?;
+        if id != b"Xing" && id != b"Info" {
+            return None;
+        }
+        let flags = u32::from_be_bytes(take(4)?.try_into().ok()?);
+        let frames = if flags & 1 != 0 { Some(u32::from_be_bytes(take(4)?.try_into().ok()?)) } else { None };
+        for (bit, len) in [(2, 4), (4, 100), (8, 4)] { // 字节数、TOC、质量
+            if flags & bit != 0 {
+                take(len)?;
+            }
+        }
+
+        // LAME 扩展：编码器 (9)、版本 (1)、低通 (1)、ReplayGain (8)、标志 (1)、ABR (1)，之后是延迟与填充
+        let mut ret = Self { frames, ..Self::default() };
+        if let Some(lame) = take(24) && matches!(&lame[..4], b"LAME" | b"Lavf" | b"Lavc") {
+            let trim = u32::from_be_bytes([0, lame[21], lame[22], lame[23]]);
+            ret.delay = (trim >> 12) + DECODER_DELAY;
+            ret.padding = (trim & 0xfff).saturating_sub(DECODER_DELAY);
+        }
+        Some(ret)
+    }
+}
+
+/// Reads ahead of a byte offset, keeping track of where it is.
+struct Scanner<R> {
+    reader: BufReader<R>,
+    pos: u64,
+    len: u64,
+}
+
+impl<R: Read + Seek> Scanner<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        Ok(Self { reader: BufReader::new(reader), pos: 0, len })
+    }
+
+    fn goto(&mut self, pos: u64) -> io::Result<()> {
+        if pos != self.pos {
+            self.reader.seek_relative(pos.cast_signed() - self.pos.cast_signed())?;
+            self.pos = pos;
+        }
+        Ok(())
+    }
+
+    /// Reads `buf.len()` bytes at `pos`, returns `false` if the file ends before that.
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<bool> {
+        if pos + buf.len() as u64 > self.len {
+            return Ok(false);
+        }
+        self.goto(pos)?;
+        self.reader.read_exact(buf)?;
+        self.pos += buf.len() as u64;
+        Ok(true)
+    }
+
+    fn header_at(&mut self, pos: u64) -> io::Result<Option<FrameHeader>> {
+        let mut word = [0; FrameHeader::LEN];
+        Ok(if self.read_at(pos, &mut word)? { FrameHeader::parse(u32::from_be_bytes(word)) } else { None })
+    }
+
+    /// Skips the `ID3v2` tags at the start, returns where the audio begins.
+    fn skip_id3v2(&mut self) -> io::Result<u64> {
+        let mut pos = 0;
+        let mut head = [0; 10];
+        while self.read_at(pos, &mut head)? && head.starts_with(b"ID3") {
+            // 同步安全整数，每字节 7 位；有 footer 时再加 10 字节
+            let size = head[6..].iter().fold(0, |acc, &b| acc << 7 | u64::from(b & 0x7f));
+            pos += 10 + size + if head[5] & 0x10 != 0 { 10 } else { 0 };
+        }
+        Ok(pos)
+    }
+
+    /// Finds the first frame in `pos..end` whose successor (if any) is also a frame of the stream.
+    fn sync(&mut self, mut pos: u64, end: u64, like: Option<FrameHeader>) -> io::Result<Option<(u64, FrameHeader)>> {
+        while pos < end && pos + FrameHeader::LEN as u64 <= self.len {
+            if let Some(header) = self.header_at(pos)?
+                && like.is_none_or(|like| like.compatible(header))
+            {
+                let next = pos + header.size as u64;
+                if next >= self.len || self.header_at(next)?.is_some_and(|h| header.compatible(h)) {
+                    return Ok(Some((pos, header)));
+                }
+            }
+            pos += 1;
+        }
+        Ok(None)
+    }
+
+    /// Locates the first audio frame, skipping `ID3v2` tags and reading the Xing/Info tag if present.
+    fn head(&mut self) -> io::Result<(u64, FrameHeader, Option<Xing>)> {
+        let start = self.skip_id3v2()?;
+        let (pos, header) = self.sync(start, start + MAX_JUNK, None)?.ok_or(NO_FRAME)?;
+
+        let mut frame = vec![0; header.size];
+        if self.read_at(pos, &mut frame)?
+            && let Some(xing) = Xing::parse(&frame, header)
+        {
+            // Xing 帧本身不含音频
+            tracing::info!("Xing/Info tag found: {xing:?}");
+            let (pos, header) = self.sync(pos + header.size as u64, pos + MAX_JUNK, Some(header))?.ok_or(NO_FRAME)?;
+            return Ok((pos, header, Some(xing)));
+        }
+        Ok((pos, header, None))
+    }
+
+    /// Finds the frame at or after `pos`, resyncing over junk in between.
+    fn next_frame(&mut self, pos: u64, first: FrameHeader) -> io::Result<Option<(u64, FrameHeader)>> {
+        match self.header_at(pos)? {
+            Some(header) if first.compatible(header) => Ok(Some((pos, header))),
+            _ => self.sync(pos, self.len, Some(first)),
+        }
+    }
+
+    /// The number of MPEG frames from `pos` on, by the Xing/VBRI tag or else as if the file were CBR.
+    fn estimate(&self, pos: u64, first: FrameHeader, xing: Option<Xing>) -> u64 {
+        match xing {
+            Some(Xing { frames: Some(frames), .. }) => frames.into(),
+            _ => (self.len - pos) / first.size as u64,
+        }
+    }
+}
+
+/// The number of PCM frames in `count` MPEG frames, after gapless trimming.
+#[inline]
+fn trimmed(count: u64, first: FrameHeader, xing: Option<Xing>) -> u64 {
+    let Xing { delay, padding, .. } = xing.unwrap_or_default();
+    (count * u64::from(first.samples())).saturating_sub(u64::from(delay + padding))
+}
+
+/// Decodes one MPEG frame, appending its samples to `pcm` as interleaved 16-bit.
+fn decode_into(decoder: &mut MpaDecoder, frame: &[u8], pcm: &mut Vec<u8>) -> symphonia_core::errors::Result<()> {
+    if let AudioBufferRef::F32(buf) = decoder.decode(&Packet::new_from_slice(0, 0, 0, frame))? {
+        for f in 0..buf.frames() {
+            for c in 0..buf.spec().channels.count() {
+                pcm.extend_from_slice(&i16::from_norm(f64::from(buf.chan(c)[f])).to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the spec and the number of frames (after gapless trimming) from the first frames only.
+///
+/// Without a Xing/Info or VBRI tag, the length is estimated as if the file were CBR.
+pub fn probe<R: Read + Seek>(reader: R) -> io::Result<(WavSpec, u64)> {
+    let mut scanner = Scanner::new(reader)?;
+    let (pos, header, xing) = scanner.head()?;
+    Ok((header.spec(), trimmed(scanner.estimate(pos, header, xing), header, xing)))
+}
+
+/// Decodes an MPEG audio layer III file to 16-bit PCM.
+///
+/// Frames are located and decoded lazily on read, so opening a remote file does not download it.
+/// Until the last frame has been located, [`Decoder::frames`] is the estimate [`probe`] gives.
+pub struct Mp3Decoder<R> {
+    scanner: Scanner<R>,
+    spec: WavSpec,
+    first: FrameHeader,
+    xing: Option<Xing>,
+    index: Vec<u64>, // 已找到的音频帧的位置，按需往后扫
+    next: Option<u64>, // 从哪里接着扫，扫到结尾后为 None
+    samples: u64, // 每帧的 PCM 帧数
+    skip: u64, // 开头裁掉的 PCM 帧数
+    frames: u64,
+    decoder: MpaDecoder,
+    frame: Vec<u8>,
+    pcm: Vec<u8>,
+    decoded: Option<usize>, // pcm 是哪一帧解码出来的
+    pos: u64,
+}
+
+impl<R: Read + Seek> Mp3Decoder<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut scanner = Scanner::new(reader)?;
+        let (pos, first, xing) = scanner.head()?;
+
+        let spec = first.spec();
+        let frames = trimmed(scanner.estimate(pos, first, xing), first, xing);
+        let delay = xing.unwrap_or_default().delay;
+        tracing::info!("MPEG stream: about {frames} samples per channel, spec={spec:?}");
+
+        let decoder = MpaDecoder::try_new(CodecParameters::new().for_codec(CODEC_TYPE_MP3), &DecoderOptions::default()).map_err(io::Error::other)?;
+        Ok(Self {
+            scanner,
+            spec,
+            first,
+            xing,
+            index: Vec::new(),
+            next: Some(pos),
+            samples: first.samples().into(),
+            skip: delay.into(),
+            frames,
+            decoder,
+            frame: Vec::new(),
+            pcm: Vec::new(),
+            decoded: None,
+            pos: 0,
+        })
+    }
+
+    #[inline]
+    fn block_align(&self) -> usize {
+        usize::from(self.spec.channels) * usize::from(self.spec.bytes_per_sample)
+    }
+
+    /// Scans on until the `i`-th frame is located or the file ends, where the number of frames becomes exact.
+    fn locate(&mut self, i: usize) -> io::Result<()> {
+        while self.index.len() <= i {
+            // 逐帧往后扫，中间有垃圾数据则重新同步
+            let Some(pos) = self.next else { break };
+            let found = self.scanner.next_frame(pos, self.first)?;
+            if let Some((at, header)) = found {
+                self.index.push(at);
+                self.next = Some(at + header.size as u64);
+            } else {
+                self.next = None;
+                self.frames = trimmed(self.index.len() as u64, self.first, self.xing);
+                self.pos = self.pos.min(self.frames);
+                tracing::info!("MPEG stream: {} frames, {} samples per channel", self.index.len(), self.frames);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the `i`-th frame into `self.pcm`, silence if it is corrupted.
+    fn decode(&mut self, i: usize) -> io::Result<()> {
+        let pos = self.index[i];
+        self.pcm.clear();
+        if let Some(header) = self.scanner.header_at(pos)? {
+            self.frame.resize(header.size, 0);
+            if self.scanner.read_at(pos, &mut self.frame)?
+                && let Err(e) = decode_into(&mut self.decoder, &self.frame, &mut self.pcm)
+            {
+                tracing::warn!("MPEG frame #{i} at {pos}: {e}");
+            }
+        }
+        self.pcm.resize(self.samples as usize * self.block_align(), 0);
+        self.decoded = Some(i);
+        Ok(())
+    }
+
+    fn decode_to(&mut self, i: usize) -> io::Result<()> {
+        let start = match self.decoded {
+            Some(d) if d == i => return Ok(()),
+            Some(d) if d + 1 == i => i,
+            _ => { // 跳转了，前面几帧要重新解码
+                self.decoder.reset();
+                i.saturating_sub(PREROLL)
+            }
+        };
+        for j in start..=i {
+            self.decode(j)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Decoder for Mp3Decoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let block_align = self.block_align();
+        let spec = self.spec;
+        let buf = decoder::as_bytes(buf, spec)?;
+        let want = (buf.len() / block_align).min((self.frames - self.pos) as usize);
+
+        let mut done = 0;
+        while done < want {
+            let raw = self.pos + self.skip;
+            let i = (raw / self.samples) as usize;
+            // 多找一帧才知道这是不是最后一帧；比估算的短就以实际的结尾为准
+            self.locate(i + 1)?;
+            if i >= self.index.len() || self.pos >= self.frames {
+                break;
+            }
+            self.decode_to(i)?;
+            let pcm = &self.pcm[(raw % self.samples) as usize * block_align..];
+            let n = (pcm.len() / block_align).min(want - done).min((self.frames - self.pos) as usize);
+            buf[done * block_align..(done + n) * block_align].copy_from_slice(&pcm[..n * block_align]);
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+
+    #[inline]
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        self.pos = frame.min(self.frames);
+        Ok(())
+    }
+}
+
+/// Decodes a live MPEG layer III stream as it comes, keeping the last [`Mp3Stream::HISTORY`] seconds
+/// of PCM so that it can seek back a little (to what is still being heard).
+pub struct Mp3Stream<R> {
+    reader: BufReader<R>,
+    first: FrameHeader,
+    spec: WavSpec,
+    decoder: MpaDecoder,
+    frame: Vec<u8>,
+    pcm: Vec<u8>, // 刚解码的一帧，每帧复用
+    history: VecDeque<u8>, // 最近解码出的 PCM，第 start 帧起
+    start: u64,
+    pos: u64,
+}
+
+impl<R: Read> Mp3Stream<R> {
+    const HISTORY: u64 = 10;
+
+    /// Makes `frame` at least `len` bytes long, unless the stream ends first.
+    fn fill(reader: &mut BufReader<R>, frame: &mut Vec<u8>, len: usize) -> io::Result<()> {
+        let have = frame.len();
+        if have < len {
+            frame.resize(len, 0);
+            let n = decoder::read_frames(reader, &mut frame[have..], 1)?;
+            frame.truncate(have + n as usize);
+        }
+        Ok(())
+    }
+
+    /// The header at the start of `frame`, if it has 4 bytes.
+    #[inline]
+    fn header(frame: &[u8]) -> Option<FrameHeader> {
+        frame.first_chunk().and_then(|&word| FrameHeader::parse(u32::from_be_bytes(word)))
+    }
+
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        // 从中途连上的流可能从半个帧开始，要连着两个帧头才算同步上
+        let mut frame = Vec::new();
+        let mut junk = 0;
+        let first = loop {
+            Self::fill(&mut reader, &mut frame, FrameHeader::LEN)?;
+            if frame.len() < FrameHeader::LEN || junk > MAX_JUNK {
+                return Err(NO_FRAME);
+            }
+            if let Some(header) = Self::header(&frame) {
+                Self::fill(&mut reader, &mut frame, header.size + FrameHeader::LEN)?;
+                if frame.get(header.size..).and_then(Self::header).is_some_and(|next| header.compatible(next)) {
+                    break header;
+                }
+            }
+            frame.remove(0);
+            junk += 1;
+        };
+        let spec = first.spec();
+        tracing::info!("MPEG stream after {junk} bytes of junk: spec={spec:?}");
+
+        let decoder = MpaDecoder::try_new(CodecParameters::new().for_codec(CODEC_TYPE_MP3), &DecoderOptions::default()).map_err(io::Error::other)?;
+        Ok(Self { reader, first, spec, decoder, frame, pcm: Vec::new(), history: VecDeque::new(), start: 0, pos: 0 })
+    }
+
+    #[inline]
+    fn block_align(&self) -> usize {
+        usize::from(self.spec.channels) * usize::from(self.spec.bytes_per_sample)
+    }
+
+    /// The frame after the history.
+    #[inline]
+    fn end(&self) -> u64 {
+        self.start + (self.history.len() / self.block_align()) as u64
+    }
+
+    /// Decodes the next frame into the history, returns `false` at the end of the stream.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        // frame 开头是下一个帧头，不对就逐字节重新同步
+        loop {
+            Self::fill(&mut self.reader, &mut self.frame, FrameHeader::LEN)?;
+            if self.frame.len() < FrameHeader::LEN {
+                return Ok(false);
+            }
+            if let Some(header) = Self::header(&self.frame)
+                && self.first.compatible(header)
+            {
+                Self::fill(&mut self.reader, &mut self.frame, header.size)?;
+                if self.frame.len() < header.size {
+                    return Ok(false);
+                }
+                self.pcm.clear();
+                if let Err(e) = decode_into(&mut self.decoder, &self.frame[..header.size], &mut self.pcm) {
+                    tracing::warn!("MPEG stream frame at {}: {e}", self.end());
+                }
+                self.pcm.resize(header.samples() as usize * self.block_align(), 0);
+                self.history.extend(&self.pcm);
+                self.frame.drain(..header.size);
+                break;
+            }
+            self.frame.remove(0);
+        }
+
+        let max = (Self::HISTORY * u64::from(self.spec.sample_rate)) as usize * self.block_align();
+        if self.history.len() > max {
+            let excess = (self.history.len() - max) / self.block_align();
+            self.history.drain(..excess * self.block_align());
+            self.start += excess as u64;
+        }
+        Ok(true)
+    }
+
+    /// The frames still in the history.
+    #[inline]
+    pub fn window(&self) -> Range<u64> {
+        self.start..self.end()
+    }
+}
+
+impl<R: Read> Decoder for Mp3Stream<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        decoder::UNKNOWN_FRAMES
+    }
+
+    fn reachable(&self) -> Range<u64> {
+        self.window()
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let block_align = self.block_align();
+        let spec = self.spec;
+        let buf = decoder::as_bytes(buf, spec)?;
+        let want = buf.len() / block_align;
+
+        let mut done = 0;
+        while done < want {
+            if self.pos == self.end() && !self.next_frame()? {
+                break;
+            }
+            let from = (self.pos - self.start) as usize * block_align;
+            let n = ((self.end() - self.pos) as usize).min(want - done);
+            for (d, s) in buf[done * block_align..(done + n) * block_align].iter_mut().zip(self.history.range(from..)) {
+                *d = *s;
+            }
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+
+    #[inline]
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        self.pos = frame.clamp(self.start, self.end());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// MPEG-1 layer III, 128 kbps, 44100 Hz, stereo, no CRC: 417 bytes per frame.
+    const HEADER: u32 = 0xfffb_9064;
+
+    fn frame() -> Vec<u8> {
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&HEADER.to_be_bytes());
+        frame
+    }
+
+    /// A frame with an Info tag claiming `frames` frames and the LAME encoder delay and padding.
+    fn info(frames: u32, delay: u32, padding: u32) -> Vec<u8> {
+        let mut frame = frame();
+        let mut tag = b"Info".to_vec();
+        tag.extend(1u32.to_be_bytes());
+        tag.extend(frames.to_be_bytes());
+        let mut lame = *b"LAME3.100\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        lame[21..].copy_from_slice(&(delay << 12 | padding).to_be_bytes()[1..]);
+        tag.extend(lame);
+        frame[4 + 32..][..tag.len()].copy_from_slice(&tag);
+        frame
+    }
+
+    #[test]
+    fn xing_delay_padding() {
+        let header = FrameHeader::parse(HEADER).unwrap();
+        assert_eq!(header.size, 417);
+        let xing = Xing::parse(&info(10, 576, 1000), header).unwrap();
+        assert_eq!(xing.frames, Some(10));
+        assert_eq!(xing.delay, 576 + DECODER_DELAY);
+        assert_eq!(xing.padding, 1000 - DECODER_DELAY);
+        // 填充比解码器延迟还短
+        assert_eq!(Xing::parse(&info(10, 576, 100), header).unwrap().padding, 0);
+        assert!(Xing::parse(&frame(), header).is_none());
+    }
+
+    #[test]
+    fn vbri_frames() {
+        let header = FrameHeader::parse(HEADER).unwrap();
+        let mut frame = frame();
+        let mut tag = b"VBRI".to_vec();
+        tag.extend([0, 1, 0, 0, 0, 75]);
+        tag.extend(123_456u32.to_be_bytes());
+        tag.extend(42u32.to_be_bytes());
+        frame[4 + 32..][..tag.len()].copy_from_slice(&tag);
+        assert_eq!(Xing::parse(&frame, header).unwrap().frames, Some(42));
+    }
+
+    /// Reads `decoder` to its end, returns how many frames it gave.
+    fn read_all(decoder: &mut Mp3Decoder<Cursor<&Vec<u8>>>) -> u64 {
+        let mut buf = vec![0i16; 1000 * 2];
+        let mut total = 0;
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                return total;
+            }
+            total += n as u64;
+        }
+    }
+
+    #[test]
+    fn probe_estimates_decoder_counts() {
+        // Info 里的帧数是错的，中间还夹着垃圾
+        let mut file = b"junk".to_vec();
+        file.extend(info(100, 576, 1000));
+        for i in 0..20 {
+            file.extend(frame());
+            if i == 9 {
+                file.extend([0; 33]);
+            }
+        }
+        let (spec, frames) = probe(Cursor::new(&file)).unwrap();
+        assert_eq!(spec.channels, 2);
+        assert_eq!(frames, 100 * 1152 - 1105 - 471);
+
+        // 解码器一开始也用估算的，读到结尾后按实际找到的帧数
+        let mut decoder = Mp3Decoder::new(Cursor::new(&file)).unwrap();
+        assert_eq!(decoder.frames(), frames);
+        assert_eq!(read_all(&mut decoder), 20 * 1152 - 1105 - 471);
+        assert_eq!(decoder.frames(), 20 * 1152 - 1105 - 471);
+        assert_eq!(decoder.index.len(), 20);
+    }
+
+    #[test]
+    fn cbr_estimate() {
+        let mut file = Vec::new();
+        for _ in 0..20 {
+            file.extend(frame());
+        }
+        file.extend(b"TAG");
+        file.extend([0; 125]);
+        let (_, frames) = probe(Cursor::new(&file)).unwrap();
+        assert_eq!(frames, 20 * 1152);
+
+        // 只扫到要读的地方
+        let mut decoder = Mp3Decoder::new(Cursor::new(&file)).unwrap();
+        decoder.seek(5 * 1152).unwrap();
+        assert_eq!(decoder.read(&mut [0i16; 2 * 1152]).unwrap(), 1152);
+        assert_eq!(decoder.index.len(), 7);
+        assert_eq!(read_all(&mut decoder), 14 * 1152);
+    }
+}
@@ -0,0 +1,821 @@
+use core::ops::Range;
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+use audiopus::{
+    Channels, SampleRate,
+    coder::{Decoder as OpusDecoder, GenericCtl},
+    packet::Packet as OpusPacket,
+};
+use hound::{SampleFormat, WavSpec};
+use symphonia_codec_vorbis::VorbisDecoder;
+use symphonia_core::{
+    audio::{AudioBufferRef, Signal},
+    codecs::{CODEC_TYPE_VORBIS, CodecParameters, Decoder as _, DecoderOptions},
+    formats::Packet,
+};
+
+use crate::{
+    decoder::{self, Decoder},
+    fmt_impl::Fmt,
+    stream::Stream,
+    util::PlayError,
+};
+
+const NOT_OGG: io::Error = io::const_error!(io::ErrorKind::InvalidData, "not an Ogg stream");
+const NO_STREAM: io::Error = io::const_error!(io::ErrorKind::InvalidData, "no Vorbis or Opus stream in the Ogg file");
+const BAD_HEADER: io::Error = io::const_error!(io::ErrorKind::InvalidData, "invalid Vorbis or Opus header");
+const UNSUPPORTED_MAPPING: io::Error = io::const_error!(io::ErrorKind::Unsupported, "only mono and stereo Opus streams (channel mapping family 0) are supported");
+
+/// Bisection stops once the range is this small (a few pages), and decodes on linearly from there.
+const LINEAR_SEEK: u64 = 16 << 10;
+/// How far from the end the last page is looked for at first.
+const TAIL_SCAN: u64 = 64 << 10;
+/// Opus needs 80 ms to converge after a seek.
+const OPUS_PREROLL: u64 = 3840;
+/// Longest Opus packet, 120 ms at 48 kHz.
+const OPUS_MAX_FRAMES: usize = 5760;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 0x8000_0000 != 0 { crc << 1 ^ 0x04c1_1db7 } else { crc << 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| crc << 8 ^ CRC_TABLE[(crc >> 24) as usize ^ usize::from(b)])
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PageHeader {
+    flags: u8, // 1 续接上一页的包，2 流开始，4 流结束
+    granule: Option<u64>, // 最后一个在本页结束的包之后的采样号，没有包在本页结束时为 None
+    serial: u32,
+    segments: usize,
+}
+
+impl PageHeader {
+    const LEN: usize = 27;
+
+    #[inline]
+    const fn continued(self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    #[inline]
+    const fn bos(self) -> bool {
+        self.flags & 2 != 0
+    }
+
+    #[inline]
+    const fn eos(self) -> bool {
+        self.flags & 4 != 0
+    }
+}
+
+/// Splits the pages of one logical stream back into packets.
+struct Demuxer<R> {
+    reader: BufReader<R>,
+    len: u64,
+    serial: Option<u32>, // None 时接受任何逻辑流
+    page: Vec<u8>, // 当前页 (页头之后的部分)
+    partial: Vec<u8>, // 跨页的包
+    resync: bool, // 定位之后，丢掉续接的半个包
+    chained: bool, // 直播流里接上了新的逻辑流，读到它的第一页为止
+}
+
+impl<R: Read + Seek> Demuxer<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        Ok(Self { reader: BufReader::new(reader), len, serial: None, page: Vec::new(), partial: Vec::new(), resync: false, chained: false })
+    }
+
+    /// A demuxer over a live stream, whose length is unknown and where one logical stream may follow another.
+    fn live(reader: R) -> Self {
+        Self { reader: BufReader::new(reader), len: u64::MAX, serial: None, page: Vec::new(), partial: Vec::new(), resync: false, chained: false }
+    }
+
+    /// Reads the page at the reader into `self.page`, `None` at the end or if there is no valid page here.
+    fn read_page(&mut self) -> io::Result<Option<PageHeader>> {
+        let mut head = [0; PageHeader::LEN];
+        match self.reader.read_exact(&mut head) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        if &head[..4] != b"OggS" || head[4] != 0 {
+            return Ok(None);
+        }
+        let segments = usize::from(head[26]);
+        self.page.resize(segments, 0);
+        if self.reader.read_exact(&mut self.page).is_err() {
+            return Ok(None);
+        }
+        let body = self.page.iter().map(|&l| usize::from(l)).sum::<usize>();
+        self.page.resize(segments + body, 0);
+        if self.reader.read_exact(&mut self.page[segments..]).is_err() {
+            return Ok(None);
+        }
+
+        // CRC 按校验和字段为 0 计算
+        let crc = u32::from_le_bytes([head[22], head[23], head[24], head[25]]);
+        head[22..26].fill(0);
+        if crc32(crc32(0, &head), &self.page) != crc {
+            return Ok(None);
+        }
+        let granule = u64::from_le_bytes(head[6..14].try_into().map_err(|_| NOT_OGG)?);
+        Ok(Some(PageHeader {
+            flags: head[5],
+            granule: (granule != u64::MAX).then_some(granule),
+            serial: u32::from_le_bytes([head[14], head[15], head[16], head[17]]),
+            segments,
+        }))
+    }
+
+    /// Finds the first valid page of the stream at or after `pos`, leaving the reader after it.
+    fn sync_from(&mut self, mut pos: u64) -> io::Result<Option<(u64, PageHeader)>> {
+        // 直播流不能定位到已读到的结尾之后
+        match self.reader.seek(SeekFrom::Start(pos)) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        };
+        let mut window = [0; 4];
+        loop {
+            let mut b = [0];
+            match self.reader.read_exact(&mut b) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                r => r?,
+            }
+            pos += 1;
+            window = [window[1], window[2], window[3], b[0]];
+            if &window == b"OggS" {
+                let at = pos - 4;
+                self.reader.seek(SeekFrom::Start(at))?;
+                match self.read_page()? {
+                    Some(header) if self.serial.is_none_or(|s| s == header.serial) => return Ok(Some((at, header))),
+                    Some(_) => pos = self.reader.stream_position()?, // 别的逻辑流，整页跳过
+                    None => {
+                        self.reader.seek(SeekFrom::Start(pos))?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Continues from the page at `pos`, dropping the packet it finishes.
+    fn goto(&mut self, pos: u64) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(pos))?;
+        self.partial.clear();
+        self.resync = true;
+        Ok(())
+    }
+
+    /// Reads the next page of the stream, returns it with the packets finished on it.
+    fn next(&mut self) -> io::Result<Option<(PageHeader, Vec<Vec<u8>>)>> {
+        let header = loop {
+            let here = self.reader.stream_position()?;
+            let (at, header) = match self.read_page()? {
+                Some(header) => (here, header),
+                None if here >= self.len => return Ok(None),
+                None => { // 损坏的页，找下一页
+                    let Some((at, header)) = self.sync_from(here + 1)? else { return Ok(None) };
+                    tracing::warn!("Ogg stream resynchronized at {at} (lost {} bytes)", at - here);
+                    self.partial.clear();
+                    self.resync = true;
+                    (at, header)
+                }
+            };
+            if self.serial.is_none_or(|s| s == header.serial) {
+                break header;
+            }
+            if header.bos() && self.len == u64::MAX {
+                // 直播流换了下一首歌，留着这一页重新读头
+                self.reader.seek(SeekFrom::Start(at))?;
+                self.serial = None;
+                self.chained = true;
+                return Ok(None);
+            }
+        };
+
+        let (lacing, mut body) = self.page.split_at(header.segments);
+        let mut drop = header.continued() && self.resync;
+        if !header.continued() {
+            self.partial.clear();
+        }
+        self.resync = false;
+        let mut packets = Vec::new();
+        for &l in lacing {
+            let (segment, rest) = body.split_at(usize::from(l));
+            body = rest;
+            if !drop {
+                self.partial.extend_from_slice(segment);
+            }
+            // 长度小于 255 的段结束一个包
+            if l < 255 {
+                if !drop {
+                    packets.push(core::mem::take(&mut self.partial));
+                }
+                drop = false;
+            }
+        }
+        Ok(Some((header, packets)))
+    }
+
+    /// Picks the first Vorbis or Opus stream and reads its headers, leaving the reader at its first audio page.
+    fn head(&mut self) -> io::Result<Head> {
+        let mut magic = [0; 4];
+        self.reader.read_exact(&mut magic)?;
+        if &magic != b"OggS" {
+            return Err(NOT_OGG);
+        }
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.headers()
+    }
+
+    /// Reads the headers of the first Vorbis or Opus stream from the reader on.
+    fn headers(&mut self) -> io::Result<Head> {
+        let (serial, mut head) = loop {
+            let Some((header, packets)) = self.next()? else { return Err(NO_STREAM) };
+            // 多路复用时所有流的第一页都在最前面
+            if !header.bos() {
+                return Err(NO_STREAM);
+            }
+            if let Some(head) = packets.first().and_then(|p| Head::identify(p)) {
+                break (header.serial, head);
+            }
+        };
+        self.serial = Some(serial);
+
+        let mut headers = Vec::new();
+        while headers.len() < head.header_packets() {
+            let Some((_, packets)) = self.next()? else { return Err(BAD_HEADER) };
+            headers.extend(packets);
+        }
+        if let Head::Vorbis { setup, .. } = &mut head {
+            let [comment, packet] = &headers[..] else { return Err(BAD_HEADER) };
+            if !comment.starts_with(b"\x03vorbis") || !packet.starts_with(b"\x05vorbis") {
+                return Err(BAD_HEADER);
+            }
+            setup.extend_from_slice(packet);
+        }
+        Ok(head)
+    }
+
+    /// Granule position of the last page of the stream.
+    fn last_granule(&mut self, first: u64) -> io::Result<u64> {
+        let mut end = self.len;
+        while end > first {
+            let mut at = end.saturating_sub(TAIL_SCAN).max(first);
+            let start = at;
+            let mut last = None;
+            while let Some((found, header)) = self.sync_from(at)?
+                && found < end
+            {
+                last = header.granule.or(last);
+                at = found + 1;
+            }
+            if let Some(granule) = last {
+                return Ok(granule);
+            }
+            end = start;
+        }
+        Ok(0)
+    }
+}
+
+/// What the first packet of a stream says.
+enum Head {
+    Vorbis { channels: u16, rate: u32, setup: Vec<u8> }, // 识别头之后接设置头，是 symphonia 要的 extra data
+    Opus { channels: u16, family: u8, pre_skip: u16, gain: i16 },
+}
+
+impl Head {
+    fn identify(packet: &[u8]) -> Option<Self> {
+        if packet.starts_with(b"\x01vorbis") && packet.len() >= 30 {
+            let rate = u32::from_le_bytes(packet[12..16].try_into().ok()?);
+            return Some(Self::Vorbis { channels: packet[11].into(), rate, setup: packet.to_vec() });
+        }
+        if packet.starts_with(b"OpusHead") && packet.len() >= 19 {
+            return Some(Self::Opus {
+                channels: packet[9].into(),
+                family: packet[18],
+                pre_skip: u16::from_le_bytes([packet[10], packet[11]]),
+                gain: i16::from_le_bytes([packet[16], packet[17]]),
+            });
+        }
+        None
+    }
+
+    /// Header packets after the identification one.
+    const fn header_packets(&self) -> usize {
+        match self {
+            Self::Vorbis { .. } => 2,
+            Self::Opus { .. } => 1,
+        }
+    }
+
+    const fn pre_skip(&self) -> u64 {
+        match self {
+            Self::Vorbis { .. } => 0,
+            Self::Opus { pre_skip, .. } => *pre_skip as u64,
+        }
+    }
+
+    const fn spec(&self) -> io::Result<WavSpec> {
+        let (channels, sample_rate) = match *self {
+            Self::Vorbis { channels, rate, .. } => (channels, rate),
+            Self::Opus { channels: channels @ (1 | 2), family: 0, .. } => (channels, 48000), // Opus 总是解码到 48 kHz
+            Self::Opus { .. } => return Err(UNSUPPORTED_MAPPING),
+        };
+        if channels == 0 || sample_rate == 0 {
+            return Err(BAD_HEADER);
+        }
+        Ok(WavSpec { channels, sample_rate, bits_per_sample: 32, bytes_per_sample: 4, sample_format: SampleFormat::Float })
+    }
+}
+
+/// Reads the spec and the number of frames from the headers and the last page.
+pub fn probe<R: Read + Seek>(reader: R) -> io::Result<(WavSpec, u64)> {
+    let mut demux = Demuxer::new(reader)?;
+    let head = demux.head()?;
+    let spec = head.spec()?;
+    let first = demux.reader.stream_position()?;
+    Ok((spec, demux.last_granule(first)?.saturating_sub(head.pre_skip())))
+}
+
+enum Codec {
+    Vorbis(Box<VorbisDecoder>),
+    Opus { decoder: OpusDecoder, buf: Vec<f32> },
+}
+
+impl Codec {
+    fn new(head: Head) -> io::Result<Self> {
+        Ok(match head {
+            Head::Vorbis { setup, .. } => {
+                let params = CodecParameters::new().for_codec(CODEC_TYPE_VORBIS).with_extra_data(setup.into_boxed_slice()).clone();
+                Self::Vorbis(Box::new(VorbisDecoder::try_new(&params, &DecoderOptions::default()).map_err(io::Error::other)?))
+            }
+            Head::Opus { channels, gain, .. } => {
+                let (channels, n) = if channels == 1 { (Channels::Mono, 1) } else { (Channels::Stereo, 2) };
+                let decoder = OpusDecoder::new(SampleRate::Hz48000, channels).map_err(io::Error::other)?;
+                // 头里的输出增益和 OPUS_SET_GAIN 一样是 Q7.8 dB
+                decoder.set_gain(gain.into()).map_err(io::Error::other)?;
+                Self::Opus { decoder, buf: vec![0.0; OPUS_MAX_FRAMES * n] }
+            }
+        })
+    }
+
+    /// Decodes a packet, appending its interleaved samples to `out`.
+    fn decode(&mut self, packet: &[u8], out: &mut Vec<f32>) -> io::Result<()> {
+        match self {
+            Self::Vorbis(decoder) => match decoder.decode(&Packet::new_from_slice(0, 0, 0, packet)) {
+                Ok(AudioBufferRef::F32(buf)) => {
+                    for f in 0..buf.frames() {
+                        for c in 0..buf.spec().channels.count() {
+                            out.push(buf.chan(c)[f]);
+                        }
+                    }
+                    Ok(())
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(io::Error::other(e)),
+            },
+            Self::Opus { decoder, buf } => {
+                let packet = OpusPacket::try_from(packet).map_err(io::Error::other)?;
+                let signals = buf.as_mut_slice().try_into().map_err(io::Error::other)?;
+                let n = decoder.decode_float(Some(packet), signals, false).map_err(io::Error::other)?;
+                out.extend_from_slice(&buf[..n * (buf.len() / OPUS_MAX_FRAMES)]);
+                Ok(())
+            }
+        }
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        match self {
+            Self::Vorbis(decoder) => decoder.reset(),
+            Self::Opus { decoder, .. } => decoder.reset_state().map_err(io::Error::other)?,
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the first Vorbis or Opus stream of an Ogg file to 32-bit float.
+///
+/// Pages are decoded lazily, each placed by its granule position; seeking bisects the pages.
+pub struct OggDecoder<R> {
+    demux: Demuxer<R>,
+    codec: Codec,
+    spec: WavSpec,
+    first: u64, // 第一个音频页的位置
+    pre_skip: u64,
+    preroll: u64,
+    frames: u64,
+    block: Vec<f32>, // 当前页解码后的交错采样
+    block_start: u64,
+    continuous: bool, // block 紧接着上一页，没有定位过
+    pos: u64,
+}
+
+impl<R: Read + Seek> OggDecoder<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut demux = Demuxer::new(reader)?;
+        let head = demux.head()?;
+        let spec = head.spec()?;
+        let first = demux.reader.stream_position()?;
+        let pre_skip = head.pre_skip();
+        let preroll = if matches!(head, Head::Opus { .. }) { OPUS_PREROLL } else { 0 };
+        let frames = demux.last_granule(first)?.saturating_sub(pre_skip);
+        tracing::info!("Ogg {} stream: {frames} frames, spec={spec:?}", if preroll == 0 { "Vorbis" } else { "Opus" });
+
+        demux.goto(first)?;
+        Ok(Self {
+            demux,
+            codec: Codec::new(head)?,
+            spec,
+            first,
+            pre_skip,
+            preroll,
+            frames,
+            block: Vec::new(),
+            block_start: 0,
+            continuous: false,
+            pos: 0,
+        })
+    }
+
+    /// Leaves the demuxer at a page ending at or before `target`, with the decoder reset.
+    fn seek_to(&mut self, target: u64) -> io::Result<()> {
+        let target = (target + self.pre_skip).saturating_sub(self.preroll);
+        let (mut lo, mut hi) = (self.first, self.demux.len);
+        while hi - lo > LINEAR_SEEK {
+            let mid = lo + (hi - lo) / 2;
+            // 找 mid 之后第一个有采样号的页
+            let mut at = mid;
+            let found = loop {
+                match self.demux.sync_from(at)? {
+                    Some((found, header)) if found < hi => match header.granule {
+                        Some(granule) => break Some((found, granule)),
+                        None => at = found + 1,
+                    },
+                    _ => break None,
+                }
+            };
+            match found {
+                Some((at, granule)) if granule <= target => lo = at,
+                _ => hi = mid,
+            }
+        }
+        self.demux.goto(lo)?;
+        self.codec.reset()?;
+        self.block.clear();
+        self.block_start = 0;
+        self.continuous = false;
+        Ok(())
+    }
+
+    /// Decodes pages up to one with a granule position into `self.block`, returns `false` at the end.
+    fn decode_page(&mut self) -> io::Result<bool> {
+        let channels = usize::from(self.spec.channels);
+        let prev_end = self.block_start + (self.block.len() / channels) as u64;
+        self.block.clear();
+        loop {
+            let Some((header, packets)) = self.demux.next()? else { return Ok(false) };
+            for packet in packets {
+                if let Err(e) = self.codec.decode(&packet, &mut self.block) {
+                    tracing::warn!("Ogg packet before granule {:?}: {e}", header.granule);
+                }
+            }
+            let Some(granule) = header.granule else { continue };
+
+            // 页尾的采样号定位整页；最后一页可能比解出来的短
+            let n = (self.block.len() / channels) as u64;
+            let end = granule.cast_signed() - self.pre_skip.cast_signed();
+            let mut start = end - n.cast_signed();
+            if header.eos() && self.continuous && prev_end.cast_signed() <= end {
+                start = prev_end.cast_signed();
+                self.block.truncate((end - start).unsigned_abs() as usize * channels);
+            }
+            if self.continuous && start > prev_end.cast_signed() {
+                // 跳过了损坏的页，以静音补齐
+                let gap = (start - prev_end.cast_signed()).unsigned_abs() as usize;
+                self.block.splice(..0, core::iter::repeat_n(0.0, gap * channels));
+                start = prev_end.cast_signed();
+            }
+            if start < 0 { // Opus 的 pre-skip
+                self.block.drain(..(start.unsigned_abs() as usize * channels).min(self.block.len()));
+                start = 0;
+            }
+            self.block_start = start.cast_unsigned();
+            self.continuous = true;
+            return Ok(true);
+        }
+    }
+
+    /// Makes `self.block` hold `self.pos`, returns `false` at the end.
+    fn locate(&mut self) -> io::Result<bool> {
+        let channels = usize::from(self.spec.channels);
+        let end = self.block_start + (self.block.len() / channels) as u64;
+        if (self.block_start..end).contains(&self.pos) {
+            return Ok(true);
+        }
+        // 往回或跳过一秒以上才真正定位，否则接着往下解码
+        if self.pos < self.block_start || self.pos - end > u64::from(self.spec.sample_rate) {
+            self.seek_to(self.pos)?;
+        }
+        loop {
+            if !self.decode_page()? {
+                return Ok(false);
+            }
+            let end = self.block_start + (self.block.len() / channels) as u64;
+            if self.pos < end {
+                return Ok(self.pos >= self.block_start);
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> Decoder for OggDecoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let channels = usize::from(self.spec.channels);
+        let spec = self.spec;
+        let buf = decoder::as_bytes(buf, spec)?;
+        let want = (buf.len() / (4 * channels)).min(self.frames.saturating_sub(self.pos) as usize);
+
+        let mut done = 0;
+        while done < want && self.locate()? {
+            let from = (self.pos - self.block_start) as usize * channels;
+            let n = ((self.block.len() - from) / channels).min(want - done);
+            for (out, s) in buf[done * channels * 4..].chunks_exact_mut(4).zip(&self.block[from..from + n * channels]) {
+                out.copy_from_slice(&s.to_le_bytes());
+            }
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+
+    #[inline]
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        self.pos = frame.min(self.frames);
+        Ok(())
+    }
+}
+
+/// Decodes a live Ogg stream as it comes, keeping the last [`OggStream::HISTORY`] seconds
+/// of samples so that it can seek back a little (to what is still being heard).
+///
+/// Each song of a radio is a logical stream of its own, played on as long as the spec stays the same.
+pub struct OggStream<R> {
+    demux: Demuxer<Stream<R>>,
+    codec: Codec,
+    spec: WavSpec,
+    skip: u64, // 当前逻辑流开头还要丢掉的帧数 (Opus 的 pre-skip)
+    block: Vec<f32>, // 刚解码的一页，每页复用
+    history: VecDeque<f32>, // 最近解码出的交错采样，第 start 帧起
+    start: u64,
+    pos: u64,
+}
+
+impl<R: Read> OggStream<R> {
+    const HISTORY: u64 = 10;
+
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut demux = Demuxer::live(Stream::new(reader));
+        let head = demux.head()?;
+        let spec = head.spec()?;
+        tracing::info!("Ogg {} stream: spec={spec:?}", if matches!(head, Head::Opus { .. }) { "Opus" } else { "Vorbis" });
+        Ok(Self { demux, skip: head.pre_skip(), codec: Codec::new(head)?, spec, block: Vec::new(), history: VecDeque::new(), start: 0, pos: 0 })
+    }
+
+    /// The frame after the history.
+    #[inline]
+    fn end(&self) -> u64 {
+        self.start + (self.history.len() / usize::from(self.spec.channels)) as u64
+    }
+
+    /// Decodes the next page into the history, returns `false` at the end of the stream.
+    fn next_page(&mut self) -> io::Result<bool> {
+        let channels = usize::from(self.spec.channels);
+        let packets = loop {
+            if let Some((_, packets)) = self.demux.next()? {
+                break packets;
+            }
+            if !self.demux.chained {
+                return Ok(false);
+            }
+            self.demux.chained = false;
+            let head = self.demux.headers()?;
+            if head.spec()? != self.spec {
+                tracing::warn!("Ogg stream changed to {:?}, ending it here.", head.spec());
+                return Ok(false);
+            }
+            self.skip = head.pre_skip();
+            self.codec = Codec::new(head)?;
+        };
+
+        self.block.clear();
+        for packet in packets {
+            if let Err(e) = self.codec.decode(&packet, &mut self.block) {
+                tracing::warn!("Ogg stream packet at {}: {e}", self.end());
+            }
+        }
+        let skip = (self.skip as usize * channels).min(self.block.len());
+        self.skip -= (skip / channels) as u64;
+        self.history.extend(&self.block[skip..]);
+
+        let max = (Self::HISTORY * u64::from(self.spec.sample_rate)) as usize * channels;
+        if self.history.len() > max {
+            let excess = (self.history.len() - max) / channels;
+            self.history.drain(..excess * channels);
+            self.start += excess as u64;
+        }
+        Ok(true)
+    }
+
+    /// The frames still in the history.
+    #[inline]
+    pub fn window(&self) -> Range<u64> {
+        self.start..self.end()
+    }
+}
+
+impl<R: Read> Decoder for OggStream<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        decoder::UNKNOWN_FRAMES
+    }
+
+    fn reachable(&self) -> Range<u64> {
+        self.window()
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let channels = usize::from(self.spec.channels);
+        let spec = self.spec;
+        let buf = decoder::as_bytes(buf, spec)?;
+        let want = buf.len() / (4 * channels);
+
+        let mut done = 0;
+        while done < want {
+            if self.pos == self.end() && !self.next_page()? {
+                break;
+            }
+            let from = (self.pos - self.start) as usize * channels;
+            let n = ((self.end() - self.pos) as usize).min(want - done);
+            for (out, s) in buf[done * channels * 4..].chunks_exact_mut(4).zip(self.history.range(from..from + n * channels)) {
+                out.copy_from_slice(&s.to_le_bytes());
+            }
+            done += n;
+            self.pos += n as u64;
+        }
+        Ok(done)
+    }
+
+    #[inline]
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        self.pos = frame.clamp(self.start, self.end());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Lacing values of a packet of `len` bytes, without the terminating one if it goes on in the next page.
+    fn lace(len: usize, ends: bool) -> Vec<u8> {
+        let mut lacing = vec![255; len / 255];
+        if ends {
+            lacing.push((len % 255) as u8);
+        }
+        lacing
+    }
+
+    /// One page with a valid checksum, also used to build Ogg files in other tests.
+    pub fn page(flags: u8, granule: u64, serial: u32, lacing: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(flags);
+        page.extend(granule.to_le_bytes());
+        page.extend(serial.to_le_bytes());
+        page.extend([0; 8]); // 页序号与校验和
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        page.extend(body);
+        let crc = crc32(0, &page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    #[test]
+    fn crc() {
+        // CRC-32/CKSUM 去掉最后的取反
+        assert_eq!(crc32(0, b"123456789"), !0x765e_7680);
+    }
+
+    #[test]
+    fn packets_across_pages() {
+        let (a, b, c) = ([1; 300], [2; 265], [3; 5]);
+        let first = page(2, 0, 1, &[lace(300, true), lace(255, false)].concat(), &[&a[..], &b[..255]].concat());
+        let other = page(2, 0, 2, &[4], b"junk");
+        let last = page(1, 100, 1, &[10, 5], &[&b[255..], &c[..]].concat());
+
+        // 别的逻辑流整页跳过
+        let mut demux = Demuxer::new(Cursor::new([&first[..], &other, &last].concat())).unwrap();
+        demux.serial = Some(1);
+        assert_eq!(demux.next().unwrap().unwrap().1, [a.to_vec()]);
+        let (header, packets) = demux.next().unwrap().unwrap();
+        assert_eq!((header.granule, packets), (Some(100), vec![b.to_vec(), c.to_vec()]));
+        assert!(demux.next().unwrap().is_none());
+
+        // 中间的页损坏时，续接的半个包也不要了
+        let mut broken = page(0, 50, 1, &[3], b"bad");
+        broken[30] ^= 1;
+        let mut demux = Demuxer::new(Cursor::new([&first[..], &broken, &last].concat())).unwrap();
+        demux.serial = Some(1);
+        demux.next().unwrap();
+        assert_eq!(demux.next().unwrap().unwrap().1, [c.to_vec()]);
+    }
+
+    #[test]
+    fn probe_opus() {
+        // 前面还有一个不认识的流
+        let mut head = b"OpusHead\x01\x02".to_vec();
+        head.extend(312u16.to_le_bytes());
+        head.extend(48000u32.to_le_bytes());
+        head.extend([0; 3]);
+        let file = [
+            page(2, 0, 7, &[7], b"\x80theora"),
+            page(2, 0, 9, &[19], &head),
+            page(0, 0, 9, &[8], b"OpusTags"),
+            page(0, 9600, 9, &[3], &[0; 3]),
+            page(0, u64::MAX, 7, &[3], &[0; 3]),
+            page(4, 48000 + 312, 9, &[3], &[0; 3]),
+            page(4, 1 << 40, 7, &[3], &[0; 3]),
+        ]
+        .concat();
+        let (spec, frames) = probe(Cursor::new(file)).unwrap();
+        assert_eq!((spec.channels, spec.sample_rate, spec.sample_format), (2, 48000, SampleFormat::Float));
+        assert_eq!(frames, 48000);
+    }
+
+    #[test]
+    fn chained_live_stream() {
+        let mut head = b"OpusHead\x01\x02".to_vec();
+        head.extend(312u16.to_le_bytes());
+        head.extend(48000u32.to_le_bytes());
+        head.extend([0; 3]);
+        // 每首歌一个逻辑流，各两页 20 ms 的包 (CELT 全频带、立体声、丢帧补齐)
+        let song = |serial| {
+            [
+                page(2, 0, serial, &[19], &head),
+                page(0, 0, serial, &[8], b"OpusTags"),
+                page(0, 960, serial, &[1], &[0xfc]),
+                page(4, 1920, serial, &[1], &[0xfc]),
+            ]
+            .concat()
+        };
+        let mut stream = OggStream::new(Cursor::new([song(1), song(2)].concat())).unwrap();
+        assert_eq!(stream.frames(), decoder::UNKNOWN_FRAMES);
+
+        let mut buf = vec![0.0f32; 2 * 4096];
+        let mut total = 0;
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        assert_eq!(total, 2 * (1920 - 312));
+        assert_eq!(stream.window(), 0..total as u64);
+    }
+}
@@ -0,0 +1,118 @@
+use core::ops::Range;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use hound::WavSpec;
+
+use crate::{
+    decoder::{self, Decoder},
+    fmt_impl::Fmt,
+    stream::Stream,
+    util::PlayError,
+};
+
+/// How the stored samples differ from the WAV layout [`crate::util::cvt_format`] knows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Layout {
+    pub big_endian: bool,
+    pub signed8: bool, // 8 位采样是有符号的 (WAV 的是无符号的)
+}
+
+/// Reads interleaved PCM samples stored at `begin..`, converting them to the WAV layout on the fly.
+pub struct PcmDecoder<R> {
+    reader: R,
+    spec: WavSpec,
+    layout: Layout,
+    begin: u64,
+    frames: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> PcmDecoder<R> {
+    pub fn new(mut reader: R, spec: WavSpec, layout: Layout, begin: u64, frames: u64) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(begin))?;
+        Ok(Self { reader, spec, layout, begin, frames, pos: 0 })
+    }
+
+    #[inline]
+    fn block_align(&self) -> u64 {
+        u64::from(self.spec.channels) * u64::from(self.spec.bytes_per_sample)
+    }
+}
+
+impl<R: Read> PcmDecoder<Stream<R>> {
+    /// The frames still in the window of the stream.
+    pub fn window(&self) -> Range<u64> {
+        let block_align = self.block_align();
+        let window = self.reader.window();
+        let start = window.start.saturating_sub(self.begin).div_ceil(block_align);
+        start..(window.end.saturating_sub(self.begin) / block_align).max(start)
+    }
+}
+
+impl<R: Read + Seek> Decoder for PcmDecoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let block_align = self.block_align();
+        let bytes = usize::from(self.spec.bytes_per_sample);
+        let buf = decoder::as_bytes(buf, self.spec)?;
+        let n = (buf.len() as u64 / block_align).min(self.frames - self.pos);
+        let n = decoder::read_frames(&mut self.reader, &mut buf[..(n * block_align) as usize], block_align)?;
+        let buf = &mut buf[..(n * block_align) as usize];
+        match (bytes, self.layout) {
+            (1, Layout { signed8: true, .. }) => buf.iter_mut().for_each(|b| *b ^= 0x80),
+            (2.., Layout { big_endian: true, .. }) => buf.chunks_exact_mut(bytes).for_each(<[u8]>::reverse),
+            _ => (),
+        }
+        self.pos += n;
+        Ok(n as usize)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        let frame = frame.min(self.frames);
+        self.reader.seek(SeekFrom::Start(self.begin + frame * self.block_align()))?;
+        self.pos = frame;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use hound::SampleFormat;
+
+    use super::*;
+
+    #[test]
+    fn big_endian_to_wav_layout() {
+        let spec = WavSpec { channels: 2, sample_rate: 8000, bits_per_sample: 16, bytes_per_sample: 2, sample_format: SampleFormat::Int };
+        let data = [0xaa, 0x12, 0x34, 0xff, 0xfe, 0x00, 0x01, 0x80, 0x00];
+        let mut decoder = PcmDecoder::new(Cursor::new(data), spec, Layout { big_endian: true, signed8: false }, 1, 2).unwrap();
+        let mut buf = [0i16; 8];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..4], [0x1234, -2, 1, i16::MIN]);
+
+        decoder.seek(1).unwrap();
+        assert_eq!(decoder.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[..2], [1, i16::MIN]);
+    }
+
+    #[test]
+    fn signed_8_bit() {
+        // WAV 的 8 位是无符号的，有符号的要翻转最高位
+        let spec = WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 8, bytes_per_sample: 1, sample_format: SampleFormat::Int };
+        let mut decoder = PcmDecoder::new(Cursor::new([0x80, 0xff, 0x00, 0x7f]), spec, Layout { big_endian: true, signed8: true }, 0, 4).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 4);
+        assert_eq!(buf, [0x00, 0x7f, 0x80, 0xff]);
+    }
+}
@@ -0,0 +1,155 @@
+use core::str::FromStr;
+use std::{
+    fs,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use hound::{SampleFormat, WavSpec};
+
+use crate::{
+    pcm::{Layout, PcmDecoder},
+    util::cvt_format,
+};
+
+const BAD_FORMAT: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "raw PCM format should look like s16le,2,48000");
+const NO_FORMAT: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "raw PCM file without a format (see --raw or the .fmt sidecar)");
+
+/// Extension of the sidecar file giving the format of a raw PCM file.
+pub const SIDECAR: &str = "fmt";
+
+/// How the samples of a headerless PCM file are stored, e.g. `s16le,2,48000`.
+///
+/// Sample formats are `u8`, `s8`, `s16`, `s24` (in 4 bytes), `s24_3`, `s32`,
+/// `f32` and `f64`, followed by `le` (default) or `be`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawFormat {
+    pub spec: WavSpec,
+    pub layout: Layout,
+}
+
+impl RawFormat {
+    #[inline]
+    pub fn block_align(self) -> u64 {
+        u64::from(self.spec.channels) * u64::from(self.spec.bytes_per_sample)
+    }
+}
+
+impl FromStr for RawFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut fields = s.trim().split(',').map(str::trim);
+        let (Some(sample), Some(channels), Some(rate), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            return Err(BAD_FORMAT);
+        };
+        let sample = sample.to_ascii_lowercase();
+        let (name, big_endian) = match sample.strip_suffix("be") {
+            Some(name) => (name, true),
+            None => (sample.strip_suffix("le").unwrap_or(&sample), false),
+        };
+        let (sample_format, bits_per_sample, bytes_per_sample) = match name {
+            "u8" | "s8" => (SampleFormat::Int, 8, 1),
+            "s16" => (SampleFormat::Int, 16, 2),
+            "s24" => (SampleFormat::Int, 24, 4), // 与 ALSA 的 S24_LE 一样放在 4 字节的低位
+            "s24_3" => (SampleFormat::Int, 24, 3),
+            "s32" => (SampleFormat::Int, 32, 4),
+            "f32" => (SampleFormat::Float, 32, 4),
+            "f64" => (SampleFormat::Float, 64, 8),
+            _ => return Err(BAD_FORMAT),
+        };
+        let channels = channels.parse().ok().filter(|&c| c != 0).ok_or(BAD_FORMAT)?;
+        let sample_rate = rate.parse().ok().filter(|&r| r != 0).ok_or(BAD_FORMAT)?;
+        let spec = WavSpec { channels, sample_rate, bits_per_sample, bytes_per_sample, sample_format };
+        // 换成 WAV 的布局后必须是能直接播放的格式
+        cvt_format(spec).map_err(|_| BAD_FORMAT)?;
+        Ok(Self { spec, layout: Layout { big_endian, signed8: name == "s8" } })
+    }
+}
+
+/// Whether `path` is named like a headerless PCM file.
+pub fn is_raw(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pcm") || e.eq_ignore_ascii_case("raw"))
+}
+
+/// The sidecar of `path`, the same name with `.fmt` appended.
+fn sidecar(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(SIDECAR);
+    PathBuf::from(name)
+}
+
+/// The format in the sidecar of `path` if there is one, `default` otherwise.
+pub fn format_of(path: &Path, default: Option<RawFormat>) -> io::Result<RawFormat> {
+    match fs::read_to_string(sidecar(path)) {
+        Ok(s) => s.parse(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => default.ok_or(NO_FORMAT),
+        Err(e) => Err(e),
+    }
+}
+
+/// Number of whole frames in a raw PCM file of `len` bytes.
+pub fn frames(len: u64, format: RawFormat) -> u64 {
+    if !len.is_multiple_of(format.block_align()) {
+        tracing::warn!("Raw PCM file ends with \x1b[36m{}\x1b[0m bytes of a partial frame, ignored.", len % format.block_align());
+    }
+    len / format.block_align()
+}
+
+/// Opens a raw PCM file as `format`.
+pub fn open<R: Read + Seek>(mut reader: R, format: RawFormat) -> io::Result<PcmDecoder<BufReader<R>>> {
+    let frames = frames(reader.seek(SeekFrom::End(0))?, format);
+    tracing::info!("Raw PCM data: {frames} frames, spec={:?}, {:?}", format.spec, format.layout);
+    PcmDecoder::new(BufReader::new(reader), format.spec, format.layout, 0, frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_format() {
+        let f: RawFormat = " S16LE, 2 ,48000\n".parse().unwrap();
+        assert_eq!((f.spec.channels, f.spec.sample_rate, f.spec.bits_per_sample), (2, 48000, 16));
+        assert!(!f.layout.big_endian);
+        assert_eq!(f.block_align(), 4);
+
+        let f: RawFormat = "s24_3be,1,96000".parse().unwrap();
+        assert_eq!((f.spec.bits_per_sample, f.spec.bytes_per_sample), (24, 3));
+        assert!(f.layout.big_endian);
+
+        // 不写字节序时是小端
+        let f: RawFormat = "s8,1,8000".parse().unwrap();
+        assert!(f.layout.signed8 && !f.layout.big_endian);
+        assert!(!"u8,1,8000".parse::<RawFormat>().unwrap().layout.signed8);
+        assert_eq!("f64be,6,44100".parse::<RawFormat>().unwrap().block_align(), 48);
+
+        for bad in ["", "s16le", "s16le,2", "s16le,2,48000,1", "s12le,2,48000", "s16le,0,48000", "s16le,2,0", "s16le,x,48000"] {
+            assert!(bad.parse::<RawFormat>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn sidecar_overrides() {
+        let dir = std::env::temp_dir().join(format!("player-raw-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.pcm");
+        let default = "s16le,2,44100".parse().ok();
+
+        assert_eq!(format_of(&path, default).unwrap(), default.unwrap());
+        assert!(format_of(&path, None).is_err());
+        fs::write(dir.join("a.pcm.fmt"), "f32le,1,16000\n").unwrap();
+        assert_eq!(format_of(&path, default).unwrap().spec.sample_rate, 16000);
+        fs::write(dir.join("a.pcm.fmt"), "nonsense").unwrap();
+        assert!(format_of(&path, default).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn partial_frame() {
+        let f = "s24_3le,2,48000".parse().unwrap();
+        assert_eq!(frames(600, f), 100);
+        assert_eq!(frames(605, f), 100);
+    }
+}
@@ -4,7 +4,13 @@ use crate::fmt_impl::Fmt;
 
 pub const BLOCK_SIZE: usize = 1024;
 pub const ADDITION: usize = 64;
-pub const MAX_BUFFER_SIZE: usize = buffer_size(4);
+pub const MAX_BUFFER_SIZE: usize = buffer_size(MAX_SPEED);
+
+/// Playback speed in hundredths (fixed point), `SPEED_ONE` being the normal speed.
+pub type Speed = u16;
+pub const SPEED_ONE: Speed = 100;
+pub const MIN_SPEED: Speed = 50;
+pub const MAX_SPEED: Speed = 200;
 
 pub const FRAME_LENGTH: usize = BLOCK_SIZE * 2;
 
@@ -35,18 +41,25 @@ fn correlate(x: &[f64], y: &[f64], out: &mut [f64]) {
 }
 
 #[inline(always)]
-pub const fn buffer_size(multiplier: u8) -> usize {
-    one_time_consume(multiplier) + FRAME_LENGTH + ADDITION
+pub const fn buffer_size(speed: Speed) -> usize {
+    one_time_consume(speed) + FRAME_LENGTH + ADDITION
 }
 
 #[inline(always)]
 /// effectively (`BLOCK_SIZE` * speed)
-pub const fn one_time_consume(multiplier: u8) -> usize {
-    multiplier as usize * const { BLOCK_SIZE / 2 }
+pub const fn one_time_consume(speed: Speed) -> usize {
+    speed as usize * BLOCK_SIZE / SPEED_ONE as usize
+}
+
+/// Input frames consumed while `frames` are produced at `speed`, rounded like [`one_time_consume`]
+/// so that the position derived from it keeps up with [`process`].
+#[inline(always)]
+pub const fn consumed(speed: Speed, frames: i64) -> i64 {
+    frames * one_time_consume(speed).cast_signed() as i64 / BLOCK_SIZE.cast_signed() as i64
 }
 
-fn compute_step(src: &[f64; MAX_BUFFER_SIZE], multiplier: u8) -> usize {
-    let m = one_time_consume(multiplier);
+fn compute_step(src: &[f64; MAX_BUFFER_SIZE], speed: Speed) -> usize {
+    let m = one_time_consume(speed);
     let ref_part = src[BLOCK_SIZE..BLOCK_SIZE + FRAME_LENGTH].as_array::<FRAME_LENGTH>().unwrap();
     let slide_window = &src[m - ADDITION / 2..m + FRAME_LENGTH + ADDITION / 2];
     let mut result = [0.0; ADDITION + 1];
@@ -57,7 +70,7 @@ fn compute_step(src: &[f64; MAX_BUFFER_SIZE], multiplier: u8) -> usize {
     argmax
 }
 
-fn process_channel(src: &[f64; MAX_BUFFER_SIZE], multiplier: u8, step: usize, dst: &mut [f64; BLOCK_SIZE]){
+fn process_channel(src: &[f64; MAX_BUFFER_SIZE], speed: Speed, step: usize, dst: &mut [f64; BLOCK_SIZE]){
 
     let overlap_part1 = src[..FRAME_LENGTH].as_array::<FRAME_LENGTH>().unwrap();
     let overlap_part2 = src[step..step + FRAME_LENGTH].as_array::<FRAME_LENGTH>().unwrap();
@@ -68,15 +81,14 @@ fn process_channel(src: &[f64; MAX_BUFFER_SIZE], multiplier: u8, step: usize, ds
 }
 
 /// (ret: 输入消耗量，输出写入量)
-pub fn process<S: Fmt>(mut r#in: &[S], channels: usize, multiplier: u8, mut out: &mut [S]) -> (usize, usize) {
-    if multiplier == 2 { // fast path, without interleave/transpose
+pub fn process<S: Fmt>(mut r#in: &[S], channels: usize, speed: Speed, mut out: &mut [S]) -> (usize, usize) {
+    if speed == SPEED_ONE { // fast path, without interleave/transpose
         let l = r#in.len().min(out.len());
         out[..l].copy_from_slice(&r#in[..l]);
         return (l, l);
     }
 
-    let n = buffer_size(multiplier);
-    let m = one_time_consume(multiplier);
+    let n = buffer_size(speed);
 
     let mut v = [0f64; MAX_BUFFER_SIZE];
     let mut scratch = [0f64; BLOCK_SIZE];
@@ -94,7 +106,7 @@ pub fn process<S: Fmt>(mut r#in: &[S], channels: usize, multiplier: u8, mut out:
             for j in 0..n {
                 v[j] = unsafe { block.get_unchecked(j * channels + i) }.to_f64();
             }
-            consume_now += compute_step(&v, multiplier);
+            consume_now += compute_step(&v, speed);
         }
         consume_now /= channels;
 
@@ -102,7 +114,7 @@ pub fn process<S: Fmt>(mut r#in: &[S], channels: usize, multiplier: u8, mut out:
             for j in 0..n {
                 v[j] = unsafe { block.get_unchecked(j * channels + i) }.to_f64();
             }
-            process_channel(&v, multiplier, consume_now, &mut scratch);
+            process_channel(&v, speed, consume_now, &mut scratch);
             for j in 0..BLOCK_SIZE {
                 *unsafe { out.get_unchecked_mut(j * channels + i) } = S::from_f64(scratch[j]);
             }
@@ -117,3 +129,44 @@ pub fn process<S: Fmt>(mut r#in: &[S], channels: usize, multiplier: u8, mut out:
 
     (consume, produce)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consume_per_block() {
+        assert_eq!(one_time_consume(MIN_SPEED), BLOCK_SIZE / 2);
+        assert_eq!(one_time_consume(SPEED_ONE), BLOCK_SIZE);
+        assert_eq!(one_time_consume(MAX_SPEED), BLOCK_SIZE * 2);
+        // 1.33 倍每块取整成 1361 帧，换算位置也按 1361 算
+        assert_eq!(one_time_consume(133), 1361);
+        assert_eq!(consumed(133, 1024 * 1000), 1_361_000);
+        assert_eq!(consumed(67, -2048), -1372);
+    }
+
+    #[test]
+    fn process_speeds() {
+        let input: Vec<i16> = (0..48_000 * 2).map(|i| ((f64::from(i / 2) * 0.05).sin() * 8000.0) as i16).collect();
+        let mut out = vec![0i16; input.len() * 3];
+        let (consume, produce) = process(&input, 2, SPEED_ONE, &mut out);
+        assert_eq!((consume, produce), (input.len(), input.len()));
+        assert_eq!(out[..produce], input);
+
+        for speed in [MIN_SPEED, 85, 133, MAX_SPEED] {
+            let (mut c, mut p) = (0, 0);
+            loop {
+                let (consume, produce) = process(&input[c..], 2, speed, &mut out[p..]);
+                if produce == 0 {
+                    break;
+                }
+                c += consume;
+                p += produce;
+            }
+            // 每块的偏移在相关搜索的范围内
+            let blocks = p / (2 * BLOCK_SIZE);
+            let expected = blocks * one_time_consume(speed) * 2;
+            assert!(c.abs_diff(expected) <= blocks * ADDITION, "{speed}: consumed {c}, expected {expected}");
+        }
+    }
+}
@@ -0,0 +1,633 @@
+use core::{any::Any, time::Duration};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::sleep,
+    time::Instant,
+};
+
+use alsa::{
+    Direction, Mixer, PCM, ValueOr,
+    card,
+    device_name::HintIter,
+    mixer::Selem,
+    pcm::{Access, Format, Frames, HwParams, IO, State},
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::{
+    fmt_impl::Fmt,
+    resample::Quality,
+    util::{Handle, PlayError, UnsupportedFormatError, cvt_err},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SinkKind {
+    /// ALSA PCM device
+    #[default]
+    Alsa,
+    /// Discard all frames, paced in real time
+    Null,
+    /// Record the frames into WAV files, as fast as possible
+    Wav,
+}
+
+#[derive(Clone, Debug)]
+pub struct SinkConfig {
+    pub kind: SinkKind,
+    /// ALSA PCM device name
+    pub device: String,
+    /// Directory of the [`SinkKind::Wav`] recordings
+    pub output: Option<PathBuf>,
+    /// Requested period time (μs), driver default if `None`
+    pub period_time: Option<u32>,
+    /// Requested buffer time (μs), driver default if `None`
+    pub buffer_time: Option<u32>,
+    /// Fixed output rate (Hz), follows the source if `None`
+    pub rate: Option<u32>,
+    /// Resampler quality, used when the output rate differs from the source
+    pub quality: Quality,
+    /// Length (ms) of the crossfade into the next song, 0 to play them back to back
+    pub crossfade: u32,
+    /// Length (ms) of the fade when skipping to another song, 0 for a hard cut
+    pub skip_fade: u32,
+    /// Length (ms) of the gain ramps around pause, resume, seek and stop, 0 for a hard cut
+    pub ramp: u32,
+    file: Option<PathBuf>,
+}
+
+impl SinkConfig {
+    #[inline]
+    pub const fn new(
+        kind: SinkKind,
+        device: String,
+        output: Option<PathBuf>,
+        period_time: Option<u32>,
+        buffer_time: Option<u32>,
+        rate: Option<u32>,
+        quality: Quality,
+    ) -> Self {
+        Self { kind, device, output, period_time, buffer_time, rate, quality, crossfade: 0, skip_fade: 0, ramp: 0, file: None }
+    }
+
+    /// The output rate for a source of `spec`.
+    #[inline]
+    pub fn rate_for(&self, spec: WavSpec) -> u32 {
+        self.rate.unwrap_or(spec.sample_rate)
+    }
+
+    /// The configuration used to play the song at `path`.
+    pub fn for_song(&self, path: &Path) -> Self {
+        let file = self.output.as_ref().map(|dir| {
+            let mut file = dir.join(path.file_stem().unwrap_or(path.as_os_str()));
+            file.set_extension("wav");
+            file
+        });
+        Self { file, ..self.clone() }
+    }
+}
+
+/// What a sink actually accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceSpec {
+    pub format: Format,
+    pub rate: u32,
+    pub channels: u16,
+}
+
+impl DeviceSpec {
+    #[inline]
+    pub const fn new(spec: WavSpec, format: Format) -> Self {
+        Self { format, rate: spec.sample_rate, channels: spec.channels }
+    }
+}
+
+/// Object-safe part of [`AudioSink`], for a parked sink of unknown type.
+trait Drain: Any + Send {
+    fn drain_dyn(&mut self) -> Result<(), PlayError>;
+}
+
+impl<K: AudioSink + Send + 'static> Drain for K {
+    #[inline]
+    fn drain_dyn(&mut self) -> Result<(), PlayError> {
+        self.drain()
+    }
+}
+
+struct Parked {
+    sink: Box<dyn Drain>,
+    spec: DeviceSpec,
+    file: Option<PathBuf>,
+    fading: bool,
+}
+
+impl Parked {
+    /// Lets the tail play out before the device is closed.
+    fn finish(mut self) {
+        if let Err(e) = self.sink.drain_dyn() {
+            tracing::warn!("drain: {e}");
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    parked: Option<Parked>,
+    generation: u64, // 停放的次数，分辨到期的是不是同一个
+    pending: Option<Handle>, // 正在等待这个被切走的播放器留下设备
+}
+
+/// An open sink left behind by a song that finished reading, still playing
+/// its tail, for the next song to continue on without reopening the device.
+#[derive(Clone, Default)]
+pub struct Handoff(Arc<(Mutex<Slot>, Condvar)>);
+
+impl Handoff {
+    /// How long [`Handoff::take`] waits for a [`Handoff::reserve`]d sink.
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    /// How long a parked sink waits to be taken before it is closed.
+    const LINGER: Duration = Duration::from_secs(5);
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Slot> {
+        self.0.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Parks the sink of the player `owner`; `fading` sinks hold the tail of a song
+    /// to be mixed under the next one, and are taken whatever the next spec is.
+    ///
+    /// A sink nobody takes within [`Handoff::LINGER`] is closed.
+    pub fn park<K: AudioSink + Send + 'static>(&self, sink: K, config: &SinkConfig, fading: bool, owner: Handle) {
+        let parked = Parked { spec: sink.device_spec(), sink: Box::new(sink), file: config.file.clone(), fading };
+        let (old, generation) = {
+            let mut slot = self.lock();
+            if slot.pending == Some(owner) {
+                slot.pending = None;
+            }
+            slot.generation += 1;
+            (slot.parked.replace(parked), slot.generation)
+        };
+        self.0.1.notify_all();
+        if let Some(old) = old {
+            old.finish();
+        }
+        let handoff = self.clone();
+        std::thread::spawn(move || handoff.expire(generation));
+    }
+
+    /// Closes the sink parked as `generation` if it is still there after [`Handoff::LINGER`].
+    fn expire(&self, generation: u64) {
+        let (mut slot, _) = self.0.1.wait_timeout_while(self.lock(), Self::LINGER, |slot| slot.generation == generation && slot.parked.is_some()).unwrap_or_else(PoisonError::into_inner);
+        let parked = if slot.generation == generation { slot.parked.take() } else { None };
+        drop(slot);
+        if let Some(parked) = parked {
+            tracing::info!("Closing the sink nobody took.");
+            parked.finish();
+        }
+    }
+
+    /// Closes the parked sink once its tail has played, as the playlist stops.
+    pub fn close(&self) {
+        let parked = self.lock().parked.take();
+        if let Some(parked) = parked {
+            parked.finish();
+        }
+    }
+
+    /// Makes the next [`Handoff::take`] wait for the player `owner` to park its sink.
+    pub fn reserve(&self, owner: Handle) {
+        self.lock().pending = Some(owner);
+    }
+
+    /// Cancels the [`Handoff::reserve`] for `owner`, which parks nothing; a newer one stays.
+    pub fn release(&self, owner: Handle) {
+        let mut slot = self.lock();
+        if slot.pending == Some(owner) {
+            slot.pending = None;
+            drop(slot);
+            self.0.1.notify_all();
+        }
+    }
+
+    /// Takes the parked sink if it can play `spec` for `config` seamlessly,
+    /// otherwise lets it finish playing first.
+    pub fn take<K: AudioSink + Send + 'static>(&self, config: &SinkConfig, spec: DeviceSpec) -> Option<K> {
+        let (mut slot, _) = self.0.1.wait_timeout_while(self.lock(), Self::TIMEOUT, |slot| slot.pending.is_some()).unwrap_or_else(PoisonError::into_inner);
+        slot.pending = None;
+        let parked = slot.parked.take();
+        drop(slot);
+        let parked = parked?;
+        if (parked.spec == spec || parked.fading) && parked.file == config.file && (&*parked.sink as &dyn Any).is::<K>() {
+            tracing::info!("Continuing on the open sink{}.", if parked.fading { ", crossfading" } else { ", gapless" });
+            return (parked.sink as Box<dyn Any>).downcast().ok().map(|sink| *sink);
+        }
+        parked.finish();
+        None
+    }
+}
+
+/// Where the frames produced by [`crate::wav::Player`] go.
+pub trait AudioSink: Sized {
+    fn open(config: &SinkConfig, spec: WavSpec, format: Format) -> Result<Self, PlayError>;
+
+    /// The negotiated format, which may differ from the requested one.
+    fn device_spec(&self) -> DeviceSpec;
+
+    /// Writes interleaved frames, returns the number of frames accepted.
+    ///
+    /// If 0 is returned, the same frames should be written again.
+    fn writei<S: Fmt>(&mut self, buf: &[S]) -> Result<usize, PlayError>;
+
+    /// Frames written but not yet heard.
+    fn delay(&self) -> Result<isize, PlayError>;
+
+    /// Discards pending frames immediately.
+    fn drop(&mut self) -> Result<(), PlayError>;
+
+    /// Makes the sink ready to accept frames again after [`AudioSink::drop`].
+    fn prepare(&mut self) -> Result<(), PlayError>;
+
+    /// Waits until all pending frames are played.
+    fn drain(&mut self) -> Result<(), PlayError>;
+
+    fn is_running(&self) -> bool;
+}
+
+pub struct AlsaSink {
+    pcm: PCM,
+    spec: DeviceSpec,
+}
+
+impl AlsaSink {
+    /// Hardware formats to fall back to, from narrow to wide.
+    const FALLBACK_FORMATS: [Format; 6] = [Format::S16LE, Format::S243LE, Format::S24LE, Format::S32LE, Format::FloatLE, Format::Float64LE];
+
+    /// The requested format if supported, otherwise the narrowest fallback not
+    /// losing precision, otherwise the widest supported one.
+    fn negotiate_format(params: &HwParams, format: Format) -> Result<Format, PlayError> {
+        if params.test_format(format).is_ok() {
+            return Ok(format);
+        }
+
+        let width = format.width()?;
+        let mut widest = None;
+        for candidate in Self::FALLBACK_FORMATS {
+            if params.test_format(candidate).is_ok() {
+                if candidate.width()? >= width {
+                    return Ok(candidate);
+                }
+                widest = Some(candidate);
+            }
+        }
+        Ok(widest.unwrap_or(format))
+    }
+}
+
+impl AudioSink for AlsaSink {
+    fn open(config: &SinkConfig, spec: WavSpec, format: Format) -> Result<Self, PlayError> {
+        // 打开 PCM 设备，分配 snd_pcm_hw_params_t 结构体，配置空间初始化
+        let pcm = PCM::new(&config.device, Direction::Playback, false)?;
+
+        let params = HwParams::any(&pcm)?;
+
+        // 设置交错模式 (访问模式)
+        params.set_access(Access::RWInterleaved)?;
+
+        // 设置样本长度 (位数)，硬件不支持时换一个
+        let hw_format = Self::negotiate_format(&params, format)?;
+        params.set_format(hw_format)?;
+
+        // 设置采样率
+        let rate = params.set_rate_near(config.rate_for(spec), ValueOr::Nearest)?;
+
+        // 设置通道数
+        let channels = params.set_channels_near(spec.channels.into())?;
+
+        // 设置缓冲区与周期大小 (决定延迟)
+        if let Some(t) = config.buffer_time {
+            params.set_buffer_size_near(time_to_frames(rate, t))?;
+        }
+        if let Some(t) = config.period_time {
+            params.set_period_size_near(time_to_frames(rate, t), ValueOr::Nearest)?;
+        }
+
+        pcm.hw_params(&params)?;
+        drop(params);
+
+        let params = pcm.hw_params_current()?;
+        let rate = params.get_rate()?;
+        let period = params.get_period_size()?;
+        let buffer = params.get_buffer_size()?;
+        drop(params);
+        let latency = u64::try_from(buffer).unwrap_or_default() * 1_000_000 / u64::from(rate.max(1));
+        tracing::info!("PCM \x1b[36m{}\x1b[0m: rate = {rate} Hz, period = {period} frames, buffer = {buffer} frames, latency = {latency} μs", config.device);
+
+        // 设置软件参数：写满一个周期就开始播放，不等整个缓冲区；空出一个周期后再唤醒写入
+        let params = pcm.sw_params_current()?;
+        params.set_start_threshold(period)?;
+        params.set_avail_min(period)?;
+        pcm.sw_params(&params)?;
+        drop(params);
+
+        // 设置硬件配置参数
+        pcm.prepare()?;
+
+        let spec = DeviceSpec { format: hw_format, rate, channels: u16::try_from(channels).unwrap_or(u16::MAX) };
+        Ok(Self { pcm, spec })
+    }
+
+    #[inline]
+    fn device_spec(&self) -> DeviceSpec {
+        self.spec
+    }
+
+    fn writei<S: Fmt>(&mut self, buf: &[S]) -> Result<usize, PlayError> {
+        let io = IO::<S>::new(&self.pcm);
+        match io.writei(buf) {
+            Ok(s) => Ok(s),
+            Err(e) if io::Error::from_raw_os_error(e.errno()).kind() == io::ErrorKind::BrokenPipe => {
+                if let Err(e) = self.pcm.prepare() { tracing::warn!("play-prepare: {e}"); }
+                Ok(0)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[inline]
+    fn delay(&self) -> Result<isize, PlayError> {
+        Ok(self.pcm.delay()? as isize)
+    }
+
+    #[inline]
+    fn drop(&mut self) -> Result<(), PlayError> {
+        Ok(self.pcm.drop()?)
+    }
+
+    #[inline]
+    fn prepare(&mut self) -> Result<(), PlayError> {
+        Ok(self.pcm.prepare()?)
+    }
+
+    #[inline]
+    fn drain(&mut self) -> Result<(), PlayError> {
+        Ok(self.pcm.drain()?)
+    }
+
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.pcm.state() == State::Running
+    }
+}
+
+#[inline]
+fn time_to_frames(rate: u32, us: u32) -> Frames {
+    Frames::try_from(u64::from(rate) * u64::from(us) / 1_000_000).unwrap_or(Frames::MAX)
+}
+
+/// Prints sound cards, playback PCM devices and playback mixer controls.
+pub fn list_devices() -> alsa::Result<()> {
+    println!("声卡：");
+    for card in card::Iter::new() {
+        let card = card?;
+        println!("  hw:{}  {} ({})", card.get_index(), card.get_name()?, card.get_longname()?);
+
+        let Ok(mixer) = Mixer::new(&format!("hw:{}", card.get_index()), false) else { continue };
+        for selem in mixer.iter().filter_map(Selem::new) {
+            if !selem.has_playback_volume() {
+                continue;
+            }
+            let id = selem.get_id();
+            let (min, max) = selem.get_playback_volume_range();
+            println!("    混音器控制：'{}',{}  音量范围 {min}..={max}", id.get_name()?, id.get_index());
+        }
+    }
+
+    println!("PCM 设备：");
+    for hint in HintIter::new_str(None, "pcm")? {
+        if hint.direction == Some(Direction::Capture) {
+            continue;
+        }
+        let Some(name) = hint.name else { continue };
+        let desc = hint.desc.unwrap_or_default().replace('\n', " / ");
+        println!("  {name}  {desc}");
+    }
+
+    Ok(())
+}
+
+/// Accepts frames at the speed a real card would consume them, so that the
+/// playback loop and the progress bar behave as usual without a sound card.
+pub struct NullSink {
+    spec: DeviceSpec,
+    channels: usize,
+    rate: u64,
+    capacity: u64,
+    start: Instant,
+    written: u64,
+}
+
+impl NullSink {
+    const BUFFER_TIME: u32 = 100_000;
+
+    #[inline]
+    fn played(&self) -> u64 {
+        (self.start.elapsed().as_nanos() * u128::from(self.rate) / 1_000_000_000) as u64
+    }
+
+    #[inline]
+    fn queued(&self) -> u64 {
+        self.written.saturating_sub(self.played())
+    }
+
+    #[inline]
+    const fn frames_to_duration(&self, frames: u64) -> Duration {
+        Duration::from_nanos(frames * 1_000_000_000 / self.rate)
+    }
+}
+
+impl AudioSink for NullSink {
+    fn open(config: &SinkConfig, spec: WavSpec, format: Format) -> Result<Self, PlayError> {
+        let spec = WavSpec { sample_rate: config.rate_for(spec), ..spec };
+        let rate = u64::from(spec.sample_rate.max(1));
+        let capacity = (rate * u64::from(config.buffer_time.unwrap_or(Self::BUFFER_TIME)) / 1_000_000).max(1);
+        Ok(Self { spec: DeviceSpec::new(spec, format), channels: spec.channels.into(), rate, capacity, start: Instant::now(), written: 0 })
+    }
+
+    #[inline]
+    fn device_spec(&self) -> DeviceSpec {
+        self.spec
+    }
+
+    fn writei<S: Fmt>(&mut self, buf: &[S]) -> Result<usize, PlayError> {
+        let queued = self.queued();
+        if queued == 0 {
+            // underrun: restart the clock
+            self.start = Instant::now();
+            self.written = 0;
+        } else if queued >= self.capacity {
+            sleep(self.frames_to_duration(queued - self.capacity / 2));
+            return Ok(0);
+        }
+        let frames = (self.capacity - self.queued()).min((buf.len() / self.channels) as u64);
+        self.written += frames;
+        Ok(frames as usize)
+    }
+
+    #[inline]
+    fn delay(&self) -> Result<isize, PlayError> {
+        Ok(isize::try_from(self.queued()).unwrap_or(isize::MAX))
+    }
+
+    #[inline]
+    fn drop(&mut self) -> Result<(), PlayError> {
+        self.written = self.played();
+        Ok(())
+    }
+
+    #[inline]
+    fn prepare(&mut self) -> Result<(), PlayError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn drain(&mut self) -> Result<(), PlayError> {
+        sleep(self.frames_to_duration(self.queued()));
+        Ok(())
+    }
+
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.queued() != 0
+    }
+}
+
+/// Writes exactly the frames that would be sent to the card, with the same
+/// [`WavSpec`] as the source (except the rate, see [`SinkConfig::rate`]).
+pub struct WavSink {
+    writer: WavWriter<io::BufWriter<fs::File>>,
+    spec: DeviceSpec,
+}
+
+impl AudioSink for WavSink {
+    fn open(config: &SinkConfig, spec: WavSpec, format: Format) -> Result<Self, PlayError> {
+        const NO_OUTPUT: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "no output file for WAV sink");
+
+        // hound 写不了的换成能放下的格式，由 Convert 转换
+        let (format, bits_per_sample, bytes_per_sample, sample_format) = match format {
+            Format::U8 | Format::S8 => (format, 8, 1, SampleFormat::Int),
+            Format::S16LE => (format, 16, 2, SampleFormat::Int),
+            Format::S183LE | Format::S203LE | Format::S243LE => (Format::S243LE, 24, 3, SampleFormat::Int),
+            Format::S20LE | Format::S24LE => (Format::S24LE, 24, 4, SampleFormat::Int),
+            Format::S32LE => (format, 32, 4, SampleFormat::Int),
+            Format::FloatLE | Format::Float64LE => (Format::FloatLE, 32, 4, SampleFormat::Float),
+            _ => return Err(UnsupportedFormatError(spec).into()),
+        };
+
+        let spec = WavSpec { sample_rate: config.rate_for(spec), bits_per_sample, bytes_per_sample, sample_format, ..spec };
+        let file = config.file.as_ref().ok_or(NO_OUTPUT)?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let writer = WavWriter::create(file, spec).map_err(cvt_err)?;
+        tracing::info!("Recording to \x1b[36m{}\x1b[0m", file.display());
+        Ok(Self { writer, spec: DeviceSpec::new(spec, format) })
+    }
+
+    #[inline]
+    fn device_spec(&self) -> DeviceSpec {
+        self.spec
+    }
+
+    fn writei<S: Fmt>(&mut self, buf: &[S]) -> Result<usize, PlayError> {
+        let channels = usize::from(self.writer.spec().channels);
+        let frames = buf.len() / channels;
+        let float = self.writer.spec().sample_format == SampleFormat::Float;
+        for &s in &buf[..frames * channels] {
+            let r = if float {
+                self.writer.write_sample(s.to_f64() as f32)
+            } else {
+                self.writer.write_sample(s.to_f64() as i32)
+            };
+            r.map_err(cvt_err)?;
+        }
+        Ok(frames)
+    }
+
+    #[inline]
+    fn delay(&self) -> Result<isize, PlayError> {
+        Ok(0)
+    }
+
+    #[inline]
+    fn drop(&mut self) -> Result<(), PlayError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn prepare(&mut self) -> Result<(), PlayError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn drain(&mut self) -> Result<(), PlayError> {
+        Ok(self.writer.flush().map_err(cvt_err)?)
+    }
+
+    #[inline]
+    fn is_running(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::WavReader;
+
+    use super::*;
+    use crate::{convert::Convert, fmt_impl::S18_3, util::get_channel_handle};
+
+    #[test]
+    fn record_18_bit() {
+        let dir = std::env::temp_dir().join(format!("player-test-{}", std::process::id()));
+        let config = SinkConfig::new(SinkKind::Wav, String::new(), Some(dir.clone()), None, None, None, Quality::Fast).for_song(Path::new("song.pcm"));
+        let spec = WavSpec { channels: 1, sample_rate: 1000, bits_per_sample: 18, bytes_per_sample: 3, sample_format: SampleFormat::Int };
+        let samples = [-131_072, -1, 0, 1, 131_071];
+
+        let mut sink = Convert::<WavSink>::open(&config, spec, Format::S183LE).unwrap();
+        let buf: Vec<_> = samples.iter().map(|&x| S18_3::from_f64(f64::from(x))).collect();
+        assert_eq!(sink.writei(&buf).unwrap(), samples.len());
+        sink.drain().unwrap();
+        drop(sink);
+
+        // 放进 24 位，值不变只是左移
+        let mut reader = WavReader::open(dir.join("song.wav")).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, 24);
+        let recorded: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(recorded, samples.map(|x| x << 6));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn handoff_reservations() {
+        let (a, b) = (std::sync::mpsc::channel::<()>().0, std::sync::mpsc::channel::<()>().0);
+        let (a, b) = (get_channel_handle(&raw const a), get_channel_handle(&raw const b));
+        let config = SinkConfig::new(SinkKind::Null, String::new(), None, None, None, None, Quality::Fast);
+        let spec = WavSpec { channels: 1, sample_rate: 1000, bits_per_sample: 16, bytes_per_sample: 2, sample_format: SampleFormat::Int };
+        let handoff = Handoff::default();
+
+        // 旧的播放器结束时不能取消别人的等待
+        handoff.reserve(a);
+        handoff.reserve(b);
+        handoff.release(a);
+        handoff.park(NullSink::open(&config, spec, Format::S16LE).unwrap(), &config, false, a);
+        assert_eq!(handoff.lock().pending, Some(b));
+        handoff.release(b);
+        assert_eq!(handoff.lock().pending, None);
+
+        // 列表停下时关掉没人接的
+        assert!(handoff.lock().parked.is_some());
+        handoff.close();
+        assert!(handoff.lock().parked.is_none());
+    }
+}
@@ -0,0 +1,187 @@
+use core::ops::Range;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::FileTypeExt,
+    path::Path,
+};
+
+use crate::{
+    decoder::UNKNOWN_FRAMES,
+    http::{self, OnTitle},
+    pcm::{Layout, PcmDecoder},
+    raw::RawFormat,
+    wav,
+};
+
+const OUT_OF_WINDOW: io::Error = io::const_error!(io::ErrorKind::NotSeekable, "position no longer buffered in the stream window");
+const NOT_PCM: io::Error = io::const_error!(io::ErrorKind::Unsupported, "only PCM and float WAVE can be streamed");
+const NO_END: io::Error = io::const_error!(io::ErrorKind::Unsupported, "the end of a stream is unknown");
+
+/// The path that stands for standard input.
+pub const STDIN: &str = "-";
+
+/// Standard input or a FIFO.
+pub type Input = Box<dyn Read + Send>;
+
+/// Whether `path` is standard input or a FIFO, which can only be read once from start to end.
+pub fn is_stream(path: &Path) -> bool {
+    path == Path::new(STDIN) || fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+/// Reads a non-seekable input, keeping the last bytes read in a ring buffer
+/// so that it can seek back a little (to what is still being heard).
+pub struct Stream<R> {
+    inner: R,
+    ring: Box<[u8]>,
+    end: u64, // 已从输入读出的字节数
+    pos: u64,
+}
+
+impl<R: Read> Stream<R> {
+    /// 8 MiB，48 kHz 双声道 16 位约 40 秒
+    const WINDOW: usize = 8 << 20;
+
+    pub fn new(inner: R) -> Self {
+        Self { inner, ring: vec![0; Self::WINDOW].into_boxed_slice(), end: 0, pos: 0 }
+    }
+
+    /// The bytes that can be read again.
+    #[inline]
+    pub fn window(&self) -> Range<u64> {
+        self.end.saturating_sub(self.ring.len() as u64)..self.end
+    }
+
+    /// Reads more of the input into the ring buffer, returns 0 at its end.
+    fn fill(&mut self, max: usize) -> io::Result<usize> {
+        let at = (self.end % self.ring.len() as u64) as usize;
+        let len = max.min(self.ring.len() - at);
+        let n = loop {
+            match self.inner.read(&mut self.ring[at..at + len]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                ret => break ret?,
+            }
+        };
+        self.end += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Read for Stream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos == self.end && self.fill(buf.len())? == 0 {
+            return Ok(0);
+        }
+        // 从环形缓冲区里读，不越过回绕处
+        let at = (self.pos % self.ring.len() as u64) as usize;
+        let n = buf.len().min((self.end - self.pos) as usize).min(self.ring.len() - at);
+        buf[..n].copy_from_slice(&self.ring[at..at + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for Stream<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n,
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d).ok_or(OUT_OF_WINDOW)?,
+            SeekFrom::End(_) => return Err(NO_END),
+        };
+        if target < self.window().start {
+            return Err(OUT_OF_WINDOW);
+        }
+        // 往前就读下去 (比如跳过文件头里的块)
+        while self.end < target {
+            if self.fill(usize::try_from(target - self.end).unwrap_or(usize::MAX))? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+/// Opens standard input, the FIFO or the live stream (an `http://` URL, prefetching `prebuffer` bytes) at `path`,
+/// with the `Content-Type` of a live stream.
+pub fn input(path: &Path, prebuffer: usize, on_title: OnTitle) -> io::Result<(Input, Option<String>)> {
+    Ok(if path == Path::new(STDIN) {
+        (Box::new(io::stdin()), None)
+    } else if http::is_url(path) {
+        let (live, content_type) = http::open_live(&path.to_string_lossy(), prebuffer, on_title)?;
+        (Box::new(live), content_type)
+    } else {
+        (Box::new(File::open(path)?), None)
+    })
+}
+
+/// Reads raw PCM of the `raw` format, or else a WAVE, from `input`.
+///
+/// The length in a streamed header is a placeholder, so the stream plays until its input ends.
+pub fn open(input: Input, raw: Option<RawFormat>) -> io::Result<PcmDecoder<Stream<Input>>> {
+    let mut stream = Stream::new(input);
+    let (spec, layout, begin) = if let Some(format) = raw {
+        (format.spec, format.layout, 0)
+    } else {
+        let header = wav::read_header(&mut stream)?;
+        if !matches!(header.tag, wav::WAVE_FORMAT_PCM | wav::WAVE_FORMAT_IEEE_FLOAT) {
+            return Err(NOT_PCM);
+        }
+        (header.spec()?, Layout::default(), header.begin)
+    };
+    tracing::info!("PCM stream from byte {begin}: spec={spec:?}, {layout:?}");
+    PcmDecoder::new(stream, spec, layout, begin, UNKNOWN_FRAMES)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn seek_in_window() {
+        let len = Stream::<Cursor<Vec<u8>>>::WINDOW + 1000;
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let mut stream = Stream::new(Cursor::new(data.clone()));
+
+        // 往前跳就读下去，越过回绕处也能接着读
+        stream.seek(SeekFrom::Start(len as u64 - 10)).unwrap();
+        let mut buf = [0; 64];
+        let n = stream.read(&mut buf).unwrap();
+        assert_eq!(buf[..n], data[len - 10..]);
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+        let start = stream.window().start;
+        assert_eq!(start, 1000);
+        assert!(stream.seek(SeekFrom::Start(start - 1)).is_err());
+        assert!(stream.seek(SeekFrom::End(0)).is_err());
+        stream.seek(SeekFrom::Current(-i64::try_from(Stream::<Cursor<Vec<u8>>>::WINDOW).unwrap())).unwrap();
+        let mut all = Vec::new();
+        stream.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data[1000..]);
+    }
+
+    #[test]
+    fn wave_with_placeholder_lengths() {
+        // 流里的 RIFF 与 data 长度都是占位值
+        let mut wave = b"RIFF\xff\xff\xff\xffWAVEfmt \x10\0\0\0".to_vec();
+        wave.extend([1, 0, 1, 0]);
+        wave.extend(8000u32.to_le_bytes());
+        wave.extend(16000u32.to_le_bytes());
+        wave.extend([2, 0, 16, 0]);
+        wave.extend(b"data\xff\xff\xff\xff");
+        wave.extend([1, 0, 2, 0, 3, 0]);
+
+        let mut decoder = open(Box::new(Cursor::new(wave)), None).unwrap();
+        assert_eq!(decoder.frames(), UNKNOWN_FRAMES);
+        let mut buf = [0i16; 8];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], [1, 2, 3]);
+        assert_eq!(decoder.window(), 0..3);
+    }
+}
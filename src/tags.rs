@@ -0,0 +1,200 @@
+use std::borrow::Cow;
+
+/// What is shown for a song besides its file name.
+#[derive(Clone, Debug, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+impl Tags {
+    /// Fills the missing fields from `other`.
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            artist: self.artist.or(other.artist),
+            album: self.album.or(other.album),
+        }
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.title.is_none() && self.artist.is_none() && self.album.is_none()
+    }
+}
+
+/// Decodes a NUL-terminated string that is UTF-8 or else Latin-1.
+pub fn text(b: &[u8]) -> Option<String> {
+    let b = b.split(|&c| c == 0).next().unwrap_or_default();
+    let s = str::from_utf8(b).map_or_else(|_| b.iter().copied().map(char::from).collect(), String::from);
+    Some(s.trim().to_owned()).filter(|s| !s.is_empty())
+}
+
+/// Parses the body of a `LIST` chunk of type `INFO`.
+pub fn parse_info(b: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+    let mut pos = 4; // 跳过 "INFO"
+    while let Some(head) = b.get(pos..pos + 8) {
+        let size = u32::from_le_bytes([head[4], head[5], head[6], head[7]]) as usize;
+        let value = b.get(pos + 8..).map(|v| &v[..size.min(v.len())]).and_then(text);
+        match &head[..4] {
+            b"INAM" => tags.title = tags.title.or(value),
+            b"IART" => tags.artist = tags.artist.or(value),
+            b"IPRD" => tags.album = tags.album.or(value),
+            _ => (),
+        }
+        pos += 8 + size + (size & 1);
+    }
+    tags
+}
+
+/// Takes the description of a Broadcast WAV `bext` chunk as the title.
+pub fn parse_bext(b: &[u8]) -> Tags {
+    Tags { title: text(&b[..b.len().min(256)]), ..Tags::default() }
+}
+
+#[inline]
+fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |n, &c| n << 7 | usize::from(c & 0x7f))
+}
+
+/// Undoes the unsynchronisation scheme, which inserts a 0 after every 0xFF.
+fn resync(b: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(b.len());
+    for (i, &c) in b.iter().enumerate() {
+        if !(c == 0 && i > 0 && b[i - 1] == 0xff) {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Decodes the first string of an `ID3v2` text frame.
+fn id3_text(b: &[u8]) -> Option<String> {
+    let (&encoding, b) = b.split_first()?;
+    let utf16 = |b: &[u8], be: bool| {
+        let units = b.chunks_exact(2).map(|u| if be { u16::from_be_bytes([u[0], u[1]]) } else { u16::from_le_bytes([u[0], u[1]]) });
+        let s: String = char::decode_utf16(units.take_while(|&u| u != 0)).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+        Some(s.trim().to_owned()).filter(|s| !s.is_empty())
+    };
+    match encoding {
+        1 => match b {
+            [0xff, 0xfe, rest @ ..] => utf16(rest, false),
+            [0xfe, 0xff, rest @ ..] => utf16(rest, true),
+            _ => utf16(b, false),
+        },
+        2 => utf16(b, true),
+        _ => text(b), // 0 应是 Latin-1，但不少软件直接写 UTF-8
+    }
+}
+
+/// Parses the title, artist and album frames of an `ID3v2.2`, `2.3` or `2.4` tag.
+pub fn parse_id3v2(b: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+    let Some(head) = b.get(..10).filter(|h| h.starts_with(b"ID3")) else { return tags };
+    let (version, flags) = (head[3], head[5]);
+    let end = b.len().min(10 + syncsafe(&head[6..10]));
+    let mut body = Cow::Borrowed(&b[10..end]);
+    if flags & 0x80 != 0 && version < 4 {
+        body = Cow::Owned(resync(&body)); // 2.4 以前是整个标签去同步
+    }
+    let mut pos = match (flags & 0x40 != 0, version) {
+        (false, _) | (true, 2) => 0,
+        (true, 3) => body.get(..4).map_or(usize::MAX, |s| 4 + u32::from_be_bytes([s[0], s[1], s[2], s[3]]) as usize),
+        (true, _) => body.get(..4).map_or(usize::MAX, syncsafe),
+    };
+
+    let header_len = if version == 2 { 6 } else { 10 };
+    while let Some(frame) = body.get(pos..pos + header_len) {
+        let (id, size, frame_flags) = match version {
+            2 => (&frame[..3], (usize::from(frame[3]) << 16) | (usize::from(frame[4]) << 8) | usize::from(frame[5]), 0),
+            3 => (&frame[..4], u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]) as usize, u16::from_be_bytes([frame[8], frame[9]])),
+            _ => (&frame[..4], syncsafe(&frame[4..8]), u16::from_be_bytes([frame[8], frame[9]])),
+        };
+        if id[0] == 0 {
+            break; // 填充
+        }
+        let Some(data) = body.get(pos + header_len..pos + header_len + size) else { break };
+        pos += header_len + size;
+
+        // 压缩或加密的帧不管；2.3 的分组标识占 1 字节，2.4 的数据长度占 4 字节
+        let data = match version {
+            3 if frame_flags & 0x00c0 != 0 => continue,
+            3 if frame_flags & 0x0020 != 0 => Cow::Borrowed(data.get(1..).unwrap_or_default()),
+            4.. if frame_flags & 0x000c != 0 => continue,
+            4.. => {
+                let data = if frame_flags & 0x0001 != 0 { data.get(4..).unwrap_or_default() } else { data };
+                if frame_flags & 0x0002 != 0 { Cow::Owned(resync(data)) } else { Cow::Borrowed(data) }
+            }
+            _ => Cow::Borrowed(data),
+        };
+        let field = match id {
+            b"TT2" | b"TIT2" => &mut tags.title,
+            b"TP1" | b"TPE1" => &mut tags.artist,
+            b"TAL" | b"TALB" => &mut tags.album,
+            _ => continue,
+        };
+        if field.is_none() {
+            *field = id3_text(&data);
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id3(version: u8, flags: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let body = frames.concat();
+        let size = (0..4).rev().map(|i| (body.len() >> (7 * i) & 0x7f) as u8);
+        [&b"ID3"[..], &[version, 0, flags], &size.collect::<Vec<_>>(), &body].concat()
+    }
+
+    fn frame(id: &[u8], flags: u16, data: &[u8]) -> Vec<u8> {
+        let size = if id.len() == 3 { (data.len() as u32).to_be_bytes()[1..].to_vec() } else { (data.len() as u32).to_be_bytes().to_vec() };
+        let flags = if id.len() == 3 { Vec::new() } else { flags.to_be_bytes().to_vec() };
+        [id, &size, &flags, data].concat()
+    }
+
+    #[test]
+    fn text_encodings() {
+        assert_eq!(text(b" Caf\xc3\xa9 \0junk").as_deref(), Some("Café"));
+        assert_eq!(text(b"Caf\xe9").as_deref(), Some("Café")); // 不是 UTF-8 就当 Latin-1
+        assert_eq!(text(b"  \0x"), None);
+    }
+
+    #[test]
+    fn info() {
+        let b = [&b"INFO"[..], b"INAM\x05\0\0\0Title\0", b"ICMT\x02\0\0\0hi", b"IART\x04\0\0\0Band"].concat();
+        let tags = parse_info(&b);
+        assert_eq!((tags.title.as_deref(), tags.artist.as_deref(), tags.album), (Some("Title"), Some("Band"), None));
+    }
+
+    #[test]
+    fn id3v23() {
+        // UTF-16 带 BOM；压缩的帧跳过；后面的填充不是帧
+        let title = [&[1, 0xff, 0xfe][..], &"Héllo".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>()].concat();
+        let b = id3(3, 0, &[frame(b"TPE1", 0x0080, b"\0zipped"), frame(b"TIT2", 0, &title), frame(b"TPE1", 0, b"\0Band"), vec![0; 20]]);
+        let tags = parse_id3v2(&b);
+        assert_eq!((tags.title.as_deref(), tags.artist.as_deref()), (Some("Héllo"), Some("Band")));
+    }
+
+    #[test]
+    fn id3v24() {
+        // 数据长度标识占 4 字节，帧内去同步
+        let b = id3(4, 0, &[frame(b"TALB", 0x0003, b"\0\0\0\x04\0A\xff\0B"), frame(b"TIT2", 0, b"\x02\0O\0K")]);
+        let tags = parse_id3v2(&b);
+        assert_eq!(tags.album.as_deref(), Some("A\u{ff}B"));
+        assert_eq!(tags.title.as_deref(), Some("OK"));
+    }
+
+    #[test]
+    fn id3v22() {
+        let tags = parse_id3v2(&id3(2, 0, &[frame(b"TT2", 0, b"\0Old"), frame(b"TAL", 0, b"\0Album")]));
+        assert_eq!((tags.title.as_deref(), tags.album.as_deref()), (Some("Old"), Some("Album")));
+        assert!(parse_id3v2(b"ID2").is_empty());
+    }
+}
@@ -3,7 +3,8 @@ use std::{io, sync::mpsc::RecvError};
 
 use alsa::pcm::Format;
 use hound::{SampleFormat, WavSpec};
-use replace_with::replace_with_or_abort;
+
+use crate::shift::{self, Speed};
 
 #[inline]
 pub fn cvt_err(err: hound::Error) -> io::Error {
@@ -14,16 +15,6 @@ pub fn cvt_err(err: hound::Error) -> io::Error {
     }
 }
 
-pub fn buffer_resize<R>(reader: &mut io::BufReader<R>, new_size: usize)
-where
-    R: io::Read,
-{
-    if reader.capacity() != new_size {
-        tracing::info!("Resizing buffer from {} to {}", reader.capacity(), new_size);
-        replace_with_or_abort(reader, |owned| io::BufReader::with_capacity(new_size, owned.into_inner()));
-    }
-}
-
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct UnsupportedFormatError(pub WavSpec);
@@ -43,7 +34,7 @@ impl Error for UnsupportedFormatError {}
 pub fn cvt_format(spec: WavSpec) -> Result<Format, PlayError> {
     let format = match spec.sample_format {
         SampleFormat::Int => match (spec.bits_per_sample, spec.bytes_per_sample) {
-            (8, 1) => Format::S8,
+            (8, 1) => Format::U8, // WAV 的 8 位 PCM 是无符号的
             (16, 2) => Format::S16LE,
             (18, 3) => Format::S183LE,
             (20, 3) => Format::S203LE,
@@ -73,6 +64,18 @@ pub enum PlayError {
     Io(io::Error),
 }
 
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Self::Alsa(e) => fmt::Display::fmt(e, f),
+            Self::Format(e) => fmt::Display::fmt(e, f),
+            Self::Io(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl Error for PlayError {}
+
 impl From<alsa::Error> for PlayError {
     #[inline]
     fn from(err: alsa::Error) -> Self {
@@ -115,8 +118,14 @@ impl From<PlayError> for io::Error {
 #[derive(Clone, Copy, Debug)]
 pub enum PlayerEvent {
     Terminate,
+    /// Terminate, fading into the next song if configured
+    Skip,
+    /// Terminate, leaving the open sink to the next track of the same file
+    Park,
     Move { offset: isize },
-    SetMultiplier { multiplier: u8 },
+    /// Move to the `offset`-th cue point from here
+    Jump { offset: isize },
+    SetSpeed { speed: Speed },
     Pause,
     Resume,
 }
@@ -130,6 +139,7 @@ pub enum MP3Event {
     Dispatch { sub: PlayerEvent },
     SwitchSong { seek: io::SeekFrom },
     SetVolume { volume: i32 },
+    SetCrossfade { enabled: bool },
 }
 
 impl From<PlayerEvent> for MP3Event {
@@ -139,33 +149,36 @@ impl From<PlayerEvent> for MP3Event {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum GUIEvent {
     SwitchSong { index: usize, handle: Handle },
     ProgressAccess { access: Option<ProgressAccess>, handle: Handle },
-    SetMultiplier { multiplier: u8 },
+    SetSpeed { speed: Speed },
+    /// A live stream of the `index`-th song announced what it is playing now
+    StreamTitle { index: usize, title: String },
 }
 
+/// Playback position in frames.
 #[derive(Clone, Copy, Default)]
 pub struct Progress {
-    pub begin: usize,
-    pub pos: usize,
-    pub end: usize,
-    pub delay: isize,
+    pub begin: u64,
+    pub pos: u64,
+    pub end: u64,
+    pub delay: i64,
 }
 
 impl Progress {
     #[inline(always)]
-    pub fn c(self, multiplier: u8, extra: isize) -> usize {
+    pub fn c(self, speed: Speed, extra: i64) -> u64 {
         self.pos
             .saturating_add_signed(extra)
-            .saturating_sub_signed(self.delay * isize::from(multiplier) / 2)
+            .saturating_sub_signed(shift::consumed(speed, self.delay))
             .clamp(self.begin, self.end)
     }
 
     #[inline(always)]
-    pub fn normalize(&mut self, multiplier: u8, extra: isize) -> bool {
-        let new_pos = self.c(multiplier, extra);
+    pub fn normalize(&mut self, speed: Speed, extra: i64) -> bool {
+        let new_pos = self.c(speed, extra);
         let eq = self.pos == new_pos;
         self.pos = new_pos;
         self.delay = 0;
@@ -175,17 +188,18 @@ impl Progress {
 
 #[derive(Clone, Copy, Debug)]
 pub struct ProgressAccess {
-    pub multiplier: *const u8,
+    pub speed: *const Speed,
     pub progress: *const Progress,
-    pub duration: usize,
-    pub size_per_second: usize,
+    pub begin: u64,
+    pub duration: u64, // 帧数
+    pub rate: u64,
 }
 
 unsafe impl Send for ProgressAccess {}
 
 impl ProgressAccess {
     #[inline]
-    fn i(mut num: usize, den: usize) -> String {
+    fn i(mut num: u64, den: u64) -> String {
         use fmt::Write;
 
         let mut ret = String::with_capacity(10); // 12:34.567\0
@@ -200,24 +214,27 @@ impl ProgressAccess {
         ret
     }
 
+    /// The position heard, from the beginning of the track.
     #[inline(always)]
-    pub fn c(self) -> usize {
-        unsafe { (*self.progress).c(*self.multiplier, 0) }
+    pub fn c(self) -> u64 {
+        unsafe { (*self.progress).c(*self.speed, 0) - self.begin }
     }
 
     #[inline(always)]
     pub fn p(self) -> usize {
-        (((self.c() as u64) << 20) / self.duration as u64) as usize
+        // 空的流或分轨没有长度
+        (u128::from(self.c()) << 20).checked_div(u128::from(self.duration)).unwrap_or_default() as usize
     }
 
     #[inline(always)]
     pub fn l(self) -> String {
-        Self::i(self.c(), self.size_per_second)
+        Self::i(self.c(), self.rate)
     }
 
     #[inline(always)]
     pub fn n(self) -> String {
-        Self::i(self.duration, self.size_per_second)
+        // 流要放完才知道多长
+        if self.duration == crate::decoder::UNKNOWN_FRAMES { "-:--.---".to_owned() } else { Self::i(self.duration, self.rate) }
     }
 }
 
@@ -259,3 +276,32 @@ mod hack {
         unsafe { *thing.add(1) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shift::SPEED_ONE;
+
+    fn access(speed: *const Speed, progress: &Progress) -> ProgressAccess {
+        ProgressAccess { speed, progress, begin: progress.begin, duration: progress.end - progress.begin, rate: 1000 }
+    }
+
+    #[test]
+    fn progress_heard() {
+        let progress = Progress { begin: 1000, pos: 3000, end: 5000, delay: 400 };
+        // 1.5 倍速时设备里的 400 帧对应 600 个输入帧
+        let access = access(&150, &progress);
+        assert_eq!(access.c(), 1400);
+        assert_eq!(access.p(), (1400 << 20) / 4000);
+        assert_eq!(access.l(), "0:01.400");
+        assert_eq!(access.n(), "0:04.000");
+    }
+
+    #[test]
+    fn progress_empty() {
+        let progress = Progress { begin: 7, pos: 7, end: 7, delay: 0 };
+        let access = access(&SPEED_ONE, &progress);
+        assert_eq!(access.c(), 0);
+        assert_eq!(access.p(), 0);
+    }
+}
@@ -1,85 +1,418 @@
-use core::{any::type_name, hint::unlikely};
+use core::{any::type_name, hint::unlikely, ops::Range};
 use std::{
-    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
     sync::mpsc::{Receiver, RecvError, Sender, TryRecvError},
 };
 
-use alsa::{
-    Direction, PCM, ValueOr,
-    pcm::{Access, Format, HwParams, IO, State},
-};
-use hound::WavReader;
+use alsa::pcm::Format;
+use hound::{SampleFormat, WavSpec};
 
 use crate::{
+    adpcm,
+    convert::Convert,
+    decoder::{self, Decoder, Leftover, UNKNOWN_FRAMES},
     fmt_impl::{Fmt, S18_3, S20_3, S20_4, S24_3, S24_4},
-    shift,
+    markers::{self, Cue, Loop, Markers},
+    mp3::Song,
+    shift::{self, SPEED_ONE, Speed},
+    sink::{AlsaSink, AudioSink, DeviceSpec, Handoff, NullSink, SinkConfig, SinkKind, WavSink},
+    tags::{self, Tags},
     util::{
         GUIEvent, Handle, MP3Event, PlayError, PlayerEvent, Progress, ProgressAccess,
-        UnsupportedFormatError, buffer_resize, cvt_format, get_channel_handle,
+        UnsupportedFormatError, cvt_format, get_channel_handle,
     },
 };
 
-pub fn dump_header<R>(reader: &WavReader<R>)
-where
-    R: io::Read,
-{
-    println!("RIFF 标志：RIFF");
-    println!("文件大小：{}", reader.len() * u32::from(reader.spec().bytes_per_sample));
+const NOT_WAV: io::Error = io::const_error!(io::ErrorKind::InvalidData, "not a RIFF, RF64 or BW64 WAVE file");
+const BAD_FMT: io::Error = io::const_error!(io::ErrorKind::InvalidData, "invalid WAVE fmt chunk");
+const BAD_DS64: io::Error = io::const_error!(io::ErrorKind::InvalidData, "RF64 file without a valid ds64 chunk");
+const NO_FMT: io::Error = io::const_error!(io::ErrorKind::InvalidData, "WAVE data chunk without a preceding fmt chunk");
+const NO_DATA: io::Error = io::const_error!(io::ErrorKind::InvalidData, "WAVE file without data chunk");
+const UNSUPPORTED_ENCODING: io::Error = io::const_error!(io::ErrorKind::Unsupported, "unsupported WAVE encoding");
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_ADPCM: u16 = 0x0002;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub const WAVE_FORMAT_MULAW: u16 = 0x0007;
+pub const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+/// `KSDATAFORMAT_SUBTYPE_*` 除去开头格式标签的部分
+const SUBTYPE_GUID_TAIL: [u8; 14] = [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71];
+/// RF64 中 32 位长度字段的占位值，真正的长度在 ds64 块里
+const RF64_PLACEHOLDER: u32 = u32::MAX;
+
+/// What the RIFF chunks say about the audio data.
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    pub rf64: bool,
+    pub tag: u16, // WAVE_FORMAT_EXTENSIBLE 已换成子格式的标签
+    pub channels: u16,
+    pub rate: u32,
+    pub block_align: u16,
+    pub bits: u16,
+    pub valid_bits: u16,
+    pub channel_mask: u32, // 0 表示未指定
+    pub coefs: Vec<[i16; 2]>, // MS ADPCM 的预测系数，可以多于标准的 7 对
+    pub samples: u64, // fact 或 ds64 记录的每声道采样数，0 表示未知
+    pub begin: u64, // data 块的起始位置
+    pub size: u64, // data 块在文件中实际有的字节数
+    pub missing: u64, // data 块超出文件结尾的字节数
+}
+
+impl Header {
+    fn parse_fmt(&mut self, b: &[u8]) -> io::Result<()> {
+        let u16_at = |i: usize| b.get(i..i + 2).map_or(0, |x| u16::from_le_bytes([x[0], x[1]]));
+        if b.len() < 16 {
+            return Err(BAD_FMT);
+        }
+        self.tag = u16_at(0);
+        self.channels = u16_at(2);
+        self.rate = u32::from_le_bytes([b[4], b[5], b[6], b[7]]);
+        self.block_align = u16_at(12);
+        self.bits = u16_at(14);
+        self.valid_bits = self.bits;
+        if self.tag == WAVE_FORMAT_EXTENSIBLE {
+            let ext = b.get(24..40).filter(|_| u16_at(16) >= 22).ok_or(BAD_FMT)?;
+            // 有的文件把有效位数写成 0，当作与容器相同
+            self.valid_bits = Some(u16_at(18)).filter(|&v| v != 0 && v <= self.bits).unwrap_or(self.bits);
+            self.channel_mask = u32::from_le_bytes([b[20], b[21], b[22], b[23]]);
+            if ext[2..] != SUBTYPE_GUID_TAIL {
+                return Err(UNSUPPORTED_ENCODING);
+            }
+            self.tag = u16::from_le_bytes([ext[0], ext[1]]);
+        }
+        if self.tag == WAVE_FORMAT_ADPCM {
+            // cbSize、每块采样数、系数个数之后是系数表
+            let n = usize::from(u16_at(20));
+            let coefs = b.get(22..22 + n * 4).filter(|_| n >= adpcm::MS_COEFS).ok_or(BAD_FMT)?;
+            self.coefs = coefs.chunks_exact(4).map(|x| [i16::from_le_bytes([x[0], x[1]]), i16::from_le_bytes([x[2], x[3]])]).collect();
+        }
+        if self.channels == 0 || self.rate == 0 || self.block_align == 0 {
+            return Err(BAD_FMT);
+        }
+        Ok(())
+    }
+
+    /// The spec the samples are played with, compressed ones are expanded to 16 bits.
+    pub const fn spec(&self) -> io::Result<WavSpec> {
+        let expanded = WavSpec { channels: self.channels, sample_rate: self.rate, bits_per_sample: 16, bytes_per_sample: 2, sample_format: SampleFormat::Int };
+        match self.tag {
+            WAVE_FORMAT_ALAW | WAVE_FORMAT_MULAW if self.bits == 8 && self.block_align == self.channels => return Ok(expanded),
+            WAVE_FORMAT_ADPCM | WAVE_FORMAT_IMA_ADPCM if self.bits == 4 && adpcm::block_frames(self.tag, self.channels, self.block_align as u64) != 0 => return Ok(expanded),
+            WAVE_FORMAT_ALAW | WAVE_FORMAT_MULAW | WAVE_FORMAT_ADPCM | WAVE_FORMAT_IMA_ADPCM => return Err(BAD_FMT),
+            _ => (),
+        }
+        let bytes = self.block_align / self.channels;
+        if bytes * self.channels != self.block_align || self.bits > bytes * 8 {
+            return Err(BAD_FMT);
+        }
+        let sample_format = match (self.tag, bytes) {
+            (WAVE_FORMAT_PCM, 1..=4) => SampleFormat::Int,
+            (WAVE_FORMAT_IEEE_FLOAT, 4 | 8) => SampleFormat::Float,
+            _ => return Err(UNSUPPORTED_ENCODING),
+        };
+        // 有效位数少于容器时采样左对齐，按整个容器宽度播放即可
+        Ok(WavSpec { channels: self.channels, sample_rate: self.rate, bits_per_sample: bytes * 8, bytes_per_sample: bytes, sample_format })
+    }
+
+    /// Number of whole frames in the data chunk.
+    pub fn frames(&self) -> u64 {
+        let block_align = u64::from(self.block_align);
+        if !matches!(self.tag, WAVE_FORMAT_ADPCM | WAVE_FORMAT_IMA_ADPCM) {
+            return self.size / block_align;
+        }
+        // 最后一块可能不完整；fact 记录了去掉末尾填充后的采样数
+        let frames = self.size / block_align * adpcm::block_frames(self.tag, self.channels, block_align)
+            + adpcm::block_frames(self.tag, self.channels, self.size % block_align);
+        if self.samples == 0 { frames } else { frames.min(self.samples) }
+    }
+}
+
+/// Walks the chunks of a RIFF, RF64 or BW64 file up to the data chunk, cutting it at the end of the file.
+pub fn read_header<R: Read + Seek>(reader: &mut R) -> io::Result<Header> {
+    let len = reader.seek(SeekFrom::End(0)).unwrap_or(u64::MAX); // 流不知道有多长
+    let mut head = [0; 12];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut head).map_err(|_| NOT_WAV)?;
+    let rf64 = match &head[..4] {
+        b"RIFF" => false,
+        b"RF64" | b"BW64" => true,
+        _ => return Err(NOT_WAV),
+    };
+    if &head[8..] != b"WAVE" {
+        return Err(NOT_WAV);
+    }
+
+    let mut header = Header { rf64, ..Header::default() };
+    let mut ds64 = Vec::new(); // ds64 表中其他超过 4 GiB 的块
+    let mut data_size = None;
+    let mut pos = 12;
+    loop {
+        let mut chunk = [0; 8];
+        reader.seek(SeekFrom::Start(pos))?;
+        if reader.read_exact(&mut chunk).is_err() {
+            return Err(NO_DATA);
+        }
+        let id: [u8; 4] = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        let mut size = u64::from(u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]));
+        if rf64 && size == u64::from(RF64_PLACEHOLDER) {
+            size = match &id {
+                b"data" => data_size.ok_or(BAD_DS64)?,
+                _ => ds64.iter().find(|&&(i, _)| i == id).map(|&(_, s)| s).ok_or(BAD_DS64)?,
+            };
+        }
+        match &id {
+            b"ds64" if rf64 => {
+                let mut b = vec![0; size.min(1024) as usize];
+                reader.read_exact(&mut b)?;
+                let u64_at = |i: usize| b.get(i..i + 8).map(|x| u64::from_le_bytes(x.try_into().unwrap_or_default()));
+                data_size = Some(u64_at(8).ok_or(BAD_DS64)?);
+                header.samples = u64_at(16).ok_or(BAD_DS64)?;
+                let table = b.get(28..).unwrap_or_default();
+                ds64 = table.chunks_exact(12).map(|e| ([e[0], e[1], e[2], e[3]], u64::from_le_bytes(e[4..].try_into().unwrap_or_default()))).collect();
+            }
+            b"fmt " => {
+                let mut b = vec![0; size.min(64) as usize];
+                reader.read_exact(&mut b).map_err(|_| BAD_FMT)?;
+                header.parse_fmt(&b)?;
+            }
+            b"fact" if header.samples == 0 => {
+                let mut b = [0; 4];
+                reader.read_exact(&mut b)?;
+                let samples = u32::from_le_bytes(b);
+                // RF64 的 fact 可能只是占位，以 ds64 为准
+                if !rf64 || samples != RF64_PLACEHOLDER {
+                    header.samples = u64::from(samples);
+                }
+            }
+            b"data" => {
+                if header.channels == 0 {
+                    return Err(NO_FMT);
+                }
+                header.begin = pos + 8;
+                header.missing = (header.begin + size).saturating_sub(len);
+                header.size = size - header.missing;
+                return Ok(header);
+            }
+            _ => (),
+        }
+        if rf64 && pos == 12 && data_size.is_none() {
+            return Err(BAD_DS64); // ds64 必须是第一个块
+        }
+        pos += 8 + size + (size & 1); // 奇数长度的块后面有一个填充字节
+    }
+}
+
+/// Whether the data chunk is followed only by the metadata chunks [`read_meta`] reads, as taggers append them.
+pub fn meta_after_data<R: Read + Seek>(reader: &mut R, header: &Header) -> io::Result<bool> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let size = header.size + header.missing;
+    let mut pos = header.begin + size + (size & 1);
+    while pos + 8 <= len {
+        let mut chunk = [0; 8];
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut chunk)?;
+        if !matches!(&chunk[..4], b"LIST" | b"id3 " | b"ID3 " | b"bext" | b"cue " | b"smpl") {
+            return Ok(false);
+        }
+        let size = u64::from(u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]));
+        pos += 8 + size + (size & 1);
+    }
+    // 最后一个块缺了填充字节的也算
+    Ok(pos == len || pos == len + 1)
+}
+
+/// Collects the tags (`LIST/INFO`, `id3 `, `bext`) and markers (`cue `, `LIST/adtl`, `smpl`) around the data chunk.
+pub fn read_meta<R: Read + Seek>(reader: &mut R, header: &Header) -> io::Result<(Tags, Markers)> {
+    const MAX_META_SIZE: u64 = 1 << 20; // 更大的 (封面之类) 只读开头
+
+    let (mut info, mut id3, mut bext) = (Tags::default(), Tags::default(), Tags::default());
+    let (mut cues, mut labels, mut sustain) = (Vec::new(), Vec::new(), None);
+    let mut pos = 12;
+    loop {
+        let mut chunk = [0; 8];
+        reader.seek(SeekFrom::Start(pos))?;
+        if reader.read_exact(&mut chunk).is_err() {
+            break;
+        }
+        let size = if pos + 8 == header.begin {
+            header.size + header.missing // RF64 的 data 块长度在 ds64 里
+        } else {
+            u64::from(u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]))
+        };
+        let mut read = || -> io::Result<Vec<u8>> {
+            let mut b = Vec::new();
+            reader.by_ref().take(size.min(MAX_META_SIZE)).read_to_end(&mut b)?;
+            Ok(b)
+        };
+        match &chunk[..4] {
+            b"LIST" => {
+                let b = read()?;
+                if b.starts_with(b"INFO") {
+                    info = tags::parse_info(&b).or(info);
+                } else if b.starts_with(b"adtl") {
+                    labels.extend(markers::parse_adtl(&b));
+                }
+            }
+            b"id3 " | b"ID3 " => id3 = tags::parse_id3v2(&read()?),
+            b"bext" => bext = tags::parse_bext(&read()?),
+            b"cue " => cues = markers::parse_cue(&read()?),
+            b"smpl" => sustain = markers::parse_smpl(&read()?),
+            _ => (),
+        }
+        pos += 8 + size + (size & 1);
+    }
+
+    let cues = cues.into_iter().map(|(id, frame)| {
+        let label = labels.iter().find(|&&(i, _)| i == id).map(|(_, l)| l.clone());
+        Cue { frame, label }
+    });
+    let markers = Markers { cues: cues.collect(), sustain }.fit(0..header.frames());
+    Ok((info.or(id3).or(bext), markers))
+}
+
+pub fn dump_header(header: &Header) {
+    println!("RIFF 标志：{}", if header.rf64 { "RF64" } else { "RIFF" });
+    println!("数据块长度：{} B", header.size);
     println!("文件格式：WAVE");
     println!("格式块标识：fmt");
-    println!("格式块长度：16");
-    println!("编码格式：{:?}", reader.spec().sample_format);
-    println!("声道数：{}", reader.spec().channels);
-    println!("采样频率：{} Hz", reader.spec().sample_rate);
-    let block_align = u32::from(reader.spec().bytes_per_sample) * u32::from(reader.spec().channels);
-    println!("传输速率：{} B/s", reader.spec().sample_rate * block_align);
+    println!("编码格式：{:#06x}", header.tag);
+    println!("声道数：{}", header.channels);
+    println!("声道掩码：{:#010x}", header.channel_mask);
+    println!("采样频率：{} Hz", header.rate);
+    let block_align = u32::from(header.block_align);
+    println!("传输速率：{} B/s", u64::from(header.rate) * u64::from(block_align));
     println!("数据块对齐单位：{block_align} B/block");
-    println!("采样位数：{} bit", reader.spec().bits_per_sample);
+    println!("采样位数：{} bit (有效 {} bit)", header.bits, header.valid_bits);
+    println!("采样数：{} (fact/ds64: {})", header.frames(), header.samples);
 }
 
-pub struct Player<R> {
-    reader: WavReader<R>,
-    format: Format,
-    multiplier: u8, // 倍速 * 0.5
-    progress: Progress,
+/// Reads the PCM data chunk of a WAV file as is.
+pub struct WavDecoder<R> {
+    reader: R,
+    spec: WavSpec,
+    begin: u64, // data 块的起始位置
+    frames: u64,
+    channel_mask: u32,
+    pos: u64,
+}
+
+impl<R: Read + Seek> WavDecoder<R> {
+    pub fn new(mut reader: R, header: &Header) -> io::Result<Self> {
+        let spec = header.spec()?;
+        let frames = header.frames();
+        reader.seek(SeekFrom::Start(header.begin))?;
+        Ok(Self { reader, spec, begin: header.begin, frames, channel_mask: header.channel_mask, pos: 0 })
+    }
+
+    #[inline]
+    fn block_align(&self) -> u64 {
+        u64::from(self.spec.channels) * u64::from(self.spec.bytes_per_sample)
+    }
 }
 
-impl<R> Player<R>
-where
-    R: io::Read,
-{
-    pub fn new(reader: WavReader<R>, multiplier: u8) -> Result<Self, PlayError> {
-        let format = cvt_format(reader.spec())?;
-        Ok(Self { reader, format, multiplier, progress: Progress::default() })
+impl<R: Read + Seek> Decoder for WavDecoder<R> {
+    #[inline]
+    fn spec(&self) -> WavSpec {
+        self.spec
     }
 
-    fn configure_pcm(&self) -> Result<PCM, alsa::Error> {
-        let header = self.reader.spec();
+    #[inline]
+    fn channel_mask(&self) -> u32 {
+        self.channel_mask
+    }
 
-        // 打开 PCM 设备，分配 snd_pcm_hw_params_t 结构体，配置空间初始化
-        let pcm = PCM::new("default", Direction::Playback, false)?;
+    #[inline]
+    fn frames(&self) -> u64 {
+        self.frames
+    }
 
-        let params = HwParams::any(&pcm)?;
+    fn read<S: Fmt>(&mut self, buf: &mut [S]) -> Result<usize, PlayError> {
+        let block_align = self.block_align();
+        let buf = decoder::as_bytes(buf, self.spec)?;
+        let n = (buf.len() as u64 / block_align).min(self.frames - self.pos);
+        self.reader.read_exact(&mut buf[..(n * block_align) as usize])?;
+        self.pos += n;
+        Ok(n as usize)
+    }
 
-        // 设置交错模式 (访问模式)
-        params.set_access(Access::RWInterleaved)?;
+    fn seek(&mut self, frame: u64) -> Result<(), PlayError> {
+        let frame = frame.min(self.frames);
+        self.reader.seek(SeekFrom::Start(self.begin + frame * self.block_align()))?;
+        self.pos = frame;
+        Ok(())
+    }
+}
 
-        // 设置样本长度 (位数)
-        params.set_format(self.format)?;
+pub struct Player<D> {
+    decoder: D,
+    source: PathBuf,
+    region: Range<u64>, // 分轨时只放文件的这一段
+    format: Format,
+    speed: Speed,
+    progress: Progress,
+    markers: Markers,
+    laps: Option<u32>, // 循环还要绕回几次，None 表示一直循环
+    wrapped: u64, // 预读的数据里绕回循环起点的次数
+}
 
-        // 设置采样率
-        params.set_rate_near(header.sample_rate, ValueOr::Nearest)?;
+impl<D: Decoder> Player<D> {
+    pub fn new(decoder: D, speed: Speed, song: &Song) -> Result<Self, PlayError> {
+        let format = cvt_format(decoder.spec())?;
+        Ok(Self {
+            decoder,
+            source: song.get_path().to_owned(),
+            region: song.get_region(),
+            format,
+            speed,
+            progress: Progress::default(),
+            markers: song.get_markers().clone(),
+            laps: None,
+            wrapped: 0,
+        })
+    }
 
-        // 设置通道数
-        params.set_channels(header.channels.into())?;
+    /// Continues decoding at `self.progress.pos`, dropping what was read ahead.
+    fn reseek(&mut self, read_ahead: &mut usize) -> Result<(), PlayError> {
+        *read_ahead = 0;
+        self.wrapped = 0;
+        // 流只能回到还缓存着的地方
+        let reachable = self.decoder.reachable();
+        self.progress.pos = self.progress.pos.clamp(reachable.start, reachable.end);
+        self.decoder.seek(self.progress.pos)
+    }
 
-        pcm.hw_params(&params)?;
-        drop(params);
+    /// The loop that reading on from frame `at` will still go round.
+    fn active_loop(&self, at: u64) -> Option<Loop> {
+        self.markers.sustain.filter(|l| self.laps != Some(0) && at <= l.end && l.end <= self.progress.end)
+    }
 
-        // 设置硬件配置参数
-        pcm.prepare()?;
+    /// Where the decoder is, `ahead` frames after `self.progress.pos` in what was read ahead.
+    fn read_pos(&self, ahead: usize) -> u64 {
+        let len = self.markers.sustain.map_or(0, |l| l.end - l.start);
+        self.progress.pos + ahead as u64 - self.wrapped * len
+    }
 
-        Ok(pcm)
+    /// How far the `offset`-th cue point from what is being heard is.
+    fn cue_offset(&self, offset: isize, rate: u64) -> i64 {
+        let heard = self.progress.c(self.speed, 0);
+        self.markers.jump(heard, offset, rate / 2).map_or(0, |cue| {
+            tracing::info!("Jump to cue point at \x1b[36m{}\x1b[0m ({}).", cue.frame, cue.label.as_deref().unwrap_or("unlabeled"));
+            cue.frame.cast_signed() - heard.cast_signed()
+        })
+    }
+
+    /// Lets the GUI read the progress while playing.
+    const fn progress_access(&self, rate: u64) -> ProgressAccess {
+        ProgressAccess {
+            speed: &raw const self.speed,
+            progress: &raw const self.progress,
+            begin: self.progress.begin,
+            duration: self.progress.end - self.progress.begin,
+            rate,
+        }
     }
 }
 
@@ -101,155 +434,264 @@ impl Drop for EndReporter {
     }
 }
 
-impl<R> Player<BufReader<R>>
-where
-    R: io::Read + io::Seek,
-{
-    pub fn play(&mut self, mtx: Sender<MP3Event>, gtx: Sender<GUIEvent>, rx: Receiver<PlayerEvent>) -> Result<(), PlayError> {
+impl<D: Decoder> Player<D> {
+    /// Leaves the decoder in `leftover` before reporting the end, whether finished or stopped.
+    pub fn play(mut self, config: &SinkConfig, handoff: &Handoff, leftover: &Leftover<D>, mtx: Sender<MP3Event>, gtx: Sender<GUIEvent>, rx: Receiver<PlayerEvent>) -> Result<(), PlayError> {
         let handle = get_channel_handle(&raw const rx);
         let _end_reporter = EndReporter { mtx, gtx: gtx.clone(), handle };
 
-        let pcm = self.configure_pcm()?;
-
-        match self.format {
-            Format::S8 => self.play_inner::<i8>(pcm, gtx, rx),
-            Format::S16LE => self.play_inner::<i16>(pcm, gtx, rx),
-            Format::S183LE => self.play_inner::<S18_3>(pcm, gtx, rx),
-            Format::S203LE => self.play_inner::<S20_3>(pcm, gtx, rx),
-            Format::S243LE => self.play_inner::<S24_3>(pcm, gtx, rx),
-            Format::S20LE => self.play_inner::<S20_4>(pcm, gtx, rx),
-            Format::S24LE => self.play_inner::<S24_4>(pcm, gtx, rx),
-            Format::S32LE => self.play_inner::<i32>(pcm, gtx, rx),
-            Format::FloatLE => self.play_inner::<f32>(pcm, gtx, rx),
-            Format::Float64LE => self.play_inner::<f64>(pcm, gtx, rx),
-            _ => return Err(PlayError::Format(UnsupportedFormatError(self.reader.spec())))
+        let ret = match config.kind {
+            SinkKind::Alsa => self.play_with::<AlsaSink>(config, handoff, gtx, rx),
+            SinkKind::Null => self.play_with::<NullSink>(config, handoff, gtx, rx),
+            SinkKind::Wav => self.play_with::<WavSink>(config, handoff, gtx, rx),
+        };
+        // 被切走却没有留下设备 (出错、或已经放完) 时不让下一首白等
+        handoff.release(handle);
+        leftover.put(self.source, self.decoder);
+        ret
+    }
+
+    fn play_with<K: AudioSink + Send + 'static>(&mut self, config: &SinkConfig, handoff: &Handoff, gtx: Sender<GUIEvent>, rx: Receiver<PlayerEvent>) -> Result<(), PlayError> {
+        let handle = get_channel_handle(&raw const rx);
+        // 上一首歌结束时留下的设备，格式相同 (或正在交叉淡化) 则直接接着写
+        let spec = DeviceSpec::new(self.decoder.spec(), self.format);
+        let mut sink = match handoff.take::<Convert<K>>(config, spec) {
+            Some(sink) => sink,
+            None => Convert::<K>::open(config, self.decoder.spec(), self.format)?,
+        };
+        sink.retarget(spec, self.decoder.channel_mask());
+
+        let finished = match self.format {
+            Format::S8 => self.play_inner::<i8, K>(sink, config, gtx, rx),
+            Format::U8 => self.play_inner::<u8, K>(sink, config, gtx, rx),
+            Format::S16LE => self.play_inner::<i16, K>(sink, config, gtx, rx),
+            Format::S183LE => self.play_inner::<S18_3, K>(sink, config, gtx, rx),
+            Format::S203LE => self.play_inner::<S20_3, K>(sink, config, gtx, rx),
+            Format::S243LE => self.play_inner::<S24_3, K>(sink, config, gtx, rx),
+            Format::S20LE => self.play_inner::<S20_4, K>(sink, config, gtx, rx),
+            Format::S24LE => self.play_inner::<S24_4, K>(sink, config, gtx, rx),
+            Format::S32LE => self.play_inner::<i32, K>(sink, config, gtx, rx),
+            Format::FloatLE => self.play_inner::<f32, K>(sink, config, gtx, rx),
+            Format::Float64LE => self.play_inner::<f64, K>(sink, config, gtx, rx),
+            _ => return Err(PlayError::Format(UnsupportedFormatError(self.decoder.spec())))
+        }?;
+
+        if let Some(sink) = finished {
+            let fading = sink.fading();
+            handoff.park(sink, config, fading, handle);
         }
+        Ok(())
     }
 
-    fn play_inner<S: Fmt>(&mut self, pcm: PCM, gtx: Sender<GUIEvent>, rx: Receiver<PlayerEvent>) -> Result<(), PlayError> {
-        const SAMPLE_SIZE_TOO_LARGE: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "sample size too large");
-        const WRITE_ZERO: io::Error = io::const_error!(io::ErrorKind::WriteZero, "fail to write audio");
+    /// Returns the sink if all frames have been written (or stashed), with the tail still playing.
+    fn play_inner<S: Fmt, K: AudioSink>(&mut self, mut sink: Convert<K>, config: &SinkConfig, gtx: Sender<GUIEvent>, rx: Receiver<PlayerEvent>) -> Result<Option<Convert<K>>, PlayError> {
         const INVALID_RET: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "invalid return values");
         const SIZE_MISMATCH: io::Error = io::const_error!(io::ErrorKind::InvalidInput, "sample size mismatch");
 
         let handle = get_channel_handle(&raw const rx);
-        let spec = self.reader.spec();
+        let spec = self.decoder.spec();
 
         if usize::from(spec.bytes_per_sample) != size_of::<S>() || S::FORMAT != self.format {
             return Err(SIZE_MISMATCH.into());
         }
 
-        let sample_size = usize::from(
-            spec.bytes_per_sample.checked_mul(spec.channels)
-                .map_or(const { Err(PlayError::Io(SAMPLE_SIZE_TOO_LARGE)) }, Ok)?
-        );
-        let num_samples = self.reader.len();
-        let size_per_second = sample_size * spec.sample_rate as usize;
+        let channels = usize::from(spec.channels);
+        let rate = u64::from(spec.sample_rate);
+        // 淡入淡出 ms 毫秒 (按当前倍速) 对应的帧数
+        let fade_size = |ms: u32, speed: Speed| shift::consumed(speed, (u64::from(ms) * rate / 1000).cast_signed()).cast_unsigned();
 
-        let reader = unsafe { self.reader.as_mut_inner() };
-
-        self.progress.begin = reader.seek(SeekFrom::Current(0))? as usize; // 重置 reader 指针并清空缓存
-        self.progress.end = self.progress.begin + spec.bytes_per_sample as usize * num_samples as usize;
+        let frames = self.decoder.frames();
+        self.progress.begin = self.region.start.min(frames);
+        self.progress.end = self.region.end.min(frames);
         self.progress.pos = self.progress.begin;
         self.progress.delay = 0;
+        // 只剩一遍就是不再绕回
+        self.laps = self.markers.sustain.and_then(|l| l.count.checked_sub(1));
 
-        let buf_size_8 = shift::MAX_BUFFER_SIZE * sample_size;
-        let buf_size = shift::MAX_BUFFER_SIZE * usize::from(spec.channels);
-        buffer_resize(reader, buf_size_8);
+        let buf_size = shift::MAX_BUFFER_SIZE * channels;
 
-        let io = IO::<S>::new(&pcm);
         let mut v = unsafe { Box::<[S]>::new_zeroed_slice(buf_size).assume_init() };
+        let mut v_e = 0; // 已从解码器读出、尚未交给 shift::process 的
         let mut w = unsafe { Box::<[S]>::new_zeroed_slice(buf_size).assume_init() };
         let mut w_b;
         let mut w_e;
+        let mut stash_until = None; // 从此处起不再写进设备，留给下一首歌淡出
+        let mut fading_out: Option<(PlayerEvent, u64)> = None; // 淡出后才处理的事件，以及淡出开始的位置
+        let mut started = false;
+
+        self.reseek(&mut v_e)?;
 
-        let _ = gtx.send(GUIEvent::ProgressAccess {
-            access: Some(ProgressAccess {
-                multiplier: &raw const self.multiplier,
-                progress: &raw const self.progress,
-                duration: spec.bytes_per_sample as usize * num_samples as usize,
-                size_per_second,
-            }),
-            handle,
-        });
+        let _ = gtx.send(GUIEvent::ProgressAccess { access: Some(self.progress_access(rate)), handle });
 
         loop {
             let e = rx.recv()?;
             tracing::info!("⟨\x1b[33m{handle}\x1b[0m, \x1b[35mStopping\x1b[0m at \x1b[36m{}/{}\x1b[0m⟩ Receive event \x1b[33m{e:?}\x1b[0m", self.progress.pos - self.progress.begin, self.progress.end - self.progress.begin);
             match e {
-                PlayerEvent::Terminate => return Ok(()),
+                PlayerEvent::Terminate | PlayerEvent::Skip => return Ok(None),
+                PlayerEvent::Park => return Ok(Some(sink)),
                 PlayerEvent::Move { offset } => {
-                    if self.progress.normalize(self.multiplier, offset * size_per_second.cast_signed()) {
-                        reader.seek(SeekFrom::Start(self.progress.pos as u64))?;
+                    if self.progress.normalize(self.speed, offset as i64 * rate.cast_signed()) {
+                        self.reseek(&mut v_e)?;
                     }
                     continue;
                 }
-                PlayerEvent::SetMultiplier { multiplier } => {
-                    self.multiplier = multiplier;
+                PlayerEvent::Jump { offset } => {
+                    if self.progress.normalize(self.speed, self.cue_offset(offset, rate)) {
+                        self.reseek(&mut v_e)?;
+                    }
+                    continue;
+                }
+                PlayerEvent::SetSpeed { speed } => {
+                    self.speed = speed;
                     continue;
                 }
                 PlayerEvent::Pause => continue,
                 PlayerEvent::Resume => {
                     w_b = 0;
                     w_e = 0;
+                    // 第一次开始时可能正接着上一首歌，不淡入
+                    if started && config.ramp != 0 {
+                        sink.ramp_in(config.ramp);
+                    }
+                    started = true;
                 }
             }
             loop {
-                if let Ok(delay) = pcm.delay() {
-                    self.progress.delay = delay as isize * sample_size.cast_signed();
+                // 已交给设备但尚未播放的，加上还没写进设备的
+                if let Ok(delay) = sink.delay() {
+                    let pending = ((w_e - w_b) / channels) as u64;
+                    self.progress.delay = delay as i64 + pending.cast_signed();
                 }
-                match rx.try_recv() {
+                let event = match rx.try_recv() {
                     Ok(e) => {
                         tracing::info!("⟨\x1b[33m{handle}\x1b[0m, \x1b[35mPlaying\x1b[0m at \x1b[36m{} ({:+})/{}\x1b[0m⟩ Receive event \x1b[33m{e:?}\x1b[0m", self.progress.pos - self.progress.begin, -self.progress.delay, self.progress.end - self.progress.begin);
-                        match e {
-                            PlayerEvent::Terminate => return Ok(()),
-                            PlayerEvent::Move { offset } => {
-                                if let Err(e) = pcm.drop() { tracing::warn!("drop: {e}"); }
-                                if let Err(e) = pcm.prepare() { tracing::warn!("prepare: {e}"); }
-                                if self.progress.normalize(self.multiplier, offset * size_per_second.cast_signed()) {
-                                    reader.seek(SeekFrom::Start(self.progress.pos as u64))?;
-                                }
+                        let interrupts = match e {
+                            PlayerEvent::Terminate | PlayerEvent::Park | PlayerEvent::Pause | PlayerEvent::Move { .. } | PlayerEvent::Jump { .. } | PlayerEvent::SetSpeed { .. } => true,
+                            PlayerEvent::Skip => config.skip_fade == 0,
+                            PlayerEvent::Resume => false,
+                        };
+                        if let Some((deferred, _)) = &mut fading_out {
+                            // 正在淡出，合并到淡出后要处理的事件；结束播放也要等淡出放完
+                            *deferred = match (*deferred, e) {
+                                (PlayerEvent::Terminate, _) | (_, PlayerEvent::Terminate) => PlayerEvent::Terminate,
+                                (PlayerEvent::Move { offset }, PlayerEvent::Move { offset: more }) => PlayerEvent::Move { offset: offset + more },
+                                (PlayerEvent::Jump { offset }, PlayerEvent::Jump { offset: more }) => PlayerEvent::Jump { offset: offset + more },
+                                (_, e) => e,
+                            };
+                            None
+                        } else if interrupts && config.ramp != 0 && stash_until.is_none() {
+                            // 先淡出，放完后再处理，之后从淡出开始的位置继续
+                            sink.ramp_out(config.ramp);
+                            let pending = ((w_e - w_b) / channels) as u64 * u64::from(self.speed) / u64::from(SPEED_ONE);
+                            fading_out = Some((e, self.progress.pos.saturating_sub(pending).max(self.progress.begin)));
+                            None
+                        } else {
+                            Some(e)
+                        }
+                    }
+                    Err(TryRecvError::Empty) => match fading_out {
+                        Some((PlayerEvent::Park, _)) if sink.ramped_out() => {
+                            // 淡出留在设备里放完，下一轨接在后面淡入
+                            sink.ramp_in(config.ramp);
+                            return Ok(Some(sink));
+                        }
+                        Some((e, at)) if sink.ramped_out() => {
+                            // 淡出已全部写进设备，等它放完再丢
+                            fading_out = None;
+                            if let Err(e) = sink.drain() { tracing::warn!("drain: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            sink.ramp_in(config.ramp);
+                            w_b = 0;
+                            w_e = 0;
+                            self.progress.pos = at;
+                            self.progress.delay = 0;
+                            self.reseek(&mut v_e)?;
+                            Some(e)
+                        }
+                        _ => None,
+                    },
+                    Err(TryRecvError::Disconnected) => return Err(RecvError.into()),
+                };
+
+                if let Some(e) = event {
+                    match e {
+                        PlayerEvent::Terminate => return Ok(None),
+                        PlayerEvent::Park => {
+                            if let Err(e) = sink.drop() { tracing::warn!("drop: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            return Ok(Some(sink));
+                        }
+                        PlayerEvent::Skip => {
+                            if config.skip_fade == 0 {
+                                return Ok(None);
                             }
-                            PlayerEvent::SetMultiplier { multiplier } => {
-                                if let Err(e) = pcm.drop() { tracing::warn!("drop: {e}"); }
-                                if let Err(e) = pcm.prepare() { tracing::warn!("prepare: {e}"); }
-                                if self.multiplier != multiplier {
-                                    w_b = 0;
-                                    w_e = 0;
-                                    if self.progress.normalize(self.multiplier, 0) {
-                                        reader.seek(SeekFrom::Start(self.progress.pos as u64))?;
-                                    }
-                                    self.multiplier = multiplier;
-                                }
+                            // 从实际听到的位置开始，短暂淡出到下一首歌
+                            if let Err(e) = sink.drop() { tracing::warn!("drop: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            w_b = 0;
+                            w_e = 0;
+                            if self.progress.normalize(self.speed, 0) {
+                                self.reseek(&mut v_e)?;
+                            }
+                            stash_until = Some(self.progress.pos + fade_size(config.skip_fade, self.speed));
+                            self.laps = Some(0); // 不然可能永远到不了淡出的终点
+                        }
+                        PlayerEvent::Move { offset } => {
+                            if let Err(e) = sink.drop() { tracing::warn!("drop: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            if config.ramp != 0 { sink.ramp_in(config.ramp); }
+                            stash_until = None;
+                            if self.progress.normalize(self.speed, offset as i64 * rate.cast_signed()) {
+                                self.reseek(&mut v_e)?;
+                            }
+                        }
+                        PlayerEvent::Jump { offset } => {
+                            if let Err(e) = sink.drop() { tracing::warn!("drop: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            if config.ramp != 0 { sink.ramp_in(config.ramp); }
+                            stash_until = None;
+                            if self.progress.normalize(self.speed, self.cue_offset(offset, rate)) {
+                                self.reseek(&mut v_e)?;
                             }
-                            PlayerEvent::Pause => {
-                                if let Err(e) = pcm.drop() { tracing::warn!("drop: {e}"); }
-                                if let Err(e) = pcm.prepare() { tracing::warn!("prepare: {e}"); }
-                                // w_b = 0;
-                                // w_e = 0;
-                                if self.progress.normalize(self.multiplier, 0) {
-                                    reader.seek(SeekFrom::Start(self.progress.pos as u64))?;
+                        }
+                        PlayerEvent::SetSpeed { speed } => {
+                            if let Err(e) = sink.drop() { tracing::warn!("drop: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            if config.ramp != 0 { sink.ramp_in(config.ramp); }
+                            stash_until = None;
+                            if self.speed != speed {
+                                w_b = 0;
+                                w_e = 0;
+                                if self.progress.normalize(self.speed, 0) {
+                                    self.reseek(&mut v_e)?;
                                 }
-                                break;
+                                self.speed = speed;
+                            }
+                        }
+                        PlayerEvent::Pause => {
+                            if let Err(e) = sink.drop() { tracing::warn!("drop: {e}"); }
+                            if let Err(e) = sink.prepare() { tracing::warn!("prepare: {e}"); }
+                            stash_until = None;
+                            // w_b = 0;
+                            // w_e = 0;
+                            if self.progress.normalize(self.speed, 0) {
+                                self.reseek(&mut v_e)?;
                             }
-                            PlayerEvent::Resume => (),
+                            break;
                         }
+                        PlayerEvent::Resume => (),
                     }
-                    Err(TryRecvError::Empty) => (),
-                    Err(TryRecvError::Disconnected) => return Err(RecvError.into()),
+                }
+
+                // 快到结尾了，剩下的留给下一首歌交叉淡化；还要循环的话到不了结尾
+                if stash_until.is_none() && fading_out.is_none() && config.crossfade != 0 && self.active_loop(self.progress.pos).is_none() && self.progress.end - self.progress.pos <= fade_size(config.crossfade, self.speed) {
+                    stash_until = Some(self.progress.end);
                 }
 
                 // 还有没写完的，先写
                 if w_b != w_e {
-                    let expected = (w_e - w_b) / usize::from(spec.channels);
-                    let real = match io.writei(&w[w_b..w_e]) {
-                        Ok(s) => s,
-                        Err(e) if io::Error::from_raw_os_error(e.errno()).kind() == io::ErrorKind::BrokenPipe => {
-                            if let Err(e) = pcm.prepare() { tracing::warn!("play-prepare: {e}"); }
-                            continue;
-                        }
-                        Err(e) => return Err(e.into()),
-                    };
+                    let expected = (w_e - w_b) / channels;
+                    let real = if stash_until.is_some() { sink.stash(&w[w_b..w_e]) } else { sink.writei(&w[w_b..w_e])? };
                     if real == 0 {
                         continue;
                     } else if real < expected { // print a warning
@@ -258,49 +700,71 @@ where
                         return Err(PlayError::Io(INVALID_RET));
                     }
 
-                    w_b += real * usize::from(spec.channels);
+                    w_b += real * channels;
                     continue;
                 }
 
-                let d_size = shift::buffer_size(self.multiplier) * usize::from(spec.channels);
-                let d_size_8 = shift::buffer_size(self.multiplier) * sample_size;
-                let buf = reader.peek(buf_size_8)?;
-
-                let l = buf.len();
-                if l == 0 {
-                    if unlikely(self.progress.pos != self.progress.end) {
-                        return Err(INVALID_RET.into());
-                    }
+                if let Some(until) = stash_until && self.progress.pos >= until {
+                    return Ok(Some(sink));
+                }
 
-                    if pcm.state() == State::Running {
-                        core::hint::spin_loop();
+                // 预读到够 shift::process 处理一次，但不越过结尾
+                let d_size = shift::buffer_size(self.speed) * channels;
+                while v_e < d_size {
+                    let at = self.read_pos(v_e / channels);
+                    let lp = self.active_loop(at);
+                    let limit = lp.map_or(self.progress.end, |l| l.end);
+                    if at >= limit {
+                        let Some(lp) = lp else { break };
+                        // 读到循环终点就接着从起点读，一起交给 shift::process，听不出接缝
+                        self.decoder.seek(lp.start)?;
+                        self.wrapped += 1;
+                        self.laps = self.laps.map(|n| n - 1);
                         continue;
                     }
-
-                    return Ok(());
+                    let room = usize::try_from(limit - at).map_or(usize::MAX, |frames| frames.saturating_mul(channels));
+                    let n = self.decoder.read(&mut v[v_e..buf_size.min(v_e.saturating_add(room))])?;
+                    if unlikely(n == 0) {
+                        // 文件比头部说的短，以实际的结尾为准
+                        if self.progress.end == UNKNOWN_FRAMES {
+                            tracing::info!("⟨\x1b[33m{handle}\x1b[0m⟩ Stream ended after \x1b[36m{at}\x1b[0m frames.");
+                        } else {
+                            tracing::warn!("⟨\x1b[33m{handle}\x1b[0m⟩ Decoder ended \x1b[36m{}\x1b[0m frames early, treating it as the end.", self.progress.end - at);
+                        }
+                        self.progress.end = at;
+                        let _ = gtx.send(GUIEvent::ProgressAccess { access: Some(self.progress_access(rate)), handle });
+                        break;
+                    }
+                    v_e += n * channels;
                 }
 
-                let consume_in;
-                if l < d_size_8 {
-                    let v8 = unsafe { core::slice::from_raw_parts_mut(v.as_mut_ptr().cast(), d_size_8) };
-
-                    if unlikely(self.progress.pos + l != self.progress.end) {
-                        return Err(INVALID_RET.into());
+                if v_e == 0 {
+                    // 淡出途中歌曲结束，设备已静音，不能留给下一首歌
+                    if fading_out.is_some() {
+                        return sink.drain().map(|()| None);
                     }
+                    return Ok(Some(sink));
+                }
 
-                    v8[..l].copy_from_slice(buf);
-                    v8[l..].fill(0);
-
-                    (consume_in, w_e) = shift::process(&v[..d_size], usize::from(spec.channels), self.multiplier, &mut w);
-                    let consume = l.min(consume_in * size_of::<S>());
-                    reader.consume(consume);
-                    self.progress.pos += consume;
+                let consume;
+                if v_e < d_size {
+                    // 只剩不到一次的量，补零
+                    v[v_e..d_size].fill(S::from_norm(0.0));
+                    let consume_in;
+                    (consume_in, w_e) = shift::process(&v[..d_size], channels, self.speed, &mut w);
+                    consume = v_e.min(consume_in);
                 } else {
-                    let reinterpret = unsafe { core::slice::from_raw_parts(buf.as_ptr().cast(), buf.len() / size_of::<S>()) };
-
-                    (consume_in, w_e) = shift::process(reinterpret, usize::from(spec.channels), self.multiplier, &mut w);
-                    reader.consume(consume_in * size_of::<S>());
-                    self.progress.pos += consume_in * size_of::<S>();
+                    (consume, w_e) = shift::process(&v[..v_e], channels, self.speed, &mut w);
+                }
+                v.copy_within(consume..v_e, 0);
+                v_e -= consume;
+                self.progress.pos += (consume / channels) as u64;
+                if let Some(lp) = self.markers.sustain {
+                    // 放过了循环终点，换回循环里的位置
+                    while self.wrapped > 0 && self.progress.pos >= lp.end {
+                        self.progress.pos -= lp.end - lp.start;
+                        self.wrapped -= 1;
+                    }
                 }
 
                 w_b = 0;
@@ -309,3 +773,122 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn chunk(id: [u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut b = id.to_vec();
+        b.extend((body.len() as u32).to_le_bytes());
+        b.extend(body);
+        if !body.len().is_multiple_of(2) {
+            b.push(0);
+        }
+        b
+    }
+
+    fn fmt(tag: u16, channels: u16, block_align: u16, bits: u16) -> Vec<u8> {
+        let mut b = tag.to_le_bytes().to_vec();
+        b.extend(channels.to_le_bytes());
+        b.extend(48000u32.to_le_bytes());
+        b.extend((48000 * u32::from(block_align)).to_le_bytes());
+        b.extend(block_align.to_le_bytes());
+        b.extend(bits.to_le_bytes());
+        b
+    }
+
+    fn extensible(valid_bits: u16, subtype: u16, tail: [u8; 14]) -> Vec<u8> {
+        let mut b = fmt(WAVE_FORMAT_EXTENSIBLE, 2, 8, 32);
+        b.extend(22u16.to_le_bytes());
+        b.extend(valid_bits.to_le_bytes());
+        b.extend(3u32.to_le_bytes());
+        b.extend(subtype.to_le_bytes());
+        b.extend(tail);
+        b
+    }
+
+    fn riff(id: [u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        [&id[..], &(body.len() as u32 + 4).to_le_bytes(), b"WAVE", &body].concat()
+    }
+
+    #[test]
+    fn extensible_fmt() {
+        // 有效位数写成 0 的当作与容器相同；奇数长度的块后有填充
+        let file = riff(*b"RIFF", &[chunk(*b"junk", &[1; 3]), chunk(*b"fmt ", &extensible(0, WAVE_FORMAT_PCM, SUBTYPE_GUID_TAIL)), chunk(*b"data", &[0; 80])]);
+        let header = read_header(&mut Cursor::new(&file)).unwrap();
+        assert_eq!((header.tag, header.valid_bits, header.channel_mask), (WAVE_FORMAT_PCM, 32, 3));
+        assert_eq!(header.begin, file.len() as u64 - 80);
+        assert_eq!(header.frames(), 10);
+        assert_eq!(header.spec().unwrap().bits_per_sample, 32);
+
+        let mut header = Header::default();
+        header.parse_fmt(&extensible(24, WAVE_FORMAT_PCM, SUBTYPE_GUID_TAIL)).unwrap();
+        assert_eq!(header.valid_bits, 24);
+        assert!(header.parse_fmt(&extensible(24, WAVE_FORMAT_PCM, [0; 14])).is_err());
+        assert!(header.parse_fmt(&fmt(WAVE_FORMAT_PCM, 0, 4, 16)).is_err());
+    }
+
+    #[test]
+    fn trailing_chunks() {
+        let wav = |trailer: &[Vec<u8>]| {
+            let file = riff(*b"RIFF", &[[chunk(*b"fmt ", &fmt(WAVE_FORMAT_PCM, 1, 2, 16)), chunk(*b"data", &[0; 8])].as_slice(), trailer].concat());
+            let mut reader = Cursor::new(file);
+            let header = read_header(&mut reader).unwrap();
+            meta_after_data(&mut reader, &header).unwrap()
+        };
+        assert!(wav(&[]));
+        assert!(wav(&[chunk(*b"LIST", b"INFOINAM\x02\0\0\0a\0"), chunk(*b"id3 ", &[0; 3])]));
+        // 没有填充字节的奇数长度块
+        assert!(wav(&[chunk(*b"cue ", &[0; 3])[..11].to_vec()]));
+        assert!(!wav(&[chunk(*b"LIST", b"INFO"), chunk(*b"data", &[0; 8])]));
+        assert!(!wav(&[vec![0; 5]]));
+        assert!(!wav(&[chunk(*b"smpl", &[0; 60])[..20].to_vec()]));
+    }
+
+    #[test]
+    fn ms_adpcm_coefs() {
+        let ms = |n: u16, pairs: u16| {
+            let mut b = fmt(WAVE_FORMAT_ADPCM, 1, 256, 4);
+            b.extend((6 + pairs * 4).to_le_bytes());
+            b.extend(500u16.to_le_bytes());
+            b.extend(n.to_le_bytes());
+            b.extend((0..pairs).map(u16::cast_signed).flat_map(|i| [i * 64, -i]).flat_map(i16::to_le_bytes));
+            b
+        };
+        // 标准的 7 对之后还可以有自定义的系数
+        let mut header = Header::default();
+        header.parse_fmt(&ms(9, 9)).unwrap();
+        assert_eq!(header.coefs.len(), 9);
+        assert_eq!(header.coefs[8], [512, -8]);
+        assert!(header.parse_fmt(&ms(6, 6)).is_err());
+        assert!(header.parse_fmt(&ms(9, 8)).is_err());
+    }
+
+    #[test]
+    fn rf64() {
+        // data 块的长度在 ds64 里，文件在 data 中间被截断
+        let mut ds64 = 0u64.to_le_bytes().to_vec();
+        ds64.extend((5u64 << 32).to_le_bytes());
+        ds64.extend(123u64.to_le_bytes());
+        ds64.extend(0u32.to_le_bytes());
+        let mut data = b"data".to_vec();
+        data.extend(RF64_PLACEHOLDER.to_le_bytes());
+        data.extend([0; 400]);
+        let fact = chunk(*b"fact", &RF64_PLACEHOLDER.to_le_bytes());
+        let file = riff(*b"RF64", &[chunk(*b"ds64", &ds64), chunk(*b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 4, 16)), fact, data]);
+
+        let header = read_header(&mut Cursor::new(&file)).unwrap();
+        assert!(header.rf64);
+        assert_eq!((header.size, header.missing), (400, (5 << 32) - 400));
+        assert_eq!(header.samples, 123);
+        assert_eq!(header.frames(), 100);
+
+        // ds64 必须在最前面
+        let file = riff(*b"BW64", &[chunk(*b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 4, 16)), chunk(*b"data", &[0; 4])]);
+        assert!(read_header(&mut Cursor::new(&file)).is_err());
+    }
+}